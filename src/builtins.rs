@@ -0,0 +1,824 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::process;
+use std::sync::OnceLock;
+use std::env;
+
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+
+use std::cmp::Ordering;
+use std::f64::consts;
+
+use crate::semantics::{self, Error, call_function, compare, exception};
+use crate::threads::{self, Op};
+use crate::value::{Value, StackValue, Slot, NativeFunction, V};
+
+/**
+ * Functions implemented in Rust, bound to their names in the main scope.
+ * The i-th builtin is stored in the i-th slot of the stack, before any variable of the program
+ */
+pub const BUILTINS: &[NativeFunction] = &[
+    NativeFunction { name: "print", num_args: Option::None, function: print },
+    NativeFunction { name: "println", num_args: Option::None, function: println },
+    NativeFunction { name: "len", num_args: Option::Some(1), function: len },
+    NativeFunction { name: "push", num_args: Option::Some(2), function: push },
+    NativeFunction { name: "pop", num_args: Option::Some(1), function: pop },
+    NativeFunction { name: "typeof", num_args: Option::Some(1), function: type_of },
+    NativeFunction { name: "str", num_args: Option::Some(1), function: str },
+    NativeFunction { name: "int", num_args: Option::Some(1), function: int },
+    // Strings
+    NativeFunction { name: "substring", num_args: Option::Some(3), function: substring },
+    NativeFunction { name: "split", num_args: Option::Some(2), function: split },
+    NativeFunction { name: "join", num_args: Option::None, function: join },
+    NativeFunction { name: "trim", num_args: Option::Some(1), function: trim },
+    NativeFunction { name: "to_upper", num_args: Option::Some(1), function: to_upper },
+    NativeFunction { name: "to_lower", num_args: Option::Some(1), function: to_lower },
+    NativeFunction { name: "contains", num_args: Option::Some(2), function: contains },
+    NativeFunction { name: "starts_with", num_args: Option::Some(2), function: starts_with },
+    NativeFunction { name: "replace", num_args: Option::Some(3), function: replace },
+    NativeFunction { name: "chars", num_args: Option::Some(1), function: chars },
+    NativeFunction { name: "parse_int", num_args: Option::Some(1), function: parse_int },
+    // Lists
+    NativeFunction { name: "map", num_args: Option::Some(2), function: map },
+    NativeFunction { name: "filter", num_args: Option::Some(2), function: filter },
+    NativeFunction { name: "fold", num_args: Option::Some(3), function: fold },
+    NativeFunction { name: "reduce", num_args: Option::Some(2), function: reduce },
+    NativeFunction { name: "any", num_args: Option::Some(2), function: any },
+    NativeFunction { name: "all", num_args: Option::Some(2), function: all },
+    NativeFunction { name: "zip", num_args: Option::Some(2), function: zip },
+    NativeFunction { name: "enumerate", num_args: Option::Some(1), function: enumerate },
+    NativeFunction { name: "reverse", num_args: Option::Some(1), function: reverse },
+    NativeFunction { name: "sort", num_args: Option::Some(1), function: sort },
+    NativeFunction { name: "sort_by", num_args: Option::Some(2), function: sort_by },
+    // Math
+    NativeFunction { name: "abs", num_args: Option::Some(1), function: abs },
+    NativeFunction { name: "min", num_args: Option::None, function: min },
+    NativeFunction { name: "max", num_args: Option::None, function: max },
+    NativeFunction { name: "pow", num_args: Option::Some(2), function: pow },
+    NativeFunction { name: "sqrt", num_args: Option::Some(1), function: sqrt },
+    NativeFunction { name: "floor", num_args: Option::Some(1), function: floor },
+    NativeFunction { name: "ceil", num_args: Option::Some(1), function: ceil },
+    NativeFunction { name: "round", num_args: Option::Some(1), function: round },
+    NativeFunction { name: "sin", num_args: Option::Some(1), function: sin },
+    NativeFunction { name: "cos", num_args: Option::Some(1), function: cos },
+    NativeFunction { name: "tan", num_args: Option::Some(1), function: tan },
+    NativeFunction { name: "asin", num_args: Option::Some(1), function: asin },
+    NativeFunction { name: "acos", num_args: Option::Some(1), function: acos },
+    NativeFunction { name: "atan", num_args: Option::Some(1), function: atan },
+    NativeFunction { name: "atan2", num_args: Option::Some(2), function: atan2 },
+    NativeFunction { name: "gcd", num_args: Option::Some(2), function: gcd },
+    // Files
+    NativeFunction { name: "read_file", num_args: Option::Some(1), function: read_file },
+    NativeFunction { name: "write_file", num_args: Option::Some(2), function: write_file },
+    NativeFunction { name: "append_file", num_args: Option::Some(2), function: append_file },
+    NativeFunction { name: "read_lines", num_args: Option::Some(1), function: read_lines },
+    NativeFunction { name: "exists", num_args: Option::Some(1), function: exists },
+    NativeFunction { name: "list_dir", num_args: Option::Some(1), function: list_dir },
+    NativeFunction { name: "remove_file", num_args: Option::Some(1), function: remove_file },
+    NativeFunction { name: "mkdir", num_args: Option::Some(1), function: mkdir },
+    // Standard input
+    NativeFunction { name: "input", num_args: Option::Some(1), function: input },
+    NativeFunction { name: "read_line", num_args: Option::Some(0), function: read_line },
+    NativeFunction { name: "read_all_stdin", num_args: Option::Some(0), function: read_all_stdin },
+    // Process
+    NativeFunction { name: "env", num_args: Option::Some(1), function: get_env },
+    NativeFunction { name: "set_env", num_args: Option::Some(2), function: set_env },
+    NativeFunction { name: "exit", num_args: Option::Some(1), function: exit },
+    // Generators
+    NativeFunction { name: "next", num_args: Option::Some(1), function: next },
+    // Threads. `join` with a thread waits for it
+    NativeFunction { name: "spawn", num_args: Option::Some(1), function: spawn },
+    NativeFunction { name: "yield_now", num_args: Option::Some(0), function: yield_now },
+    NativeFunction { name: "channel", num_args: Option::None, function: channel },
+    NativeFunction { name: "send", num_args: Option::Some(2), function: send },
+    NativeFunction { name: "recv", num_args: Option::Some(1), function: recv }
+];
+
+/**
+ * Values bound to their names in the main scope. They are stored in the stack right after the builtins
+ */
+pub const GLOBALS: &[Global] = &[
+    Global { name: "pi", value: || Value::Float(consts::PI) },
+    // Not named e, which is the usual name of the variable of a catch
+    Global { name: "euler", value: || Value::Float(consts::E) },
+    // Command line arguments that follow the script path
+    Global { name: "args", value: || string_list(SCRIPT_ARGS.get().cloned().unwrap_or_default()) }
+];
+
+pub struct Global {
+    pub name: &'static str,
+    // Computes the value when the program starts
+    pub value: fn() -> Value
+}
+
+static SCRIPT_ARGS: OnceLock<Vec<String>> = OnceLock::new();
+
+/**
+ * Sets the value of `args`. It must be called before the program is run
+ */
+pub fn set_script_args(args: Vec<String>) {
+    SCRIPT_ARGS.set(args).expect("Script arguments already set")
+}
+
+/**
+ * Scope of the builtins, used by the parser to resolve their names
+ */
+pub fn builtin_variables() -> HashMap<String, usize> {
+    let names = BUILTINS.iter().map(|builtin| builtin.name).chain(GLOBALS.iter().map(|global| global.name));
+    names.enumerate().map(|(i, name)| (String::from(name), i)).collect()
+}
+
+/**
+ * Values of the builtins, to be pushed at the bottom of the stack
+ */
+pub fn builtin_values() -> Vec<Slot> {
+    let functions = BUILTINS.iter().map(|builtin| Value::NativeFn(*builtin));
+    let globals = GLOBALS.iter().map(|global| (global.value)());
+    functions.chain(globals).map(|value| Slot::new(StackValue::from_box(Box::new(value)))).collect()
+}
+
+/**
+ * Returns the value of an argument. Unit arguments are null pointers
+ */
+fn value(arg: &StackValue) -> &Value {
+    if arg.is_unit() { &Value::Unit } else { arg.as_ref() }
+}
+
+fn string_arg<'a>(args: &'a [StackValue], i: usize, function: &str) -> Result<&'a str, Error> {
+    match value(&args[i]) {
+        Value::Str(s) => Result::Ok(s),
+        other => Result::Err(exception(&format!("Function {} expects a string, found {}", function, other)))
+    }
+}
+
+fn int_arg(args: &[StackValue], i: usize, function: &str) -> Result<i64, Error> {
+    match value(&args[i]) {
+        Value::Int(i) => Result::Ok(*i),
+        other => Result::Err(exception(&format!("Function {} expects an int, found {}", function, other)))
+    }
+}
+
+/**
+ * Returns a copy of the list argument. The elements are shared with the original list
+ */
+fn list_arg(args: &[StackValue], i: usize, function: &str) -> Result<Vec<StackValue>, Error> {
+    match value(&args[i]) {
+        Value::List(list) => Result::Ok(list.clone()),
+        other => Result::Err(exception(&format!("Function {} expects a list, found {}", function, other)))
+    }
+}
+
+/**
+ * Moves a value returned by a function to the heap, to store it in a list.
+ * Eg: a function whose body ends with an assignment returns a null pointer, which is stored as a boxed unit
+ */
+fn stack_value(v: V) -> StackValue {
+    match v {
+        V::Ptr(ptr) if ptr.is_unit() => StackValue::from_box(Box::new(Value::Unit)),
+        V::Ptr(ptr) => ptr,
+        V::Val(value) => StackValue::from_box(Box::new(value))
+    }
+}
+
+fn string_list(strings: Vec<String>) -> Value {
+    Value::List(strings.into_iter().map(|s| StackValue::from_box(Box::new(Value::Str(s)))).collect())
+}
+
+fn to_string(arg: &StackValue) -> String {
+    if arg.is_unit() { String::from("unit") } else { arg.as_ref().to_string() }
+}
+
+fn print(args: &[StackValue]) -> Result<V, Error> {
+    let args: Vec<String> = args.iter().map(to_string).collect();
+    print!("{}", args.join(" "));
+    io::stdout().flush().or(Result::Err(exception("Can not write to stdout")))?;
+    Result::Ok(V::Val(Value::Unit))
+}
+
+fn println(args: &[StackValue]) -> Result<V, Error> {
+    let args: Vec<String> = args.iter().map(to_string).collect();
+    println!("{}", args.join(" "));
+    Result::Ok(V::Val(Value::Unit))
+}
+
+fn len(args: &[StackValue]) -> Result<V, Error> {
+    let len: usize = match value(&args[0]) {
+        Value::List(list) => list.len(),
+        Value::Tuple(values) => values.len(),
+        Value::Map(map) => map.len(),
+        Value::Str(s) => s.chars().count(),
+        value => return Result::Err(exception(&format!("Value {} has no length", value)))
+    };
+    Result::Ok(V::Val(Value::Int(len as i64)))
+}
+
+fn push(args: &[StackValue]) -> Result<V, Error> {
+    let mut list: StackValue = args[0];
+    match if list.is_unit() { Option::None } else { Option::Some(list.as_mut_ref()) } {
+        Option::Some(Value::List(list)) => list.push(args[1]),
+        _ => return Result::Err(exception(&format!("Can not push to {}", to_string(&list))))
+    };
+    Result::Ok(V::Val(Value::Unit))
+}
+
+fn pop(args: &[StackValue]) -> Result<V, Error> {
+    let mut list: StackValue = args[0];
+    match if list.is_unit() { Option::None } else { Option::Some(list.as_mut_ref()) } {
+        Option::Some(Value::List(list)) => match list.pop() {
+            Option::Some(value) => Result::Ok(V::Ptr(value)),
+            Option::None => Result::Err(exception("Can not pop from an empty list"))
+        },
+        _ => Result::Err(exception(&format!("Can not pop from {}", to_string(&list))))
+    }
+}
+
+fn type_of(args: &[StackValue]) -> Result<V, Error> {
+    let name: &str = match value(&args[0]) {
+        Value::Unit => "unit",
+        Value::Int(_) | Value::BigInt(_) => "int",
+        Value::Float(_) => "float",
+        Value::Bool(_) => "bool",
+        Value::Fn(_) | Value::NativeFn(_) => "function",
+        Value::List(_) => "list",
+        Value::Tuple(_) => "tuple",
+        Value::Str(_) => "string",
+        Value::Map(_) => "map",
+        Value::Range(_, _) => "range",
+        Value::Continuation(_) => "continuation",
+        Value::Generator(_) => "generator",
+        Value::Thread(_) => "thread",
+        Value::Channel(_) => "channel",
+        // Values of user defined types have the name of their type
+        Value::Struct(s) => &s.name,
+        Value::Enum(e) => &e.name
+    };
+    Result::Ok(V::Val(Value::Str(String::from(name))))
+}
+
+fn str(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(Value::Str(to_string(&args[0]))))
+}
+
+fn int(args: &[StackValue]) -> Result<V, Error> {
+    let value: Value = match value(&args[0]) {
+        Value::Int(i) => Value::Int(*i),
+        Value::BigInt(i) => Value::BigInt(i.clone()),
+        Value::Bool(b) => Value::Int(*b as i64),
+        // Truncates towards zero
+        Value::Float(x) if x.is_finite() => Value::from_big_int(BigInt::from_f64(x.trunc()).unwrap()),
+        Value::Str(s) => string_to_int(s)?,
+        value => return Result::Err(exception(&format!("Can not convert {} to int", value)))
+    };
+    Result::Ok(V::Val(value))
+}
+
+fn string_to_int(s: &str) -> Result<Value, Error> {
+    match s.trim().parse::<BigInt>() {
+        Result::Ok(i) => Result::Ok(Value::from_big_int(i)),
+        Result::Err(_) => Result::Err(exception(&format!("Can not convert string \"{}\" to int", s)))
+    }
+}
+
+/**
+ * Characters from index `start`, included, to index `end`, excluded
+ */
+fn substring(args: &[StackValue]) -> Result<V, Error> {
+    let s: &str = string_arg(args, 0, "substring")?;
+    let (start, end) = (int_arg(args, 1, "substring")?, int_arg(args, 2, "substring")?);
+    let len: i64 = s.chars().count() as i64;
+    if start < 0 || start > end || end > len {
+        return Result::Err(exception(&format!("Invalid range {}..{} for string of length {}", start, end, len)))
+    }
+    let substring: String = s.chars().skip(start as usize).take((end - start) as usize).collect();
+    Result::Ok(V::Val(Value::Str(substring)))
+}
+
+fn split(args: &[StackValue]) -> Result<V, Error> {
+    let (s, separator) = (string_arg(args, 0, "split")?, string_arg(args, 1, "split")?);
+    if separator.is_empty() {
+        return Result::Err(exception("The separator of split can not be empty"))
+    }
+    Result::Ok(V::Val(string_list(s.split(separator).map(String::from).collect())))
+}
+
+/**
+ * The form depends on the first argument:
+ * `join(list, separator)` concatenates the elements of the list, which are converted to strings like `str` does,
+ * and `join(thread)` waits for the end of the thread and returns its result
+ */
+fn join(args: &[StackValue]) -> Result<V, Error> {
+    match (args.first().map(value), args.len()) {
+        (Option::Some(Value::List(list)), 2) => {
+            let separator: &str = string_arg(args, 1, "join")?;
+            let strings: Vec<String> = list.iter().map(to_string).collect();
+            Result::Ok(V::Val(Value::Str(strings.join(separator))))
+        },
+        (Option::Some(Value::Thread(id)), 1) => threads::wait(Op::Join(*id)),
+        // The message names the form chosen by the first argument, because the mistake can be the first argument
+        (Option::Some(Value::List(_)), n) => {
+            Result::Err(exception(&format!("Wrong number of arguments for join(list, separator). Expected 2, found {}", n)))
+        },
+        (Option::Some(Value::Thread(_)), n) => {
+            Result::Err(exception(&format!("Wrong number of arguments for join(thread). Expected 1, found {}", n)))
+        },
+        (Option::Some(other), _) => Result::Err(exception(&format!("Function join expects a list or a thread, found {}", other))),
+        (Option::None, _) => Result::Err(exception("Wrong number of arguments. Expected 1 or 2, found 0"))
+    }
+}
+
+fn trim(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(Value::Str(String::from(string_arg(args, 0, "trim")?.trim()))))
+}
+
+fn to_upper(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(Value::Str(string_arg(args, 0, "to_upper")?.to_uppercase())))
+}
+
+fn to_lower(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(Value::Str(string_arg(args, 0, "to_lower")?.to_lowercase())))
+}
+
+fn contains(args: &[StackValue]) -> Result<V, Error> {
+    let (s, substring) = (string_arg(args, 0, "contains")?, string_arg(args, 1, "contains")?);
+    Result::Ok(V::Val(Value::Bool(s.contains(substring))))
+}
+
+fn starts_with(args: &[StackValue]) -> Result<V, Error> {
+    let (s, prefix) = (string_arg(args, 0, "starts_with")?, string_arg(args, 1, "starts_with")?);
+    Result::Ok(V::Val(Value::Bool(s.starts_with(prefix))))
+}
+
+fn replace(args: &[StackValue]) -> Result<V, Error> {
+    let s: &str = string_arg(args, 0, "replace")?;
+    let (from, to) = (string_arg(args, 1, "replace")?, string_arg(args, 2, "replace")?);
+    if from.is_empty() {
+        return Result::Err(exception("The pattern of replace can not be empty"))
+    }
+    Result::Ok(V::Val(Value::Str(s.replace(from, to))))
+}
+
+fn chars(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(string_list(string_arg(args, 0, "chars")?.chars().map(String::from).collect())))
+}
+
+fn parse_int(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(string_to_int(string_arg(args, 0, "parse_int")?)?))
+}
+
+fn map(args: &[StackValue]) -> Result<V, Error> {
+    let mut result: Vec<StackValue> = Vec::new();
+    for value in list_arg(args, 0, "map")? {
+        result.push(stack_value(call_function(args[1], vec![value])?))
+    }
+    Result::Ok(V::Val(Value::List(result)))
+}
+
+fn filter(args: &[StackValue]) -> Result<V, Error> {
+    let mut result: Vec<StackValue> = Vec::new();
+    for value in list_arg(args, 0, "filter")? {
+        if call_function(args[1], vec![value])?.as_bool() {
+            result.push(value)
+        }
+    }
+    Result::Ok(V::Val(Value::List(result)))
+}
+
+/**
+ * Combines the elements from left to right, starting from an initial value. Eg: fold(list, 0, fn (acc, x) { acc + x })
+ */
+fn fold(args: &[StackValue]) -> Result<V, Error> {
+    let mut acc: StackValue = args[1];
+    for value in list_arg(args, 0, "fold")? {
+        acc = stack_value(call_function(args[2], vec![acc, value])?)
+    }
+    Result::Ok(V::Ptr(acc))
+}
+
+/**
+ * Like `fold`, starting from the first element. Error if the list is empty
+ */
+fn reduce(args: &[StackValue]) -> Result<V, Error> {
+    let mut list = list_arg(args, 0, "reduce")?.into_iter();
+    let mut acc: StackValue = list.next().ok_or(exception("Can not reduce an empty list"))?;
+    for value in list {
+        acc = stack_value(call_function(args[1], vec![acc, value])?)
+    }
+    Result::Ok(V::Ptr(acc))
+}
+
+fn any(args: &[StackValue]) -> Result<V, Error> {
+    for value in list_arg(args, 0, "any")? {
+        if call_function(args[1], vec![value])?.as_bool() {
+            return Result::Ok(V::Val(Value::Bool(true)))
+        }
+    }
+    Result::Ok(V::Val(Value::Bool(false)))
+}
+
+fn all(args: &[StackValue]) -> Result<V, Error> {
+    for value in list_arg(args, 0, "all")? {
+        if !call_function(args[1], vec![value])?.as_bool() {
+            return Result::Ok(V::Val(Value::Bool(false)))
+        }
+    }
+    Result::Ok(V::Val(Value::Bool(true)))
+}
+
+/**
+ * List of pairs of elements with the same index. The longer list is truncated
+ */
+fn zip(args: &[StackValue]) -> Result<V, Error> {
+    let (list1, list2) = (list_arg(args, 0, "zip")?, list_arg(args, 1, "zip")?);
+    let pairs: Vec<StackValue> = list1.into_iter().zip(list2)
+        .map(|(v1, v2)| StackValue::from_box(Box::new(Value::Tuple(vec![v1, v2]))))
+        .collect();
+    Result::Ok(V::Val(Value::List(pairs)))
+}
+
+/**
+ * List of pairs made of the index and the element. Eg: enumerate(["a", "b"]) is [(0, "a"), (1, "b")]
+ */
+fn enumerate(args: &[StackValue]) -> Result<V, Error> {
+    let pairs: Vec<StackValue> = list_arg(args, 0, "enumerate")?.into_iter().enumerate()
+        .map(|(i, value)| StackValue::from_box(Box::new(Value::Tuple(vec![StackValue::from_box(Box::new(Value::Int(i as i64))), value]))))
+        .collect();
+    Result::Ok(V::Val(Value::List(pairs)))
+}
+
+fn reverse(args: &[StackValue]) -> Result<V, Error> {
+    match value(&args[0]) {
+        Value::Str(s) => Result::Ok(V::Val(Value::Str(s.chars().rev().collect()))),
+        _ => {
+            let mut list: Vec<StackValue> = list_arg(args, 0, "reverse")?;
+            list.reverse();
+            Result::Ok(V::Val(Value::List(list)))
+        }
+    }
+}
+
+/**
+ * Sorts a list with a comparison function that can fail. The sort is stable.
+ * After the first error the remaining comparisons are skipped, and the error is returned
+ */
+fn sort_list(
+    mut list: Vec<StackValue>,
+    mut cmp: impl FnMut(StackValue, StackValue) -> Result<Ordering, Error>
+) -> Result<V, Error> {
+    let mut error: Option<Error> = Option::None;
+    list.sort_by(|v1, v2| {
+        if error.is_some() { return Ordering::Equal }
+        cmp(*v1, *v2).unwrap_or_else(|err| {
+            error = Option::Some(err);
+            Ordering::Equal
+        })
+    });
+    match error {
+        Option::Some(err) => Result::Err(err),
+        Option::None => Result::Ok(V::Val(Value::List(list)))
+    }
+}
+
+/**
+ * Returns a sorted copy of a list of numbers or strings
+ */
+fn sort(args: &[StackValue]) -> Result<V, Error> {
+    sort_list(list_arg(args, 0, "sort")?, |v1, v2| compare(value(&v1), value(&v2)))
+}
+
+/**
+ * Returns a sorted copy of a list. The comparison function returns a negative number if its
+ * first argument comes first, a positive number if it comes last and 0 if they are equal
+ */
+fn sort_by(args: &[StackValue]) -> Result<V, Error> {
+    sort_list(list_arg(args, 0, "sort_by")?, |v1, v2| {
+        let result: StackValue = stack_value(call_function(args[1], vec![v1, v2])?);
+        match value(&result) {
+            Value::Int(i) => Result::Ok(i.cmp(&0)),
+            other => Result::Err(exception(&format!("The comparison function of sort_by must return an int, found {}", other)))
+        }
+    })
+}
+
+fn float_arg(args: &[StackValue], i: usize, function: &str) -> Result<f64, Error> {
+    match value(&args[i]) {
+        Value::Int(i) => Result::Ok(*i as f64),
+        Value::BigInt(i) => Result::Ok(i.to_f64().unwrap_or(f64::NAN)),
+        Value::Float(x) => Result::Ok(*x),
+        other => Result::Err(exception(&format!("Function {} expects a number, found {}", function, other)))
+    }
+}
+
+fn abs(args: &[StackValue]) -> Result<V, Error> {
+    let value: Value = match value(&args[0]) {
+        Value::Int(i) => match i.checked_abs() {
+            Option::Some(i) => Value::Int(i),
+            Option::None => Value::from_big_int(BigInt::from(*i).abs())
+        },
+        Value::BigInt(i) => Value::from_big_int(i.abs()),
+        Value::Float(x) => Value::Float(x.abs()),
+        other => return Result::Err(exception(&format!("Function abs expects a number, found {}", other)))
+    };
+    Result::Ok(V::Val(value))
+}
+
+/**
+ * Returns the argument that comes first (or last) in the given order.
+ * With a single list argument the elements of the list are compared instead
+ */
+fn extremum(args: &[StackValue], function: &str, order: Ordering) -> Result<V, Error> {
+    let values: Vec<StackValue> = match args {
+        [list] if matches!(value(list), Value::List(_)) => list_arg(args, 0, function)?,
+        _ => args.to_vec()
+    };
+    let mut result: StackValue = match values.first() {
+        Option::Some(first) => *first,
+        Option::None => return Result::Err(exception(&format!("Function {} expects at least one value", function)))
+    };
+    for v in &values[1..] {
+        if compare(value(v), value(&result))? == order {
+            result = *v
+        }
+    }
+    Result::Ok(V::Ptr(result))
+}
+
+fn min(args: &[StackValue]) -> Result<V, Error> {
+    extremum(args, "min", Ordering::Less)
+}
+
+fn max(args: &[StackValue]) -> Result<V, Error> {
+    extremum(args, "max", Ordering::Greater)
+}
+
+fn pow(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(semantics::pow(value(&args[0]), value(&args[1]))?))
+}
+
+fn sqrt(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(Value::Float(float_arg(args, 0, "sqrt")?.sqrt())))
+}
+
+/**
+ * Rounds a number to an integer. Integers are returned unchanged
+ */
+fn round_with(args: &[StackValue], function: &str, round: fn(f64) -> f64) -> Result<V, Error> {
+    let value: Value = match value(&args[0]) {
+        Value::Int(i) => Value::Int(*i),
+        Value::BigInt(i) => Value::BigInt(i.clone()),
+        Value::Float(x) if x.is_finite() => Value::from_big_int(BigInt::from_f64(round(*x)).unwrap()),
+        other => return Result::Err(exception(&format!("Function {} expects a finite number, found {}", function, other)))
+    };
+    Result::Ok(V::Val(value))
+}
+
+fn floor(args: &[StackValue]) -> Result<V, Error> {
+    round_with(args, "floor", f64::floor)
+}
+
+fn ceil(args: &[StackValue]) -> Result<V, Error> {
+    round_with(args, "ceil", f64::ceil)
+}
+
+/**
+ * Rounds half-way cases away from zero
+ */
+fn round(args: &[StackValue]) -> Result<V, Error> {
+    round_with(args, "round", f64::round)
+}
+
+fn sin(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(Value::Float(float_arg(args, 0, "sin")?.sin())))
+}
+
+fn cos(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(Value::Float(float_arg(args, 0, "cos")?.cos())))
+}
+
+fn tan(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(Value::Float(float_arg(args, 0, "tan")?.tan())))
+}
+
+fn asin(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(Value::Float(float_arg(args, 0, "asin")?.asin())))
+}
+
+fn acos(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(Value::Float(float_arg(args, 0, "acos")?.acos())))
+}
+
+fn atan(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(Value::Float(float_arg(args, 0, "atan")?.atan())))
+}
+
+fn atan2(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(Value::Float(float_arg(args, 0, "atan2")?.atan2(float_arg(args, 1, "atan2")?))))
+}
+
+/**
+ * Greatest common divisor of two integers. It is never negative
+ */
+fn gcd(args: &[StackValue]) -> Result<V, Error> {
+    let big_int_arg = |i: usize| match value(&args[i]) {
+        Value::Int(i) => Result::Ok(BigInt::from(*i)),
+        Value::BigInt(i) => Result::Ok(i.clone()),
+        other => Result::Err(exception(&format!("Function gcd expects an int, found {}", other)))
+    };
+    let (mut a, mut b) = (big_int_arg(0)?.abs(), big_int_arg(1)?.abs());
+    while !b.is_zero() {
+        let r = &a % &b;
+        a = b;
+        b = r;
+    }
+    Result::Ok(V::Val(Value::from_big_int(a)))
+}
+
+fn io_exception(action: &str, path: &str, err: io::Error) -> Error {
+    exception(&format!("Can not {} {}: {}", action, path, err))
+}
+
+fn read_file(args: &[StackValue]) -> Result<V, Error> {
+    let path: &str = string_arg(args, 0, "read_file")?;
+    let text: String = fs::read_to_string(path).map_err(|err| io_exception("read file", path, err))?;
+    Result::Ok(V::Val(Value::Str(text)))
+}
+
+fn write_file(args: &[StackValue]) -> Result<V, Error> {
+    let path: &str = string_arg(args, 0, "write_file")?;
+    let text: &str = string_arg(args, 1, "write_file")?;
+    fs::write(path, text).map_err(|err| io_exception("write file", path, err))?;
+    Result::Ok(V::Val(Value::Unit))
+}
+
+/**
+ * Writes at the end of a file, which is created if it does not exist
+ */
+fn append_file(args: &[StackValue]) -> Result<V, Error> {
+    let path: &str = string_arg(args, 0, "append_file")?;
+    let text: &str = string_arg(args, 1, "append_file")?;
+    fs::OpenOptions::new().append(true).create(true).open(path)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .map_err(|err| io_exception("append to file", path, err))?;
+    Result::Ok(V::Val(Value::Unit))
+}
+
+fn read_lines(args: &[StackValue]) -> Result<V, Error> {
+    let path: &str = string_arg(args, 0, "read_lines")?;
+    let text: String = fs::read_to_string(path).map_err(|err| io_exception("read file", path, err))?;
+    Result::Ok(V::Val(string_list(text.lines().map(String::from).collect())))
+}
+
+fn exists(args: &[StackValue]) -> Result<V, Error> {
+    Result::Ok(V::Val(Value::Bool(Path::new(string_arg(args, 0, "exists")?).exists())))
+}
+
+/**
+ * Returns the sorted names of the entries of a directory
+ */
+fn list_dir(args: &[StackValue]) -> Result<V, Error> {
+    let path: &str = string_arg(args, 0, "list_dir")?;
+    let mut names: Vec<String> = fs::read_dir(path)
+        .and_then(|entries| entries.map(|entry| Result::Ok(entry?.file_name().to_string_lossy().into_owned())).collect())
+        .map_err(|err| io_exception("list directory", path, err))?;
+    names.sort();
+    Result::Ok(V::Val(string_list(names)))
+}
+
+fn remove_file(args: &[StackValue]) -> Result<V, Error> {
+    let path: &str = string_arg(args, 0, "remove_file")?;
+    fs::remove_file(path).map_err(|err| io_exception("remove file", path, err))?;
+    Result::Ok(V::Val(Value::Unit))
+}
+
+/**
+ * Creates a directory and its missing parents. It is not an error if the directory already exists
+ */
+fn mkdir(args: &[StackValue]) -> Result<V, Error> {
+    let path: &str = string_arg(args, 0, "mkdir")?;
+    fs::create_dir_all(path).map_err(|err| io_exception("create directory", path, err))?;
+    Result::Ok(V::Val(Value::Unit))
+}
+
+/**
+ * Reads a line from stdin, without the line terminator. At the end of the input it returns unit
+ */
+fn read_stdin_line() -> Result<V, Error> {
+    let mut line: String = String::new();
+    let read: usize = io::stdin().lock().read_line(&mut line).map_err(|err| io_exception("read from", "stdin", err))?;
+    if read == 0 { return Result::Ok(V::Val(Value::Unit)) }
+    if line.ends_with('\n') { line.pop(); }
+    if line.ends_with('\r') { line.pop(); }
+    Result::Ok(V::Val(Value::Str(line)))
+}
+
+/**
+ * Prints a prompt and reads a line from stdin
+ */
+fn input(args: &[StackValue]) -> Result<V, Error> {
+    print!("{}", to_string(&args[0]));
+    io::stdout().flush().or(Result::Err(exception("Can not write to stdout")))?;
+    read_stdin_line()
+}
+
+fn read_line(_args: &[StackValue]) -> Result<V, Error> {
+    read_stdin_line()
+}
+
+fn read_all_stdin(_args: &[StackValue]) -> Result<V, Error> {
+    let mut text: String = String::new();
+    io::stdin().lock().read_to_string(&mut text).map_err(|err| io_exception("read from", "stdin", err))?;
+    Result::Ok(V::Val(Value::Str(text)))
+}
+
+/**
+ * Returns the value of an environment variable, or unit if it is not set
+ */
+fn get_env(args: &[StackValue]) -> Result<V, Error> {
+    match env::var(string_arg(args, 0, "env")?) {
+        Result::Ok(value) => Result::Ok(V::Val(Value::Str(value))),
+        Result::Err(_) => Result::Ok(V::Val(Value::Unit))
+    }
+}
+
+fn set_env(args: &[StackValue]) -> Result<V, Error> {
+    let name: &str = string_arg(args, 0, "set_env")?;
+    let value: &str = string_arg(args, 1, "set_env")?;
+    if name.is_empty() || name.contains(['=', '\0']) || value.contains('\0') {
+        return Result::Err(exception(&format!("Invalid environment variable {}", name)))
+    }
+    env::set_var(name, value);
+    Result::Ok(V::Val(Value::Unit))
+}
+
+/**
+ * Terminates the process with the given exit code
+ */
+fn exit(args: &[StackValue]) -> Result<V, Error> {
+    let code: i32 = match i32::try_from(int_arg(args, 0, "exit")?) {
+        Result::Ok(code) => code,
+        Result::Err(_) => return Result::Err(exception("Exit code out of range"))
+    };
+    let _ = io::stdout().flush();
+    process::exit(code)
+}
+
+/**
+ * Resumes a generator until its next yield and returns the yielded value.
+ * Once the generator has ended it throws "GeneratorDone", so that a yielded null is not mistaken for the end
+ */
+fn next(args: &[StackValue]) -> Result<V, Error> {
+    semantics::next_value(args[0])?.ok_or_else(|| exception("GeneratorDone"))
+}
+
+/**
+ * Creates a thread that calls the function. Threads run while the main program waits: in `yield_now`,
+ * `join`, `send` or `recv`. The threads that have not ended run when the main program ends, until they end
+ * or wait for something that will never happen
+ */
+fn spawn(args: &[StackValue]) -> Result<V, Error> {
+    match value(&args[0]) {
+        Value::Fn(_) | Value::NativeFn(_) | Value::Continuation(_) => Result::Ok(V::Val(Value::Thread(threads::spawn(args[0])))),
+        other => Result::Err(exception(&format!("Function spawn expects a function, found {}", other)))
+    }
+}
+
+/**
+ * Lets the other threads run
+ */
+fn yield_now(_args: &[StackValue]) -> Result<V, Error> {
+    threads::wait(Op::Yield)
+}
+
+/**
+ * Creates a channel. `channel()` is unbuffered: each send waits until the value is received.
+ * `channel(n)` buffers up to n values, and send waits only when the buffer is full
+ */
+fn channel(args: &[StackValue]) -> Result<V, Error> {
+    let capacity: usize = match args.len() {
+        0 => 0,
+        1 => usize::try_from(int_arg(args, 0, "channel")?)
+            .or(Result::Err(exception("The capacity of a channel can not be negative")))?,
+        n => return Result::Err(exception(&format!("Wrong number of arguments. Expected 0 or 1, found {}", n)))
+    };
+    Result::Ok(V::Val(Value::Channel(threads::channel(capacity))))
+}
+
+fn channel_arg(args: &[StackValue], function: &str) -> Result<usize, Error> {
+    match value(&args[0]) {
+        Value::Channel(id) => Result::Ok(*id),
+        other => Result::Err(exception(&format!("Function {} expects a channel, found {}", function, other)))
+    }
+}
+
+fn send(args: &[StackValue]) -> Result<V, Error> {
+    threads::wait(Op::Send(channel_arg(args, "send")?, args[1]))
+}
+
+/**
+ * Waits for a value in the channel and returns it. Values are received in the order they were sent
+ */
+fn recv(args: &[StackValue]) -> Result<V, Error> {
+    threads::wait(Op::Recv(channel_arg(args, "recv")?))
+}
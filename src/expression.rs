@@ -1,23 +1,58 @@
 use std::fmt;
 
+use num_bigint::BigInt;
+
 #[derive(Clone, Debug)]
 pub enum Exp {
     // Eg: 1, False, None, "Hello"
     Const(Const),
     // Eg: x, y, z
     Var(Var),
+    // Variable captured by a function from an enclosing scope. Eg: x in `let x = 1; fn () { x }`
+    ExternalVar(Var),
     // List instantiation. Eg: [exp1, ... , expN]
     List(Vec<Exp>),
-    // List selection. Eg: exp[1]
+    // Tuple instantiation. Eg: (exp1, ... , expN)
+    Tuple(Vec<Exp>),
+    // Map instantiation. Eg: #{key1: exp1, ... , keyN: expN}
+    Map(Vec<(Exp, Exp)>),
+    // Struct instantiation, with the fields in declaration order. Eg: Point { x: exp1, y: exp2 }
+    StructInstance(String, Vec<(String, Exp)>),
+    // Eg: exp.x
+    FieldAccess(Box<Exp>, String),
+    // Enum variant instantiation: enum name, variant name and values. Eg: Circle(exp)
+    EnumInstance(String, String, Vec<Exp>),
+    // Match arms are made of a pattern, an optional guard and a body. Eg: match exp { pattern if guard => body, ... }
+    Match(Box<Exp>, Vec<(Pattern, Option<Exp>, Exp)>),
+    // List selection, also used to access maps. Eg: exp[1]
     ListSelection(Box<Exp>, Box<Exp>),
     // Eg: let x = exp1; exp2
-    Decl(Var, Box<Exp>, Box<Exp>),
-    // While loop. Eg: while (exp1) { exp2 }
-    While(Box<Exp>, Box<Exp>),
+    // Eg: let (x, y) = exp1; exp2
+    Decl(Pattern, Box<Exp>, Box<Exp>),
+    // Function declarations, visible in the whole block that contains them. Eg: fn f(x) { exp1 } fn g(y) { exp2 } exp3
+    FunctionDecl(Vec<Var>, Vec<Exp>, Box<Exp>),
+    // While loop, with an optional label. Eg: while (exp1) { exp2 }, 'outer: while (exp1) { exp2 }
+    While(Box<Exp>, Box<Exp>, Option<String>),
+    // For loop over the elements of a list, the characters of a string, the keys of a map or a range.
+    // Eg: for x in exp1 { exp2 }
+    For(Pattern, Box<Exp>, Box<Exp>, Option<String>),
+    // Exits the loop with the given label, or the innermost loop, which evaluates to the value. Eg: break 'outer exp
+    Break(Option<String>, Box<Exp>),
+    // Skips to the next iteration of the loop with the given label, or of the innermost loop. Eg: continue
+    Continue(Option<String>),
+    // Exits the function that is being executed, which evaluates to the value. Eg: return exp
+    Return(Box<Exp>),
+    // Suspends the generator that is being executed, which produces the value. Eg: yield exp
+    Yield(Box<Exp>),
+    // Body of a function that contains `yield`: the call evaluates to a generator, which evaluates the body lazily
+    Generator(Box<Exp>),
+    // Module of the file with the given path, relative to the importing file. Eg: import "lib.epi"
+    Import(String),
     // If then else. Eg: if exp {exp1} else {exp2}
     IfThenElse(Box<Exp>, Box<Exp>, Box<Exp>),
     // Function definition. Eg: fn (arg_1, .. arg_n) { body }
-    Function(Vec<Var>, Box<Exp>),
+    // The second element holds the captured variables, as seen from the enclosing scope
+    Function(Vec<Var>, Vec<Exp>, Box<Exp>),
     // Function call. Eg: exp(arg_1, ... , arg_n)
     FunctionCall(Box<Exp>, Vec<Exp>),
     // Eg: x = exp
@@ -30,6 +65,8 @@ pub enum Exp {
     Sub(Box<Exp>, Box<Exp>),
     // Eg: exp1 * exp2
     Mul(Box<Exp>, Box<Exp>),
+    // Eg: exp1 ** exp2
+    Pow(Box<Exp>, Box<Exp>),
     // Eg: exp1 * exp2
     Mod(Box<Exp>, Box<Exp>),
     // Eg: exp1 / exp2
@@ -46,6 +83,10 @@ pub enum Exp {
     Eq(Box<Exp>, Box<Exp>),
     // Eg: exp1 != exp2
     Neq(Box<Exp>, Box<Exp>),
+    // Membership in a list, map, string or range. Eg: exp1 in exp2
+    In(Box<Exp>, Box<Exp>),
+    // Range of integers, the boolean is true if the end is included. Eg: exp1..exp2, exp1..=exp2
+    Range(Box<Exp>, Box<Exp>, bool),
     // Eg: exp1 && exp2
     And(Box<Exp>, Box<Exp>),
     // Eg: exp1 || exp2
@@ -58,21 +99,69 @@ pub enum Exp {
     TryCatch(Box<Exp>,Var,Box<Exp>),
     //Eg: throw "DivZeroException"
     Throw(Box<Exp>),
-    //Eg: throw k 5
-    Throwcc(Var, Box<Exp>),
-    //Eg: callcc k in e
-    Callcc(Var,Box<Exp>)
+    //Eg: throw k 5, resumes the continuation k with the value 5
+    Throwcc(Box<Exp>, Box<Exp>),
+    //Eg: callcc k in { e }, binds the current continuation to k in e
+    Callcc(Var,Box<Exp>),
+    // Calls the innermost handler of the effect, with the arguments and the continuation up to its handle.
+    // Eg: perform Ask(x)
+    Perform(String, Vec<Exp>),
+    // Evaluates the body with handlers for effects. Eg: handle { e } with { Ask(x), k => k(x + 1) }
+    Handle(Box<Exp>, Vec<Handler>),
+    // Delimits the continuations captured by the shifts in the body. Eg: reset { e }
+    Reset(Box<Exp>),
+    // Binds the continuation up to the enclosing reset to k, as a function, and makes the reset evaluate to e.
+    // Eg: shift k { e }
+    Shift(Var, Box<Exp>)
 }
 
+// Handler of an effect: the effect name, the patterns of the arguments as a tuple pattern,
+// the variable of the continuation and the body
+pub type Handler = (String, Pattern, Var, Exp);
+
 #[derive(Clone, Debug)]
 pub struct Var {
     pub name: String,
     pub scope: usize
 }
 
+/**
+ * Left-hand side of a let declaration, a function parameter or a match arm.
+ * The variables of a pattern are stored in consecutive scopes, from left to right
+ */
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    // Eg: x
+    Var(Var),
+    // Eg: (x, (y, z))
+    Tuple(Vec<Pattern>),
+    // Only in match arms. Eg: _
+    Wildcard,
+    // Only in match arms. Eg: 1, "hello"
+    Literal(Const),
+    // Only in match arms. Eg: [x, 1, _]
+    List(Vec<Pattern>),
+    // Only in match arms: enum name, variant name and patterns of the values. Eg: Rect(w, h)
+    Constructor(String, String, Vec<Pattern>)
+}
+
+impl Pattern {
+    pub fn num_vars(&self) -> usize {
+        match self {
+            Pattern::Var(_) => 1,
+            Pattern::Wildcard | Pattern::Literal(_) => 0,
+            Pattern::Tuple(patterns) | Pattern::List(patterns) | Pattern::Constructor(_, _, patterns) =>
+                patterns.iter().map(|pattern| pattern.num_vars()).sum()
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Const {
-    Integer(i32),
+    Integer(i64),
+    // Integer literal that does not fit in 64 bits
+    BigInt(BigInt),
+    Float(f64),
     Boolean(bool),
     String(String),
     None
@@ -82,6 +171,8 @@ impl fmt::Display for Const {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Const::Integer(i) => write!(f, "{}", i),
+            Const::BigInt(i) => write!(f, "{}", i),
+            Const::Float(x) => write!(f, "{:?}", x),
             Const::Boolean(b) => write!(f, "{}", b),
             Const::String(s) => write!(f, "{}", s),
             Const::None => write!(f, "unit")
@@ -93,6 +184,8 @@ impl PartialEq for Const {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Const::Integer(i1), Const::Integer(i2)) => i1 == i2,
+            (Const::BigInt(i1), Const::BigInt(i2)) => i1 == i2,
+            (Const::Float(f1), Const::Float(f2)) => f1 == f2,
             (Const::Boolean(b1), Const::Boolean(b2)) => b1 == b2,
             (Const::String(s1), Const::String(s2)) => s1 == s2,
             (Const::None, Const::None) => true,
@@ -1,3 +1,5 @@
+use num_bigint::BigInt;
+
 use crate::token::Token;
 use crate::token::Operand;
 use crate::token::Operator;
@@ -16,6 +18,9 @@ pub fn tokenize(text: String) -> Result<Vec<Token>, LexicalError> {
 
     let mut chars = text.chars().peekable();
 
+    // For each open curly bracket, true if it is the beginning of a map literal
+    let mut curly_brackets: Vec<bool> = Vec::new();
+
     let mut tmp=Token::If;
     let mut found=false;
 
@@ -53,8 +58,66 @@ pub fn tokenize(text: String) -> Result<Vec<Token>, LexicalError> {
                 tokens.push(token)
             },
 
+            Option::Some('#') => {
+                flush_buffer(&mut buffer, &mut tokens, &mut callable)?;
+                match chars.next() {
+                    Option::Some('{') => (),
+                    _ => return Result::Err(LexicalError { msg: String::from("Expected `{` after `#`") })
+                };
+                curly_brackets.push(true);
+                let token = Token::MapOpen;
+                callable = token.is_callable();
+                tokens.push(token)
+            },
+
+            Option::Some('{') => {
+                flush_buffer(&mut buffer, &mut tokens, &mut callable)?;
+                curly_brackets.push(false);
+                let token = Token::CurlyBracketOpen;
+                callable = token.is_callable();
+                tokens.push(token)
+            },
+
+            Option::Some('}') => {
+                flush_buffer(&mut buffer, &mut tokens, &mut callable)?;
+                let token = match curly_brackets.pop() {
+                    Option::Some(true) => Token::MapClosed,
+                    _ => Token::CurlyBracketClosed
+                };
+                callable = token.is_callable();
+                tokens.push(token)
+            },
+
+            // Ranges. Eg: 0..n, 1..=n
+            Option::Some('.') if chars.peek() == Option::Some(&'.') => {
+                flush_buffer(&mut buffer, &mut tokens, &mut callable)?;
+                chars.next();
+                let token = match chars.peek() {
+                    Option::Some('=') => {
+                        chars.next();
+                        Token::Operator(Operator::RangeInclusive)
+                    },
+                    _ => Token::Operator(Operator::Range)
+                };
+                callable = token.is_callable();
+                tokens.push(token)
+            },
+
+            // Decimal point of float literals
+            Option::Some('.') if buffer.starts_with(|c: char| c.is_ascii_digit()) => buffer.push('.'),
+
+            Option::Some('.') => {
+                flush_buffer(&mut buffer, &mut tokens, &mut callable)?;
+                let token = Token::Dot;
+                callable = token.is_callable();
+                tokens.push(token)
+            },
+
+            // Sign of the exponent in float literals. Eg: 1e-9
+            Option::Some(c) if (c == '-' || c == '+') && is_exponent_prefix(&buffer) => buffer.push(c),
+
             Option::Some(c) if [
-                ';', ',', '+', '-', '*', ']', '{', '}', ')', '%'
+                ';', ',', ':', '+', '-', ']', ')', '%'
             ].contains(&c) => {
                 flush_buffer(&mut buffer, &mut tokens, &mut callable)?;
                 let token = make_token(&c.to_string())?;
@@ -62,6 +125,19 @@ pub fn tokenize(text: String) -> Result<Vec<Token>, LexicalError> {
                 tokens.push(token)
             },
 
+            Option::Some('*') => {
+                flush_buffer(&mut buffer, &mut tokens, &mut callable)?;
+                let token = match chars.peek() {
+                    Option::Some('*') => {
+                        chars.next();
+                        Token::Operator(Operator::Pow)
+                    },
+                    _ => Token::Operator(Operator::Mul)
+                };
+                callable = token.is_callable();
+                tokens.push(token)
+            },
+
             Option::Some('=') => {
                 flush_buffer(&mut buffer, &mut tokens, &mut callable)?;
                 let token = match chars.peek() {
@@ -69,6 +145,10 @@ pub fn tokenize(text: String) -> Result<Vec<Token>, LexicalError> {
                         chars.next();
                         Token::Operator(Operator::Eq)
                     },
+                    Option::Some('>') => {
+                        chars.next();
+                        Token::FatArrow
+                    },
                     _ => Token::Operator(Operator::Assign)
                 };
                 callable = token.is_callable();
@@ -210,7 +290,7 @@ pub fn tokenize(text: String) -> Result<Vec<Token>, LexicalError> {
 
 fn flush_buffer(buffer: &mut String, tokens: &mut Vec<Token>, callable: &mut bool) -> Result<(), LexicalError> {
     if !buffer.is_empty() {
-        let token: Token = make_token(&buffer)?;
+        let token: Token = make_token(buffer)?;
         buffer.clear();
         *callable = token.is_callable();
         tokens.push(token);
@@ -218,8 +298,8 @@ fn flush_buffer(buffer: &mut String, tokens: &mut Vec<Token>, callable: &mut boo
     Result::Ok(())
 }
 
-fn make_token(word: &String) -> Result<Token, LexicalError> {
-    let token = match word.as_str() {
+fn make_token(word: &str) -> Result<Token, LexicalError> {
+    let token = match word {
         "true" => Token::Operand(Operand::Bool(true)),
         "false" => Token::Operand(Operand::Bool(false)),
         "null" => Token::Operand(Operand::Null),
@@ -246,7 +326,19 @@ fn make_token(word: &String) -> Result<Token, LexicalError> {
         "catch" => Token::Catch,
         "throw" => Token::Operator(Operator::Throw),
         "callcc" => Token::Callcc,
+        "reset" => Token::Reset,
+        "shift" => Token::Shift,
         "in" => Token::In,
+        "struct" => Token::Struct,
+        "enum" => Token::Enum,
+        "match" => Token::Match,
+        "for" => Token::For,
+        "break" => Token::Break,
+        "continue" => Token::Continue,
+        "return" => Token::Return,
+        "yield" => Token::Yield,
+        "perform" => Token::Perform,
+        "import" => Token::Import,
         "(" => Token::RoundBracketOpen,
         ")" => Token::RoundBracketClosed,
         "[" => Token::SquareBracketOpen,
@@ -254,10 +346,36 @@ fn make_token(word: &String) -> Result<Token, LexicalError> {
         "{" => Token::CurlyBracketOpen,
         "}" => Token::CurlyBracketClosed,
         "," => Token::Comma,
-        s => match s.parse::<i32>() {
+        ":" => Token::Colon,
+        s if s.starts_with('\'') => match s.strip_prefix('\'') {
+            Option::Some(name) if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') => Token::Label(String::from(name)),
+            _ => return Result::Err(LexicalError { msg: format!("Invalid loop label `{}`", s) })
+        },
+        s => match s.parse::<i64>() {
             Result::Ok(i) => Token::Operand(Operand::Int(i)),
+            // Integer literals that do not fit in 64 bits
+            Result::Err(_) if s.chars().all(|c| c.is_ascii_digit()) => match s.parse::<BigInt>() {
+                Result::Ok(i) => Token::Operand(Operand::BigInt(i)),
+                Result::Err(_) => return Result::Err(LexicalError { msg: format!("Invalid number literal `{}`", s) })
+            },
+            // Words like `inf` or `NaN` are parsed as floats by Rust, so we also check the first character
+            Result::Err(_) if s.starts_with(|c: char| c.is_ascii_digit()) => match s.parse::<f64>() {
+                Result::Ok(x) if s.contains(['.', 'e', 'E']) => Token::Operand(Operand::Float(x)),
+                _ => return Result::Err(LexicalError { msg: format!("Invalid number literal `{}`", s) })
+            },
             Result::Err(_) => Token::Operand(Operand::Var(String::from(s)))
         }
     };
     Result::Ok(token)
 }
+
+/**
+ * Returns true if the buffer contains a number literal that ends with the exponent marker. Eg: `1e`, `2.5E`
+ */
+fn is_exponent_prefix(buffer: &str) -> bool {
+    match buffer.strip_suffix(|c: char| c == 'e' || c == 'E') {
+        Option::Some(mantissa) => mantissa.starts_with(|c: char| c.is_ascii_digit())
+            && mantissa.chars().all(|c| c.is_ascii_digit() || c == '.'),
+        Option::None => false
+    }
+}
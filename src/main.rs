@@ -5,19 +5,32 @@ mod lexer;
 mod semantics;
 mod shell;
 mod value;
+mod builtins;
+mod module;
 mod run;
+mod threads;
 mod tests;
 
 use std::env;
-use run::run_file;
+use std::io::{self, IsTerminal};
+use std::process::ExitCode;
+use builtins::set_script_args;
+use run::{run_file, run_stdin};
 
 
-fn main() {
+fn main() -> ExitCode {
     let mut args: Vec<String> = env::args().collect();
     if args.len() > 1 {
         let file_path: String = args.remove(1);
-        run_file(file_path)
+        // The arguments after the program are passed to it
+        set_script_args(args.split_off(1));
+        // `-` reads the program from stdin
+        if file_path == "-" { run_stdin() } else { run_file(file_path) }
+    } else if !io::stdin().is_terminal() {
+        // The program is piped, so there is no user to interact with
+        run_stdin()
     } else {
-        shell::run_shell()
+        shell::run_shell();
+        ExitCode::SUCCESS
     }
 }
@@ -0,0 +1,91 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::expression::Exp;
+use crate::lexer::tokenize;
+use crate::token::Token;
+use crate::parser::parse_module;
+use crate::semantics::{Error, eval};
+use crate::value::{StackValue, V};
+use crate::value::Value::Unit;
+use crate::value::V::Val;
+
+/**
+ * Name of the struct that holds the bindings of a module
+ */
+pub const MODULE_STRUCT: &str = "module";
+
+/**
+ * Modules that have already been evaluated, and the files that are being evaluated, from the main file
+ */
+struct Modules {
+    loaded: HashMap<PathBuf, StackValue>,
+    loading: Vec<PathBuf>
+}
+
+thread_local! {
+    static MODULES: RefCell<Modules> = RefCell::new(Modules{loaded: HashMap::new(), loading: Vec::new()});
+}
+
+/**
+ * Sets the file of the main program. The paths imported by it are relative to its directory
+ */
+pub fn set_main_file(path: &Path) {
+    let path: PathBuf = path.canonicalize().unwrap_or(path.to_path_buf());
+    MODULES.with(|modules| modules.borrow_mut().loading.push(path))
+}
+
+/**
+ * Evaluates the file at the given path the first time it is imported, and returns its module.
+ * Importing a file that is still being evaluated is an import cycle
+ */
+pub fn import(path: &str) -> Result<StackValue, Error> {
+    let path: PathBuf = resolve(path)?;
+    let cycle: Option<Vec<PathBuf>> = MODULES.with(|modules| {
+        let modules = modules.borrow();
+        modules.loading.iter().position(|file| *file == path).map(|i| modules.loading[i..].to_vec())
+    });
+    if let Option::Some(files) = cycle {
+        let files: Vec<String> = files.iter().chain([&path]).map(|file| file.display().to_string()).collect();
+        return Result::Err(Error{msg: format!("Import cycle: {}", files.join(" -> ")), v:Val(Unit)})
+    }
+    if let Option::Some(module) = MODULES.with(|modules| modules.borrow().loaded.get(&path).copied()) {
+        return Result::Ok(module)
+    }
+
+    MODULES.with(|modules| modules.borrow_mut().loading.push(path.clone()));
+    let module: Result<V, Error> = eval_module(&path);
+    MODULES.with(|modules| modules.borrow_mut().loading.pop());
+    let module: StackValue = match module? {
+        V::Ptr(ptr) => ptr,
+        V::Val(value) => StackValue::from_box(Box::new(value))
+    };
+    MODULES.with(|modules| modules.borrow_mut().loaded.insert(path, module));
+    Result::Ok(module)
+}
+
+/**
+ * Resolves a path relative to the directory of the file that is being evaluated
+ */
+fn resolve(path: &str) -> Result<PathBuf, Error> {
+    let dir: Option<PathBuf> = MODULES.with(|modules| {
+        modules.borrow().loading.last().and_then(|file| file.parent()).map(Path::to_path_buf)
+    });
+    let full_path: PathBuf = match dir {
+        Option::Some(dir) => dir.join(path),
+        Option::None => PathBuf::from(path)
+    };
+    full_path.canonicalize().or(Result::Err(Error{msg: format!("Module {} not found", path), v:Val(Unit)}))
+}
+
+fn eval_module(path: &Path) -> Result<V, Error> {
+    let text: String = fs::read_to_string(path)
+        .or(Result::Err(Error{msg: format!("Can not read module {}", path.display()), v:Val(Unit)}))?;
+    let mut tokens: Vec<Token> = tokenize(text)
+        .map_err(|err| Error{msg: format!("Lexical error in module {}: {}", path.display(), err.msg), v:Val(Unit)})?;
+    let exp: Exp = parse_module(&mut tokens)
+        .map_err(|err| Error{msg: format!("Syntax error in module {}: {}", path.display(), err.msg), v:Val(Unit)})?;
+    eval(exp)
+}
@@ -3,6 +3,11 @@ use std::collections::HashMap;
 use crate::expression::Exp;
 use crate::expression::Const;
 use crate::expression::Var;
+use crate::expression::Pattern;
+use crate::expression::Handler;
+
+use crate::builtins::{BUILTINS, GLOBALS, builtin_variables};
+use crate::module::MODULE_STRUCT;
 
 use crate::token::Token;
 use crate::token::Operand;
@@ -14,20 +19,90 @@ pub struct SyntaxError {
 
 pub struct FunctionScope {
     pub input_vars: Vec<Var>,
-    pub external_variables: Vec<Var>,
+    // Variables captured from the enclosing scopes, as seen from the enclosing function scope.
+    // An `Exp::ExternalVar` with scope `i` refers to `external_variables[i]`
+    pub external_variables: Vec<Exp>,
     pub var_scope: usize,
-    pub variable_map: HashMap<String, usize>
+    pub variable_map: HashMap<String, usize>,
+    // Input variables that are destructured at the beginning of the function body. Eg: `(x, y)` in `fn ((x, y)) { x }`
+    pub param_patterns: Vec<(Var, Pattern)>,
+    // Struct types declared in this function scope, with their field names
+    pub struct_types: HashMap<String, Vec<String>>,
+    // Variants of the enum types declared in this function scope, with their enum name and number of values
+    pub enum_variants: HashMap<String, (String, usize)>,
+    // Labels of the loops that enclose the current token, from the outermost. Unlabeled loops have no label
+    pub loop_labels: Vec<Option<String>>,
+    // True if the body contains `yield`: calling the function creates a generator
+    pub is_generator: bool,
+    // Variable maps saved at the beginning of the blocks that enclose the current token, restored at their end
+    pub block_variable_maps: Vec<HashMap<String, usize>>
+}
+
+impl FunctionScope {
+    /**
+     * Scope of the main program, of a module or of the shell
+     */
+    pub fn main() -> FunctionScope {
+        FunctionScope {
+            input_vars: Vec::new(),
+            external_variables: Vec::new(),
+            // Current variable scope depth. The builtins are declared before the variables of the program
+            var_scope: BUILTINS.len() + GLOBALS.len(),
+            variable_map: builtin_variables(),
+            param_patterns: Vec::new(),
+            struct_types: HashMap::new(),
+            enum_variants: HashMap::new(),
+            loop_labels: Vec::new(),
+            is_generator: false,
+            block_variable_maps: Vec::new()
+        }
+    }
 }
 
 pub fn parse(tokens: &mut Vec<Token>) -> Result<Exp, SyntaxError> {
-    let main_scope: FunctionScope = FunctionScope {
-        input_vars: Vec::new(),
-        external_variables: Vec::new(),
-        // Current variable scope depth
-        var_scope: 0,
-        variable_map: HashMap::new()
-    };
-    parse_tokens(tokens, &mut vec![main_scope])
+    parse_tokens(tokens, &mut vec![FunctionScope::main()])
+}
+
+/**
+ * Parses a file that is imported as a module. The module evaluates to a struct with its top level bindings,
+ * except those whose name starts with `_`
+ */
+pub fn parse_module(tokens: &mut Vec<Token>) -> Result<Exp, SyntaxError> {
+    let exp: Exp = parse(tokens)?;
+    Result::Ok(export_bindings(exp, &mut Vec::new()))
+}
+
+/**
+ * Appends to the last expression of the top level scope the struct of the bindings declared before it
+ */
+fn export_bindings(exp: Exp, exports: &mut Vec<Var>) -> Exp {
+    match exp {
+        Exp::Decl(pattern, value, rest) => {
+            pattern_vars(&pattern, exports);
+            Exp::Decl(pattern, value, Box::new(export_bindings(*rest, exports)))
+        },
+        Exp::FunctionDecl(vars, functions, rest) => {
+            exports.extend(vars.iter().cloned());
+            Exp::FunctionDecl(vars, functions, Box::new(export_bindings(*rest, exports)))
+        },
+        Exp::Seq(exp1, exp2) => Exp::Seq(exp1, Box::new(export_bindings(*exp2, exports))),
+        exp => {
+            let fields: Vec<(String, Exp)> = exports.iter()
+                .filter(|var| !var.name.starts_with('_'))
+                .map(|var| (var.name.clone(), Exp::Var(var.clone())))
+                .collect();
+            Exp::Seq(Box::new(exp), Box::new(Exp::StructInstance(String::from(MODULE_STRUCT), fields)))
+        }
+    }
+}
+
+fn pattern_vars(pattern: &Pattern, vars: &mut Vec<Var>) {
+    match pattern {
+        Pattern::Var(var) => vars.push(var.clone()),
+        Pattern::Wildcard | Pattern::Literal(_) => (),
+        Pattern::Tuple(patterns) | Pattern::List(patterns) | Pattern::Constructor(_, _, patterns) =>
+            patterns.iter().for_each(|pattern| pattern_vars(pattern, vars))
+    }
 }
 
 /**
@@ -39,14 +114,34 @@ pub fn parse_tokens(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionSc
     // Initialize the output queue
     let mut out: Vec<Exp> = Vec::new();
 
+    handle_function_decls(tokens, &mut stack, function_stack)?;
+
     loop {
         if tokens.is_empty() { break };
         let token = tokens.pop().unwrap();
         match &token {
+            // Enum variants are used like constants or like functions. Eg: Empty, Circle(1)
+            Token::Operand(Operand::Var(x)) if resolve_variant(x, function_stack).is_some() => {
+                let (name, num_values) = resolve_variant(x, function_stack).unwrap();
+                let exp: Exp = parse_enum_instance(name, x, num_values, tokens, function_stack)?;
+                out.push(exp)
+            },
+            // `handle` is not a keyword, so it can still be used as a variable name. Eg: handle { e } with { Ask(), k => k(1) }
+            Token::Operand(Operand::Var(x)) if x == "handle" && tokens.last() == Option::Some(&Token::CurlyBracketOpen) => {
+                let exp: Exp = parse_handle(tokens, function_stack)?;
+                out.push(exp);
+                insert_seq_after_block(tokens)
+            },
+            // A struct name followed by `{` is a struct instantiation. Eg: Point { x: 1, y: 2 }
+            Token::Operand(Operand::Var(x)) if tokens.last() == Option::Some(&Token::CurlyBracketOpen) && resolve_struct(x, function_stack).is_some() => {
+                let fields: Vec<String> = resolve_struct(x, function_stack).unwrap();
+                let exp: Exp = parse_struct_instance(x, &fields, tokens, function_stack)?;
+                out.push(exp)
+            },
             // Push variable to out. Error if not present in scope
-            Token::Operand(Operand::Var(x)) => match function_stack.last().unwrap().variable_map.get(x) {
-                Option::Some(scope) => out.push(Exp::Var(Var{name: x.clone(), scope: *scope})),
-                Option::None => return Result::Err(SyntaxError{msg: String::from(format!("Unknown variable {}", x))})
+            Token::Operand(Operand::Var(x)) => match resolve_variable(x, function_stack) {
+                Option::Some(var) => out.push(var),
+                Option::None => return Result::Err(SyntaxError{msg: format!("Unknown variable {}", x)})
             }
             // Operands that are not variables are easily pushed to out 
             Token::Operand(o) => out.push(o.to_exp()),
@@ -75,21 +170,179 @@ pub fn parse_tokens(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionSc
                 match tokens.last() {
                     Option::Some(Token::RoundBracketClosed) => {
                         tokens.pop();
-                        handle_round_bracket_closed_token(tokens, &mut stack, &mut out, false)?;
+                        handle_round_bracket_closed_token(&mut stack, &mut out, false)?;
                     }
                     _ => ()
                 }
             }
 
-            Token::RoundBracketOpen => stack.push(Token::RoundBracketOpen),
+            Token::RoundBracketOpen => match tokens.last() {
+                // Empty tuple
+                Option::Some(Token::RoundBracketClosed) => {
+                    tokens.pop();
+                    out.push(Exp::Tuple(Vec::new()))
+                },
+                Option::Some(Token::Comma) => return Result::Err(SyntaxError{msg: String::from("Unexpected token `,` after `(`")}),
+                _ => stack.push(Token::RoundBracketOpen)
+            },
 
-            Token::RoundBracketClosed => handle_round_bracket_closed_token(tokens, &mut stack, &mut out, true)?,
+            Token::RoundBracketClosed => handle_round_bracket_closed_token(&mut stack, &mut out, true)?,
 
-            Token::SquareBracketOpen => stack.push(Token::SquareBracketOpen),
+            Token::SquareBracketOpen => match tokens.last() {
+                // Empty list
+                Option::Some(Token::SquareBracketClosed) => {
+                    tokens.pop();
+                    out.push(Exp::List(Vec::new()))
+                },
+                _ => stack.push(Token::SquareBracketOpen)
+            },
 
             Token::SquareBracketClosed => handle_square_bracket_closed_token(&mut stack, &mut out, false)?,
 
-            Token::While => stack.push(Token::While),
+            Token::MapOpen => match tokens.last() {
+                // Empty map
+                Option::Some(Token::MapClosed) => {
+                    tokens.pop();
+                    out.push(Exp::Map(Vec::new()))
+                },
+                _ => stack.push(Token::MapOpen)
+            },
+
+            Token::Colon => {
+                loop {
+                    match stack.last() {
+                        Option::Some(Token::MapOpen | Token::Comma) => break,
+                        Option::Some(Token::Operator(op)) => {
+                            push_operator_to_out(op, &mut out)?;
+                            stack.pop();
+                        },
+                        _ => return Result::Err(SyntaxError{msg: String::from("Unexpected token `:`")})
+                    }
+                }
+                stack.push(Token::Colon)
+            },
+
+            Token::MapClosed => handle_map_closed_token(&mut stack, &mut out)?,
+
+            // Field access binds tighter than any operator, so it applies to the last expression in out
+            Token::Dot => {
+                let field: String = match tokens.pop() {
+                    Option::Some(Token::Operand(Operand::Var(field))) => field,
+                    _ => return Result::Err(SyntaxError{msg: String::from("Expected field name after `.`")})
+                };
+                let exp: Exp = out.pop().ok_or(SyntaxError{msg: String::from("Missing expression before `.`")})?;
+                out.push(Exp::FieldAccess(Box::new(exp), field))
+            },
+
+            // Struct and enum declarations are removed from the tokens at the beginning of their block
+            Token::Struct => return Result::Err(SyntaxError{msg: String::from("Struct declarations must be statements")}),
+            Token::Enum => return Result::Err(SyntaxError{msg: String::from("Enum declarations must be statements")}),
+
+            Token::Match => {
+                let exp: Exp = parse_match(tokens, function_stack)?;
+                out.push(exp);
+                insert_seq_after_block(tokens)
+            },
+
+            Token::FatArrow => return Result::Err(SyntaxError{msg: String::from("Unexpected `=>` outside of match")}),
+
+            Token::For => {
+                let exp: Exp = parse_for(tokens, function_stack, Option::None)?;
+                out.push(exp);
+                insert_seq_after_block(tokens)
+            },
+
+            Token::While => {
+                function_stack.last_mut().unwrap().loop_labels.push(Option::None);
+                stack.push(Token::While)
+            },
+
+            Token::Label(label) => {
+                match tokens.pop() {
+                    Option::Some(Token::Colon) => (),
+                    _ => return Result::Err(SyntaxError{msg: format!("Expected `:` after label '{}", label)})
+                };
+                match tokens.pop() {
+                    Option::Some(Token::While) => {
+                        function_stack.last_mut().unwrap().loop_labels.push(Option::Some(label.clone()));
+                        stack.push(Token::While)
+                    },
+                    Option::Some(Token::For) => {
+                        let exp: Exp = parse_for(tokens, function_stack, Option::Some(label.clone()))?;
+                        out.push(exp);
+                        insert_seq_after_block(tokens)
+                    },
+                    _ => return Result::Err(SyntaxError{msg: format!("Expected loop after label '{}", label)})
+                }
+            },
+
+            Token::Break => {
+                let label: Option<String> = parse_loop_target(&token, tokens, function_stack)?;
+                // The value is optional. Eg: break; break 'outer; break x + 1
+                let value: Exp = parse_optional_value(tokens, function_stack)?;
+                out.push(Exp::Break(label, Box::new(value)))
+            },
+
+            Token::Continue => {
+                let label: Option<String> = parse_loop_target(&token, tokens, function_stack)?;
+                out.push(Exp::Continue(label))
+            },
+
+            // The main scope is the only one that is not a function body
+            Token::Return if function_stack.len() == 1 => return Result::Err(SyntaxError{msg: String::from("`return` outside of a function")}),
+
+            Token::Return => {
+                let value: Exp = parse_optional_value(tokens, function_stack)?;
+                out.push(Exp::Return(Box::new(value)))
+            },
+
+            Token::Perform => {
+                let effect: String = match tokens.pop() {
+                    Option::Some(Token::Operand(Operand::Var(effect))) => effect,
+                    _ => return Result::Err(SyntaxError{msg: String::from("Expected effect name after perform")})
+                };
+                // Effects without arguments can be performed without brackets
+                let args: Vec<Exp> = match tokens.last() {
+                    Option::Some(Token::FunctionCallOpen) => {
+                        tokens.pop();
+                        parse_args(tokens, function_stack)?
+                    },
+                    _ => Vec::new()
+                };
+                out.push(Exp::Perform(effect, args))
+            },
+
+            Token::Yield => {
+                if function_stack.len() == 1 {
+                    return Result::Err(SyntaxError{msg: String::from("`yield` outside of a function")})
+                }
+                function_stack.last_mut().unwrap().is_generator = true;
+                let value: Exp = parse_optional_value(tokens, function_stack)?;
+                out.push(Exp::Yield(Box::new(value)))
+            },
+
+            Token::Import => {
+                let path: String = match tokens.pop() {
+                    Option::Some(Token::Operand(Operand::Str(path))) => path,
+                    _ => return Result::Err(SyntaxError{msg: String::from("Expected file path after `import`")})
+                };
+                match tokens.last() {
+                    // `import "path" as name` is a shorthand for `let name = import "path"`
+                    Option::Some(Token::Operand(Operand::Var(keyword))) if keyword == "as" => {
+                        tokens.pop();
+                        let name: Token = match tokens.pop() {
+                            Option::Some(token @ Token::Operand(Operand::Var(_))) => token,
+                            _ => return Result::Err(SyntaxError{msg: String::from("Expected module name after `as`")})
+                        };
+                        tokens.push(Token::Operand(Operand::Str(path)));
+                        tokens.push(Token::Import);
+                        tokens.push(Token::Operator(Operator::Assign));
+                        tokens.push(name);
+                        tokens.push(Token::Let)
+                    },
+                    _ => out.push(Exp::Import(path))
+                }
+            },
 
             Token::If => stack.push(Token::If),
 
@@ -104,7 +357,10 @@ pub fn parse_tokens(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionSc
                 //otherwise the interpreter will try to evaluate the exception and fail
                 match tokens.last() {
                     Option::Some(Token::Operand(Operand::Var(name))) => {
+                        // The variable is declared like a let, until the end of the catch block
+                        function_scope.block_variable_maps.push(function_scope.variable_map.clone());
                         function_scope.variable_map.insert(name.clone(), function_scope.var_scope);
+                        function_scope.var_scope += 1;
                     },
                     _ =>return Result::Err(SyntaxError{msg: String::from("Expected variable name after catch token")})
                 };
@@ -115,55 +371,74 @@ pub fn parse_tokens(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionSc
                 handle_let_token(tokens, &mut stack, function_stack.last_mut().unwrap())?
             },
 
+            // A function declaration moved after a let, see `parse_function_decls`
+            Token::Fn if stack.last() == Option::Some(&Token::Operator(Operator::Seq)) && matches!(tokens.last(), Option::Some(Token::Operand(Operand::Var(_)))) => {
+                tokens.push(Token::Fn);
+                handle_function_decls(tokens, &mut stack, function_stack)?
+            },
+
             Token::Fn => {
                 let mut variable_map: HashMap<String, usize> = HashMap::new();
-                let input_var_names: Vec<String> = parse_function_def(tokens)?;
-                let mut input_vars: Vec<Var> = Vec::with_capacity(input_var_names.len());
-                for i in 0..input_var_names.len() {
-                    variable_map.insert(input_var_names[i].clone(), i);
-                    input_vars.push(Var{name: input_var_names[i].clone(), scope: i});
+                let params: Vec<Pattern> = parse_function_def(tokens)?;
+                let mut input_vars: Vec<Var> = Vec::with_capacity(params.len());
+                let mut param_patterns: Vec<(Var, Pattern)> = Vec::new();
+                for (i, param) in params.into_iter().enumerate() {
+                    match param {
+                        Pattern::Var(var) => {
+                            variable_map.insert(var.name.clone(), i);
+                            input_vars.push(Var{name: var.name, scope: i});
+                        },
+                        // The argument has no name, its pattern is declared after all the input variables
+                        pattern => {
+                            let var: Var = Var{name: format!("#{}", i), scope: i};
+                            input_vars.push(var.clone());
+                            param_patterns.push((var, pattern));
+                        }
+                    }
                 }
-                function_stack.push(FunctionScope {
-                    input_vars: input_vars,
+                let mut function = FunctionScope {
+                    // Local variables are pushed to the stack right after the input variables
+                    var_scope: input_vars.len(),
+                    input_vars,
                     external_variables: Vec::new(),
-                    var_scope: 0,
-                    variable_map: variable_map
-                });
+                    variable_map,
+                    param_patterns: Vec::with_capacity(param_patterns.len()),
+                    struct_types: HashMap::new(),
+                    enum_variants: HashMap::new(),
+                    loop_labels: Vec::new(),
+                    is_generator: false,
+                    block_variable_maps: Vec::new()
+                };
+                for (var, mut pattern) in param_patterns {
+                    declare_pattern(&mut pattern, &mut function);
+                    function.param_patterns.push((var, pattern));
+                }
+                function_stack.push(function);
                 stack.push(Token::Fn)
             },
 
             Token::CurlyBracketOpen => {
-                match stack.last(){
-                    Option::Some(Token::In) => {
-                        tokens.push(Token::Operator(Operator::Seq));
-                        tokens.push(Token::Operand(Operand::Null));
-                        tokens.push(Token::Operator(Operator::Assign));
-                        let cont=match out.last(){
-                            Option::Some(Exp::Var(v)) => &v.name,
-                            _ => return Result::Err(SyntaxError{msg: String::from("Unknown error")})
-                        };
-                        tokens.push(Token::Operand(Operand::Var(cont.clone())));
-                        tokens.push(Token::Let);
-                    },
-                    _ => ()
+                // The guard of an if or a while ends where its block begins, so its operators are reduced
+                let is_guard: bool = match stack.iter().rev().find(|token| match token { Token::Operator(_) => false, _ => true }) {
+                    Option::Some(Token::If | Token::While) => true,
+                    _ => false
+                };
+                if is_guard {
+                    while let Option::Some(Token::Operator(op)) = stack.last() {
+                        push_operator_to_out(op, &mut out)?;
+                        stack.pop();
+                    }
                 };
-                stack.push(Token::CurlyBracketOpen)
+                stack.push(Token::CurlyBracketOpen);
+                let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
+                function_scope.block_variable_maps.push(function_scope.variable_map.clone());
+                handle_function_decls(tokens, &mut stack, function_stack)?
             },
 
             Token::CurlyBracketClosed => {
                 // Closing curly brackets can decrement scope
                 handle_curly_bracket_closed_token(&mut stack, &mut out, function_stack)?;
-                // After closing a curly bracket we automatically insert `;` if not present.
-                // This makes the syntax more similar to Java, C++ etc
-                match tokens.last() {
-                    Option::Some(Token::Operator(Operator::Seq)) => (),
-                    Option::Some(Token::Catch) => (),
-                    Option::Some(Token::Else) => (),
-                    Option::Some(Token::CurlyBracketClosed) => (),
-                    Option::None => (),
-                    _ => tokens.push(Token::Operator(Operator::Seq))
-                }
-
+                insert_seq_after_block(tokens)
             },
             Token::Comma => {
                 loop {
@@ -171,7 +446,9 @@ pub fn parse_tokens(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionSc
                         Option::Some(
                             Token::SquareBracketOpen |
                             Token::FunctionCallOpen |
-                            Token::Comma
+                            Token::RoundBracketOpen |
+                            Token::Comma |
+                            Token::Colon
                         ) => break,
                         Option::Some(Token::Operator(op)) => {
                             push_operator_to_out(op, &mut out)?;
@@ -181,27 +458,53 @@ pub fn parse_tokens(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionSc
                         Option::None => return Result::Err(SyntaxError{msg: String::from("Unexpected token `,`",)})
                     }
                 }
-                stack.push(Token::Comma)
+                // A comma before the closing round bracket builds a tuple, also with a single element. Eg: (1,)
+                let in_round_brackets: bool = matches!(stack.iter().rev().find(|token| !matches!(token, Token::Comma)), Option::Some(Token::RoundBracketOpen));
+                if in_round_brackets && matches!(tokens.last(), Option::Some(Token::RoundBracketClosed)) {
+                    tokens.pop();
+                    let single: bool = matches!(stack.last(), Option::Some(Token::RoundBracketOpen));
+                    handle_round_bracket_closed_token(&mut stack, &mut out, true)?;
+                    if single {
+                        let elem: Exp = out.pop().ok_or(SyntaxError{msg: String::from("Malformed tuple")})?;
+                        out.push(Exp::Tuple(vec![elem]))
+                    }
+                } else {
+                    stack.push(Token::Comma)
+                }
             },
 
             Token::Callcc => {
-                stack.push(Token::Callcc);
-                //we need to add the continuation label to the variables environment, for the same reason as above with catch
-                let function_scope=function_stack.last_mut().unwrap();
-                match tokens.last() {
-                    Option::Some(Token::Operand(Operand::Var(name))) => {
-                        function_scope.variable_map.insert(name.clone(), function_scope.var_scope);
-                    },
-                    _ =>return Result::Err(SyntaxError{msg: String::from("Expected variable name after callcc token")})
-                };
+                let exp: Exp = parse_callcc(tokens, function_stack)?;
+                out.push(exp);
+                insert_seq_after_block(tokens)
             },
 
-            Token::In => {
-                match stack.last(){
-                    Option::Some(Token::Callcc) => stack.push(Token::In),
-                    _ => return Result::Err(SyntaxError{msg: format!("Unexpected token `in`")})
+            Token::Reset => {
+                if tokens.last() != Option::Some(&Token::CurlyBracketOpen) {
+                    return Result::Err(SyntaxError{msg: String::from("Expected `{` after reset")})
                 }
-            }
+                let end: usize = find_block_end(tokens)?;
+                let mut body_tokens: Vec<Token> = tokens.drain(end..).collect();
+                let body: Exp = parse_tokens(&mut body_tokens, function_stack)?;
+                out.push(Exp::Reset(Box::new(body)));
+                insert_seq_after_block(tokens)
+            },
+
+            Token::Shift => {
+                let name: String = match tokens.pop() {
+                    Option::Some(Token::Operand(Operand::Var(name))) => name,
+                    _ => return Result::Err(SyntaxError{msg: String::from("Expected variable name after shift token")})
+                };
+                let (k, body) = parse_continuation_block(name, tokens, function_stack)?;
+                out.push(Exp::Shift(k, Box::new(body)));
+                insert_seq_after_block(tokens)
+            },
+
+            // `in` after the pattern of a for loop or the variable of callcc is consumed by their parsers
+            Token::In => handle_operator_token(Operator::In, &mut stack, &mut out, tokens)?,
+
+            Token::FunctionDecl(_, _) => panic!("FunctionDecl token must never be produced by the lexer"),
+            Token::LetPattern(_) => panic!("LetPattern token must never be produced by the lexer")
         }
     }
 
@@ -219,6 +522,14 @@ pub fn parse_tokens(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionSc
                 function_stack.last_mut().unwrap().var_scope -= 1;
                 push_let_expr_to_out(&mut out, function_stack.last_mut().unwrap().var_scope)?
             }
+            Option::Some(Token::LetPattern(pattern)) => {
+                function_stack.last_mut().unwrap().var_scope -= pattern.num_vars();
+                push_let_pattern_expr_to_out(&mut out, pattern)?
+            }
+            Option::Some(Token::FunctionDecl(vars, functions)) => {
+                function_stack.last_mut().unwrap().var_scope -= vars.len();
+                push_function_decl_expr_to_out(&mut out, vars, functions)?
+            }
             Option::Some(Token::RoundBracketOpen) => return Result::Err(SyntaxError{msg: String::from("Unexpected bracket `(`")}),
             Option::Some(Token::FunctionCallOpen) => return Result::Err(SyntaxError{msg: String::from("Unexpected function call open `(`")}),
             Option::Some(Token::ListSelectionOpen) => return Result::Err(SyntaxError{msg: String::from("Unexpected list selection open `[`")}),
@@ -232,23 +543,847 @@ pub fn parse_tokens(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionSc
             Option::Some(Token::Try) => return Result::Err(SyntaxError{msg: String::from("Unexpected `try`")}),
             Option::Some(Token::Catch) => return Result::Err(SyntaxError{msg: String::from("Unexpected `catch`")}),
             Option::Some(Token::Comma) => return Result::Err(SyntaxError{msg: String::from("Unexpected `,`")}),
+            Option::Some(Token::MapOpen) => return Result::Err(SyntaxError{msg: String::from("Unexpected `#{`")}),
+            Option::Some(Token::Colon) => return Result::Err(SyntaxError{msg: String::from("Unexpected `:`")}),
+            Option::Some(Token::MapClosed) => panic!("Found MapClosed in parser operator stack"),
             Option::Some(Token::RoundBracketClosed) => panic!("Found RoundBracketClosed in parser operator stack"),
             Option::Some(Token::SquareBracketClosed) => panic!("Found SquareBracketClosed in parser operator stack"),
             Option::Some(Token::Operand(_)) => panic!("Found Operand in parser operator stack"),
             Option::Some(Token::Callcc) => panic!("Found Callcc in parser operator stack"),
-            Option::Some(Token::In) => panic!("Found 'In' token in parser operator stack")
+            Option::Some(Token::In) => panic!("Found 'In' token in parser operator stack"),
+            Option::Some(token @ (Token::Struct | Token::Dot | Token::Enum | Token::Match | Token::For | Token::Break | Token::Continue | Token::Return | Token::Import | Token::Label(_) | Token::FatArrow | Token::Reset | Token::Shift | Token::Yield | Token::Perform)) => panic!("Found {} in parser operator stack", token)
         }
     }
     
-    if out.len() != 1  {
-        Result::Err(SyntaxError{msg: String::from("Can not parse a single expression. Probabily missing a ;")})
-    } else if !stack.is_empty() {
+    if out.len() != 1 || !stack.is_empty() {
         Result::Err(SyntaxError{msg: String::from("Can not parse a single expression. Probabily missing a ;")})
     } else {
         Result::Ok(out.pop().unwrap())
     }
 }
 
+/**
+ * Resolves a variable name starting from the innermost function scope.
+ * When the variable is declared in an enclosing function, it is captured by every function
+ * in between, so that its value is available when the inner function is created.
+ */
+fn resolve_variable(name: &String, function_stack: &mut [FunctionScope]) -> Option<Exp> {
+    let (function_scope, enclosing_scopes) = function_stack.split_last_mut()?;
+    if let Option::Some(scope) = function_scope.variable_map.get(name) {
+        return Option::Some(Exp::Var(Var{name: name.clone(), scope: *scope}))
+    }
+    let position = function_scope.external_variables.iter().position(|exp| match exp {
+        Exp::Var(var) | Exp::ExternalVar(var) => var.name == *name,
+        _ => false
+    });
+    let index: usize = match position {
+        Option::Some(index) => index,
+        Option::None => {
+            let external_var: Exp = resolve_variable(name, enclosing_scopes)?;
+            function_scope.external_variables.push(external_var);
+            function_scope.external_variables.len() - 1
+        }
+    };
+    Option::Some(Exp::ExternalVar(Var{name: name.clone(), scope: index}))
+}
+
+/**
+ * After closing a curly bracket we automatically insert `;` if not present.
+ * This makes the syntax more similar to Java, C++ etc
+ */
+fn insert_seq_after_block(tokens: &mut Vec<Token>) {
+    match tokens.last() {
+        Option::Some(Token::Operator(Operator::Seq)) => (),
+        Option::Some(Token::Catch) => (),
+        Option::Some(Token::Else) => (),
+        Option::Some(Token::CurlyBracketClosed) => (),
+        // Blocks and functions can also be list elements, map values or function call arguments
+        Option::Some(Token::Comma | Token::RoundBracketClosed | Token::SquareBracketClosed | Token::MapClosed) => (),
+        Option::None => (),
+        _ => tokens.push(Token::Operator(Operator::Seq))
+    }
+}
+
+/**
+ * Called at the beginning of every block. Function declarations found in the block are parsed
+ * in advance and stored in a FunctionDecl token, which builds the FunctionDecl expression
+ * when the block is closed.
+ */
+fn handle_function_decls(
+    tokens: &mut Vec<Token>,
+    stack: &mut Vec<Token>,
+    function_stack: &mut Vec<FunctionScope>
+) -> Result<(), SyntaxError> {
+    let num_tokens: usize = tokens.len();
+    let (vars, functions) = parse_function_decls(tokens, function_stack)?;
+    // The block could contain only declarations
+    if num_tokens != tokens.len() {
+        match tokens.last() {
+            Option::Some(Token::CurlyBracketClosed) => tokens.push(Token::Operand(Operand::Null)),
+            Option::None => tokens.push(Token::Operand(Operand::Null)),
+            _ => ()
+        }
+    };
+    if vars.is_empty() { return Result::Ok(()) }
+    stack.push(Token::FunctionDecl(vars, functions));
+    Result::Ok(())
+}
+
+/**
+ * Function declarations like `fn name(args) { body }` are hoisted to the beginning of the block
+ * that contains them, so they can be called before being declared and can call each other.
+ * A function that uses a variable declared by an earlier `let` of the block is hoisted only up to
+ * that `let`: its tokens are moved right after it, where they are parsed when they are reached.
+ * This function removes the function declarations of the current block from `tokens`,
+ * declares the names of the hoisted ones in the current function scope and parses them.
+ * Struct and enum declarations like `struct Name { fields }` are hoisted and removed in the same way.
+ */
+pub fn parse_function_decls(
+    tokens: &mut Vec<Token>,
+    function_stack: &mut Vec<FunctionScope>
+) -> Result<(Vec<Var>, Vec<Exp>), SyntaxError> {
+    // Remember that tokens are popped from the end, so we scan the block backwards
+    // Function declarations with the position of the `;` of the let they are moved after, or 0 if they are hoisted.
+    // Positions are counted from the end, so draining the declarations that follow the let does not change them
+    let mut fn_decls: Vec<(usize, Vec<Token>)> = Vec::new();
+    let mut struct_decls: Vec<Vec<Token>> = Vec::new();
+    let mut enum_decls: Vec<Vec<Token>> = Vec::new();
+    // Names declared by the lets found so far, with the position of the `;` that ends each let
+    let mut lets: Vec<(Vec<String>, usize)> = Vec::new();
+    let mut let_names: Option<Vec<String>> = Option::None;
+    let mut depth: usize = 0;
+    let mut statement_start = true;
+    let mut i: usize = tokens.len();
+    while i > 0 {
+        i -= 1;
+        if depth == 0 && statement_start && let_names.is_none() {
+            let_names = declared_names(tokens, i);
+        }
+        let is_decl: bool = depth == 0 && statement_start && tokens[i] == Token::Fn && i > 0 && match tokens[i - 1] {
+            Token::Operand(Operand::Var(_)) => true,
+            _ => false
+        };
+        let is_struct_decl: bool = depth == 0 && statement_start && tokens[i] == Token::Struct;
+        let is_enum_decl: bool = depth == 0 && statement_start && tokens[i] == Token::Enum;
+        if is_decl || is_struct_decl || is_enum_decl {
+            let end: usize = find_function_decl_end(tokens, i)?;
+            if is_decl {
+                let decl: Vec<Token> = tokens.drain(end..=i).collect();
+                let uses: Vec<&String> = used_names(&decl);
+                let position: usize = match lets.iter().rev().find(|(names, _)| names.iter().any(|name| uses.contains(&name))) {
+                    Option::Some((_, position)) => *position,
+                    Option::None => 0
+                };
+                fn_decls.push((position, decl))
+            } else if is_struct_decl {
+                struct_decls.push(tokens.drain(end..=i).collect())
+            } else {
+                enum_decls.push(tokens.drain(end..=i).collect())
+            };
+            i = end;
+            // A `;` right after the declaration is removed too
+            if i > 0 && tokens[i - 1] == Token::Operator(Operator::Seq) {
+                tokens.remove(i - 1);
+                i -= 1;
+            }
+            statement_start = true;
+            continue
+        }
+        match tokens[i] {
+            Token::CurlyBracketOpen => depth += 1,
+            Token::CurlyBracketClosed => {
+                // End of the current block
+                if depth == 0 { break }
+                depth -= 1
+            },
+            Token::Operator(Operator::Seq) if depth == 0 => match let_names.take() {
+                Option::Some(names) => lets.push((names, tokens.len() - i)),
+                Option::None => ()
+            },
+            _ => ()
+        };
+        statement_start = match tokens[i] {
+            Token::Operator(Operator::Seq) | Token::CurlyBracketOpen | Token::CurlyBracketClosed => true,
+            _ => false
+        };
+    };
+    // A function that uses a moved function is moved after it too
+    let names: Vec<&String> = fn_decls.iter().map(|(_, decl)| match &decl[decl.len() - 2] {
+        Token::Operand(Operand::Var(name)) => name,
+        _ => panic!("Function declaration without name")
+    }).collect();
+    let used: Vec<Vec<&String>> = fn_decls.iter().map(|(_, decl)| used_names(decl)).collect();
+    let mut positions: Vec<usize> = fn_decls.iter().map(|(position, _)| *position).collect();
+    let mut moved: bool = true;
+    while moved {
+        moved = false;
+        for j in 0..used.len() {
+            for k in 0..used.len() {
+                if positions[k] > positions[j] && used[j].contains(&names[k]) {
+                    positions[j] = positions[k];
+                    moved = true
+                }
+            }
+        }
+    };
+    let mut decls: Vec<Vec<Token>> = Vec::new();
+    let mut moved_decls: Vec<(usize, Vec<Token>)> = Vec::new();
+    for (position, (_, decl)) in positions.into_iter().zip(fn_decls) {
+        if position == 0 { decls.push(decl) } else { moved_decls.push((position, decl)) }
+    };
+    // Inserting after the last let first does not change the positions of the earlier ones
+    moved_decls.sort_by(|(a, _), (b, _)| b.cmp(a));
+    for (position, decl) in moved_decls {
+        let i: usize = tokens.len() - position;
+        tokens.splice(i..i, decl);
+    };
+
+    // Declare all the names before parsing the function bodies
+    let mut vars: Vec<Var> = Vec::with_capacity(decls.len());
+    let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
+    for decl in struct_decls {
+        let (name, fields) = parse_struct_decl(decl)?;
+        function_scope.struct_types.insert(name, fields);
+    };
+    for decl in enum_decls {
+        let (name, variants) = parse_enum_decl(decl)?;
+        for (variant, num_values) in variants {
+            function_scope.enum_variants.insert(variant, (name.clone(), num_values));
+        }
+    };
+    for decl in decls.iter_mut() {
+        decl.pop();
+        let name: String = match decl.pop() {
+            Option::Some(Token::Operand(Operand::Var(name))) => name,
+            _ => return Result::Err(SyntaxError{msg: String::from("Expected function name after fn")})
+        };
+        if vars.iter().any(|var| var.name == name) {
+            return Result::Err(SyntaxError{msg: format!("Function {} is declared more than once in the same block", name)})
+        }
+        function_scope.variable_map.insert(name.clone(), function_scope.var_scope);
+        vars.push(Var{name, scope: function_scope.var_scope});
+        function_scope.var_scope += 1;
+        // What remains is parsed like an anonymous function: `fn (args) { body }`
+        match decl.pop() {
+            Option::Some(Token::FunctionCallOpen) => decl.push(Token::RoundBracketOpen),
+            _ => return Result::Err(SyntaxError{msg: String::from("Expected `(` after function name")})
+        };
+        decl.push(Token::Fn);
+    };
+    let mut functions: Vec<Exp> = Vec::with_capacity(decls.len());
+    for mut decl in decls {
+        functions.push(parse_tokens(&mut decl, function_stack)?);
+    };
+    Result::Ok((vars, functions))
+}
+
+/**
+ * Returns the names declared by the statement that starts at position `i`,
+ * if it is a let or an `import "path" as name`
+ */
+fn declared_names(tokens: &[Token], i: usize) -> Option<Vec<String>> {
+    match tokens[i] {
+        Token::Let => (),
+        Token::Import if i >= 3 => {
+            return match (&tokens[i - 2], &tokens[i - 3]) {
+                (Token::Operand(Operand::Var(keyword)), Token::Operand(Operand::Var(name))) if keyword == "as" => Option::Some(vec![name.clone()]),
+                _ => Option::None
+            }
+        },
+        _ => return Option::None
+    };
+    // The names of a let come before its `=`
+    let mut names: Vec<String> = Vec::new();
+    for token in tokens[..i].iter().rev() {
+        match token {
+            Token::Operand(Operand::Var(name)) => names.push(name.clone()),
+            Token::Operator(Operator::Assign | Operator::Seq) => break,
+            _ => ()
+        }
+    };
+    Option::Some(names)
+}
+
+/**
+ * Returns the variable names that a function declaration uses from the enclosing scopes.
+ * The name of the function, its parameters and the variables declared by `let`, `for` and `catch`
+ * in the body are left out, like field names after `.`
+ */
+fn used_names(decl: &[Token]) -> Vec<&String> {
+    let mut names: Vec<&String> = Vec::new();
+    let mut declared: Vec<&String> = Vec::new();
+    // The name and the parameters are declared until the body begins
+    let mut declaring: bool = true;
+    // Remember that the tokens are reversed, so the previous token is the next one in the vector
+    for (i, token) in decl.iter().enumerate().rev() {
+        match token {
+            Token::Let | Token::For | Token::Catch => declaring = true,
+            Token::Operator(Operator::Assign | Operator::Seq | Operator::In) | Token::In | Token::CurlyBracketOpen => declaring = false,
+            Token::Operand(Operand::Var(name)) => match decl.get(i + 1) {
+                Option::Some(Token::Dot) => (),
+                _ if declaring => declared.push(name),
+                _ => names.push(name)
+            },
+            _ => ()
+        }
+    };
+    names.retain(|name| !declared.contains(name));
+    names
+}
+
+/**
+ * Parses the tokens of `struct Name { field1, ... , fieldN }`, returning the struct name and its field names
+ */
+fn parse_struct_decl(mut decl: Vec<Token>) -> Result<(String, Vec<String>), SyntaxError> {
+    decl.pop();
+    let name: String = match decl.pop() {
+        Option::Some(Token::Operand(Operand::Var(name))) => name,
+        _ => return Result::Err(SyntaxError{msg: String::from("Expected struct name after struct")})
+    };
+    match decl.pop() {
+        Option::Some(Token::CurlyBracketOpen) => (),
+        _ => return Result::Err(SyntaxError{msg: String::from("Expected `{` after struct name")})
+    };
+    let mut fields: Vec<String> = Vec::new();
+    loop {
+        match decl.pop() {
+            Option::Some(Token::CurlyBracketClosed) => break,
+            Option::Some(Token::Operand(Operand::Var(field))) => {
+                if fields.contains(&field) {
+                    return Result::Err(SyntaxError{msg: format!("Field {} is declared more than once in struct {}", field, name)})
+                }
+                fields.push(field);
+                match decl.pop() {
+                    Option::Some(Token::CurlyBracketClosed) => break,
+                    Option::Some(Token::Comma) => (),
+                    _ => return Result::Err(SyntaxError{msg: String::from("Expected `,` after struct field")})
+                }
+            },
+            _ => return Result::Err(SyntaxError{msg: format!("Malformed declaration of struct {}", name)})
+        }
+    };
+    Result::Ok((name, fields))
+}
+
+/**
+ * Parses the tokens of `enum Name { Variant1(value1, ...), ... , VariantN }`,
+ * returning the enum name and the number of values of each variant
+ */
+fn parse_enum_decl(mut decl: Vec<Token>) -> Result<(String, Vec<(String, usize)>), SyntaxError> {
+    decl.pop();
+    let name: String = match decl.pop() {
+        Option::Some(Token::Operand(Operand::Var(name))) => name,
+        _ => return Result::Err(SyntaxError{msg: String::from("Expected enum name after enum")})
+    };
+    match decl.pop() {
+        Option::Some(Token::CurlyBracketOpen) => (),
+        _ => return Result::Err(SyntaxError{msg: String::from("Expected `{` after enum name")})
+    };
+    let mut variants: Vec<(String, usize)> = Vec::new();
+    loop {
+        let variant: String = match decl.pop() {
+            Option::Some(Token::CurlyBracketClosed) => break,
+            Option::Some(Token::Operand(Operand::Var(variant))) => variant,
+            _ => return Result::Err(SyntaxError{msg: format!("Malformed declaration of enum {}", name)})
+        };
+        if variants.iter().any(|(v, _)| *v == variant) {
+            return Result::Err(SyntaxError{msg: format!("Variant {} is declared more than once in enum {}", variant, name)})
+        }
+        // The names of the values are only used as documentation
+        let mut num_values: usize = 0;
+        if decl.last() == Option::Some(&Token::FunctionCallOpen) {
+            decl.pop();
+            loop {
+                match decl.pop() {
+                    Option::Some(Token::RoundBracketClosed) => break,
+                    Option::Some(Token::Operand(Operand::Var(_))) => num_values += 1,
+                    Option::Some(Token::Comma) => (),
+                    _ => return Result::Err(SyntaxError{msg: format!("Malformed variant {} of enum {}", variant, name)})
+                }
+            }
+        }
+        variants.push((variant, num_values));
+        match decl.pop() {
+            Option::Some(Token::CurlyBracketClosed) => break,
+            Option::Some(Token::Comma) => (),
+            _ => return Result::Err(SyntaxError{msg: String::from("Expected `,` after enum variant")})
+        }
+    };
+    Result::Ok((name, variants))
+}
+
+/**
+ * Returns the enum name and the number of values of a variant, looking from the innermost function scope
+ */
+fn resolve_variant(name: &String, function_stack: &[FunctionScope]) -> Option<(String, usize)> {
+    function_stack.iter().rev().find_map(|function_scope| function_scope.enum_variants.get(name).cloned())
+}
+
+/**
+ * Parses an enum variant used as a value. Variants with values are used like function calls
+ */
+fn parse_enum_instance(
+    name: String,
+    variant: &String,
+    num_values: usize,
+    tokens: &mut Vec<Token>,
+    function_stack: &mut Vec<FunctionScope>
+) -> Result<Exp, SyntaxError> {
+    if num_values == 0 {
+        return Result::Ok(Exp::EnumInstance(name, variant.clone(), Vec::new()))
+    }
+    match tokens.pop() {
+        Option::Some(Token::FunctionCallOpen) => (),
+        _ => return Result::Err(SyntaxError{msg: format!("Variant {} expects {} values", variant, num_values)})
+    };
+    let values: Vec<Exp> = parse_args(tokens, function_stack)?;
+    if values.len() != num_values {
+        return Result::Err(SyntaxError{msg: format!("Variant {} expects {} values, found {}", variant, num_values, values.len())})
+    }
+    Result::Ok(Exp::EnumInstance(name, variant.clone(), values))
+}
+
+/**
+ * Parses the expressions separated by `,` after a `(`, until the matching `)`, which is removed
+ */
+fn parse_args(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionScope>) -> Result<Vec<Exp>, SyntaxError> {
+    let mut args: Vec<Exp> = Vec::new();
+    if tokens.last() == Option::Some(&Token::RoundBracketClosed) {
+        tokens.pop();
+        return Result::Ok(args)
+    }
+    loop {
+        let mut arg_tokens: Vec<Token> = drain_expression(tokens, &Token::RoundBracketClosed)?;
+        args.push(parse_tokens(&mut arg_tokens, function_stack)?);
+        match tokens.pop() {
+            Option::Some(Token::RoundBracketClosed) => break,
+            _ => ()
+        }
+    };
+    Result::Ok(args)
+}
+
+/**
+ * Parses `{ body } with { Effect1(patterns1), k1 => body1, ... }` after `handle`. The variables of the
+ * patterns and the continuation are in scope only in the body of their handler, like in a match arm
+ */
+fn parse_handle(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionScope>) -> Result<Exp, SyntaxError> {
+    let end: usize = find_block_end(tokens)?;
+    let mut body_tokens: Vec<Token> = tokens.drain(end..).collect();
+    let body: Exp = parse_tokens(&mut body_tokens, function_stack)?;
+    match tokens.pop() {
+        // Like `as` in imports, `with` is not a keyword
+        Option::Some(Token::Operand(Operand::Var(keyword))) if keyword == "with" => (),
+        _ => return Result::Err(SyntaxError{msg: String::from("Expected `with` after handle block")})
+    };
+    match tokens.pop() {
+        Option::Some(Token::CurlyBracketOpen) => (),
+        _ => return Result::Err(SyntaxError{msg: String::from("Expected `{` after with")})
+    };
+    let mut handlers: Vec<Handler> = Vec::new();
+    loop {
+        if tokens.last() == Option::Some(&Token::CurlyBracketClosed) {
+            tokens.pop();
+            break
+        }
+        let effect: String = match tokens.pop() {
+            Option::Some(Token::Operand(Operand::Var(effect))) => effect,
+            _ => return Result::Err(SyntaxError{msg: String::from("Expected effect name in handler")})
+        };
+        let patterns: Vec<Pattern> = match tokens.last() {
+            Option::Some(Token::FunctionCallOpen) => {
+                tokens.pop();
+                parse_match_patterns(tokens, &Token::RoundBracketClosed, function_stack)?
+            },
+            _ => Vec::new()
+        };
+        let k: String = match (tokens.pop(), tokens.pop()) {
+            (Option::Some(Token::Comma), Option::Some(Token::Operand(Operand::Var(k)))) => k,
+            _ => return Result::Err(SyntaxError{msg: format!("Expected `,` and the continuation after effect {}", effect)})
+        };
+        match tokens.pop() {
+            Option::Some(Token::FatArrow) => (),
+            _ => return Result::Err(SyntaxError{msg: String::from("Expected `=>` after the continuation of a handler")})
+        };
+        let mut pattern: Pattern = Pattern::Tuple(patterns);
+        let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
+        // The variables of the pattern and the continuation can shadow other variables only inside the handler
+        let variable_map: HashMap<String, usize> = function_scope.variable_map.clone();
+        declare_pattern(&mut pattern, function_scope);
+        let k: Var = Var{name: k, scope: function_scope.var_scope};
+        declare_pattern(&mut Pattern::Var(k.clone()), function_scope);
+        let mut handler_tokens: Vec<Token> = match tokens.last() {
+            Option::Some(Token::CurlyBracketOpen) => {
+                let end: usize = find_block_end(tokens)?;
+                tokens.drain(end..).collect()
+            },
+            _ => drain_expression(tokens, &Token::CurlyBracketClosed)?
+        };
+        let handler: Exp = parse_tokens(&mut handler_tokens, function_stack)?;
+        let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
+        function_scope.var_scope -= pattern.num_vars() + 1;
+        function_scope.variable_map = variable_map;
+        handlers.push((effect, pattern, k, handler));
+        match tokens.last() {
+            Option::Some(Token::Comma) => { tokens.pop(); },
+            _ => ()
+        }
+    };
+    Result::Ok(Exp::Handle(Box::new(body), handlers))
+}
+
+/**
+ * Parses `match exp { pattern1 if guard1 => body1, ... , patternN => bodyN }` after the match token.
+ * The variables of each pattern are in scope only in the guard and in the body of their arm
+ */
+fn parse_match(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionScope>) -> Result<Exp, SyntaxError> {
+    let mut exp_tokens: Vec<Token> = drain_expression(tokens, &Token::CurlyBracketOpen)?;
+    let exp: Exp = parse_tokens(&mut exp_tokens, function_stack)?;
+    match tokens.pop() {
+        Option::Some(Token::CurlyBracketOpen) => (),
+        _ => return Result::Err(SyntaxError{msg: String::from("Expected `{` after match expression")})
+    };
+    let mut arms: Vec<(Pattern, Option<Exp>, Exp)> = Vec::new();
+    loop {
+        if tokens.last() == Option::Some(&Token::CurlyBracketClosed) {
+            tokens.pop();
+            break
+        }
+        let mut pattern: Pattern = parse_match_pattern(tokens, function_stack)?;
+        let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
+        // The variables of the pattern can shadow other variables only inside the arm
+        let variable_map: HashMap<String, usize> = function_scope.variable_map.clone();
+        declare_pattern(&mut pattern, function_scope);
+        let guard: Option<Exp> = match tokens.last() {
+            Option::Some(Token::If) => {
+                tokens.pop();
+                let mut guard_tokens: Vec<Token> = drain_expression(tokens, &Token::FatArrow)?;
+                Option::Some(parse_tokens(&mut guard_tokens, function_stack)?)
+            },
+            _ => Option::None
+        };
+        match tokens.pop() {
+            Option::Some(Token::FatArrow) => (),
+            _ => return Result::Err(SyntaxError{msg: String::from("Expected `=>` after match pattern")})
+        };
+        // Like in Rust, the `,` after a block body is optional
+        let mut body_tokens: Vec<Token> = match tokens.last() {
+            Option::Some(Token::CurlyBracketOpen) => {
+                let end: usize = find_block_end(tokens)?;
+                tokens.drain(end..).collect()
+            },
+            _ => drain_expression(tokens, &Token::CurlyBracketClosed)?
+        };
+        let body: Exp = parse_tokens(&mut body_tokens, function_stack)?;
+        let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
+        function_scope.var_scope -= pattern.num_vars();
+        function_scope.variable_map = variable_map;
+        arms.push((pattern, guard, body));
+        match tokens.last() {
+            Option::Some(Token::Comma) => { tokens.pop(); },
+            _ => ()
+        }
+    };
+    Result::Ok(Exp::Match(Box::new(exp), arms))
+}
+
+/**
+ * Parses `pattern in exp { body }` after the `for` keyword.
+ * The iterated expression is parsed before the variables of the pattern are declared, because it is
+ * evaluated only once before the loop starts
+ */
+fn parse_for(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionScope>, label: Option<String>) -> Result<Exp, SyntaxError> {
+    let mut pattern: Pattern = parse_pattern(tokens)?;
+    match tokens.pop() {
+        Option::Some(Token::In) => (),
+        _ => return Result::Err(SyntaxError{msg: String::from("Expected `in` after for pattern")})
+    };
+    let mut exp_tokens: Vec<Token> = drain_expression(tokens, &Token::CurlyBracketOpen)?;
+    let exp: Exp = parse_tokens(&mut exp_tokens, function_stack)?;
+    if tokens.last() != Option::Some(&Token::CurlyBracketOpen) {
+        return Result::Err(SyntaxError{msg: String::from("Expected `{` after for expression")})
+    }
+    let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
+    // The variables of the pattern can shadow other variables only inside the loop
+    let variable_map: HashMap<String, usize> = function_scope.variable_map.clone();
+    declare_pattern(&mut pattern, function_scope);
+    function_scope.loop_labels.push(label.clone());
+    let end: usize = find_block_end(tokens)?;
+    let mut body_tokens: Vec<Token> = tokens.drain(end..).collect();
+    let body: Exp = parse_tokens(&mut body_tokens, function_stack)?;
+    let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
+    function_scope.loop_labels.pop();
+    function_scope.var_scope -= pattern.num_vars();
+    function_scope.variable_map = variable_map;
+    Result::Ok(Exp::For(pattern, Box::new(exp), Box::new(body), label))
+}
+
+/**
+ * Parses `callcc k in { body }`. The continuation is bound to `k` in the body, like the pattern of a for loop
+ */
+fn parse_callcc(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionScope>) -> Result<Exp, SyntaxError> {
+    let name: String = match tokens.pop() {
+        Option::Some(Token::Operand(Operand::Var(name))) => name,
+        _ => return Result::Err(SyntaxError{msg: String::from("Expected variable name after callcc token")})
+    };
+    match tokens.pop() {
+        Option::Some(Token::In) => (),
+        _ => return Result::Err(SyntaxError{msg: String::from("Expected `in` after callcc variable")})
+    };
+    let (k, body) = parse_continuation_block(name, tokens, function_stack)?;
+    Result::Ok(Exp::Callcc(k, Box::new(body)))
+}
+
+/**
+ * Parses the block of `callcc` or `shift`, where the variable holds the captured continuation
+ */
+fn parse_continuation_block(name: String, tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionScope>) -> Result<(Var, Exp), SyntaxError> {
+    if tokens.last() != Option::Some(&Token::CurlyBracketOpen) {
+        return Result::Err(SyntaxError{msg: format!("Expected `{{` after {}", name)})
+    }
+    let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
+    let variable_map: HashMap<String, usize> = function_scope.variable_map.clone();
+    let k: Var = Var{name, scope: function_scope.var_scope};
+    declare_pattern(&mut Pattern::Var(k.clone()), function_scope);
+    let end: usize = find_block_end(tokens)?;
+    let mut body_tokens: Vec<Token> = tokens.drain(end..).collect();
+    let body: Exp = parse_tokens(&mut body_tokens, function_stack)?;
+    let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
+    function_scope.var_scope -= 1;
+    function_scope.variable_map = variable_map;
+    Result::Ok((k, body))
+}
+
+/**
+ * Parses the value after `break` or `return`, which ends at the end of the statement.
+ * If the value is missing, the statement evaluates to null
+ */
+fn parse_optional_value(tokens: &mut Vec<Token>, function_stack: &mut Vec<FunctionScope>) -> Result<Exp, SyntaxError> {
+    match tokens.last() {
+        Option::None | Option::Some(
+            Token::Operator(Operator::Seq) | Token::CurlyBracketClosed | Token::RoundBracketClosed |
+            Token::SquareBracketClosed | Token::MapClosed | Token::Comma
+        ) => Result::Ok(Exp::Const(Const::None)),
+        _ => {
+            let mut value_tokens: Vec<Token> = drain_expression(tokens, &Token::Operator(Operator::Seq))?;
+            parse_tokens(&mut value_tokens, function_stack)
+        }
+    }
+}
+
+/**
+ * Parses the optional label after `break` or `continue`.
+ * Loops do not extend into function bodies, so the label must belong to a loop of the current function
+ */
+fn parse_loop_target(keyword: &Token, tokens: &mut Vec<Token>, function_stack: &[FunctionScope]) -> Result<Option<String>, SyntaxError> {
+    let loop_labels: &Vec<Option<String>> = &function_stack.last().unwrap().loop_labels;
+    match tokens.last() {
+        Option::Some(Token::Label(label)) => {
+            let label: String = label.clone();
+            tokens.pop();
+            if !loop_labels.contains(&Option::Some(label.clone())) {
+                return Result::Err(SyntaxError{msg: format!("Unknown loop label '{}", label)})
+            }
+            Result::Ok(Option::Some(label))
+        },
+        _ if loop_labels.is_empty() => Result::Err(SyntaxError{msg: format!("`{}` outside of a loop", keyword)}),
+        _ => Result::Ok(Option::None)
+    }
+}
+
+/**
+ * Returns the index of the `}` closing the block that starts with the last token
+ */
+fn find_block_end(tokens: &[Token]) -> Result<usize, SyntaxError> {
+    let mut depth: usize = 0;
+    let mut i: usize = tokens.len();
+    while i > 0 {
+        i -= 1;
+        match tokens[i] {
+            Token::CurlyBracketOpen => depth += 1,
+            Token::CurlyBracketClosed => {
+                depth -= 1;
+                if depth == 0 { return Result::Ok(i) }
+            },
+            _ => ()
+        }
+    };
+    Result::Err(SyntaxError{msg: String::from("Curly brackets mismatch")})
+}
+
+/**
+ * Parses the pattern of a match arm. Names of enum variants are constructor patterns, `_` is the wildcard
+ */
+fn parse_match_pattern(tokens: &mut Vec<Token>, function_stack: &[FunctionScope]) -> Result<Pattern, SyntaxError> {
+    match tokens.pop() {
+        Option::Some(Token::Operand(Operand::Var(name))) if name == "_" => Result::Ok(Pattern::Wildcard),
+        Option::Some(Token::Operand(Operand::Var(name))) => match resolve_variant(&name, function_stack) {
+            Option::Some((enum_name, num_values)) => {
+                let mut patterns: Vec<Pattern> = Vec::with_capacity(num_values);
+                if tokens.last() == Option::Some(&Token::FunctionCallOpen) {
+                    tokens.pop();
+                    patterns = parse_match_patterns(tokens, &Token::RoundBracketClosed, function_stack)?;
+                }
+                if patterns.len() != num_values {
+                    return Result::Err(SyntaxError{msg: format!("Variant {} expects {} values, found {}", name, num_values, patterns.len())})
+                }
+                Result::Ok(Pattern::Constructor(enum_name, name, patterns))
+            },
+            Option::None => Result::Ok(Pattern::Var(Var{name, scope: 0}))
+        },
+        Option::Some(Token::Operand(operand)) => match operand.to_exp() {
+            Exp::Const(c) => Result::Ok(Pattern::Literal(c)),
+            _ => Result::Err(SyntaxError{msg: String::from("Unexpected operand in pattern")})
+        },
+        Option::Some(Token::RoundBracketOpen) => {
+            let mut patterns: Vec<Pattern> = Vec::new();
+            let mut comma: bool = false;
+            while !matches!(tokens.last(), Option::Some(Token::RoundBracketClosed)) {
+                patterns.push(parse_match_pattern(tokens, function_stack)?);
+                match tokens.last() {
+                    Option::Some(Token::Comma) => {
+                        tokens.pop();
+                        comma = true
+                    },
+                    Option::Some(Token::RoundBracketClosed) => (),
+                    _ => return Result::Err(SyntaxError{msg: String::from("Expected `,` or `)` in pattern")})
+                }
+            }
+            tokens.pop();
+            // Brackets around a single pattern are only used for grouping, unless it is followed by a comma. Eg: (x,)
+            if patterns.len() == 1 && !comma {
+                return Result::Ok(patterns.pop().unwrap())
+            }
+            Result::Ok(Pattern::Tuple(patterns))
+        },
+        Option::Some(Token::SquareBracketOpen) => Result::Ok(Pattern::List(parse_match_patterns(tokens, &Token::SquareBracketClosed, function_stack)?)),
+        _ => Result::Err(SyntaxError{msg: String::from("Malformed match pattern")})
+    }
+}
+
+/**
+ * Parses patterns separated by `,` until the `end` token, which is removed
+ */
+fn parse_match_patterns(tokens: &mut Vec<Token>, end: &Token, function_stack: &[FunctionScope]) -> Result<Vec<Pattern>, SyntaxError> {
+    let mut patterns: Vec<Pattern> = Vec::new();
+    if tokens.last() == Option::Some(end) {
+        tokens.pop();
+        return Result::Ok(patterns)
+    }
+    loop {
+        patterns.push(parse_match_pattern(tokens, function_stack)?);
+        match tokens.pop() {
+            Option::Some(Token::Comma) => (),
+            Option::Some(token) if token == *end => break,
+            _ => return Result::Err(SyntaxError{msg: format!("Expected `,` or `{}` in pattern", end)})
+        }
+    };
+    Result::Ok(patterns)
+}
+
+/**
+ * Returns the field names of the struct type with the given name, looking from the innermost function scope
+ */
+fn resolve_struct(name: &String, function_stack: &[FunctionScope]) -> Option<Vec<String>> {
+    function_stack.iter().rev().find_map(|function_scope| function_scope.struct_types.get(name).cloned())
+}
+
+/**
+ * Parses `{ field1: exp1, ... , fieldN: expN }` after a struct name.
+ * Every field of the struct must be initialized exactly once. Fields are stored in declaration order
+ */
+fn parse_struct_instance(
+    name: &String,
+    fields: &[String],
+    tokens: &mut Vec<Token>,
+    function_stack: &mut Vec<FunctionScope>
+) -> Result<Exp, SyntaxError> {
+    tokens.pop();
+    let mut values: Vec<Option<Exp>> = vec![Option::None; fields.len()];
+    loop {
+        let field: String = match tokens.pop() {
+            Option::Some(Token::CurlyBracketClosed) => break,
+            Option::Some(Token::Operand(Operand::Var(field))) => field,
+            _ => return Result::Err(SyntaxError{msg: format!("Expected field name in instance of struct {}", name)})
+        };
+        match tokens.pop() {
+            Option::Some(Token::Colon) => (),
+            _ => return Result::Err(SyntaxError{msg: format!("Expected `:` after field {}", field)})
+        };
+        let mut value_tokens: Vec<Token> = drain_expression(tokens, &Token::CurlyBracketClosed)?;
+        if tokens.last() == Option::Some(&Token::Comma) {
+            tokens.pop();
+        }
+        let index: usize = fields.iter().position(|f| *f == field)
+            .ok_or(SyntaxError{msg: format!("Struct {} has no field {}", name, field)})?;
+        if values[index].is_some() {
+            return Result::Err(SyntaxError{msg: format!("Field {} is initialized more than once", field)})
+        }
+        values[index] = Option::Some(parse_tokens(&mut value_tokens, function_stack)?);
+    };
+    let mut field_values: Vec<(String, Exp)> = Vec::with_capacity(fields.len());
+    for (field, value) in fields.iter().zip(values) {
+        match value {
+            Option::Some(exp) => field_values.push((field.clone(), exp)),
+            Option::None => return Result::Err(SyntaxError{msg: format!("Missing field {} in instance of struct {}", field, name)})
+        }
+    };
+    Result::Ok(Exp::StructInstance(name.clone(), field_values))
+}
+
+/**
+ * Removes from `tokens` the expression that ends before the first `,` or `end` token which is not nested
+ * in brackets, and returns its tokens. The token after the expression is left in `tokens`
+ */
+fn drain_expression(tokens: &mut Vec<Token>, end: &Token) -> Result<Vec<Token>, SyntaxError> {
+    let mut depth: usize = 0;
+    let mut i: usize = tokens.len();
+    while i > 0 {
+        let token: &Token = &tokens[i - 1];
+        if depth == 0 && (token == end || *token == Token::Comma) { break }
+        match token {
+            Token::CurlyBracketOpen | Token::MapOpen | Token::RoundBracketOpen | Token::FunctionCallOpen |
+            Token::SquareBracketOpen | Token::ListSelectionOpen => depth += 1,
+            Token::CurlyBracketClosed | Token::MapClosed | Token::RoundBracketClosed | Token::SquareBracketClosed => {
+                // Bracket opened before the expression
+                if depth == 0 { break }
+                depth -= 1
+            },
+            _ => ()
+        };
+        i -= 1
+    };
+    if i == 0 {
+        return Result::Err(SyntaxError{msg: format!("Expected `{}`", end)})
+    }
+    let expression: Vec<Token> = tokens.drain(i..).collect();
+    if expression.is_empty() {
+        return Result::Err(SyntaxError{msg: format!("Expected expression before `{}`", tokens.last().unwrap())})
+    }
+    Result::Ok(expression)
+}
+
+/**
+ * Returns the index of the `}` closing the body of the function declared at index `fn_index`
+ */
+fn find_function_decl_end(tokens: &[Token], fn_index: usize) -> Result<usize, SyntaxError> {
+    let mut depth: usize = 0;
+    let mut i: usize = fn_index;
+    while i > 0 {
+        i -= 1;
+        match tokens[i] {
+            Token::CurlyBracketOpen => depth += 1,
+            Token::CurlyBracketClosed => {
+                if depth == 0 { break }
+                depth -= 1;
+                if depth == 0 { return Result::Ok(i) }
+            },
+            _ => ()
+        }
+    };
+    Result::Err(SyntaxError{msg: String::from("Missing function declaration body")})
+}
+
 fn handle_let_token(
     tokens: &mut Vec<Token>,
     stack: &mut Vec<Token>,
@@ -259,6 +1394,17 @@ fn handle_let_token(
         Option::Some(Token::Operand(Operand::Var(name))) => {
             function_scope.variable_map.insert(name.clone(), function_scope.var_scope);
         },
+        // Destructuring let. The pattern and `=` are consumed here, so the value expression follows
+        Option::Some(Token::RoundBracketOpen) => {
+            let mut pattern: Pattern = parse_pattern(tokens)?;
+            match tokens.pop() {
+                Option::Some(Token::Operator(Operator::Assign)) => (),
+                _ => return Result::Err(SyntaxError{msg: String::from("Expected `=` after let pattern")})
+            };
+            declare_pattern(&mut pattern, function_scope);
+            stack.push(Token::LetPattern(pattern));
+            return Result::Ok(())
+        },
         _ =>return Result::Err(SyntaxError{msg: String::from("Expected variable name after let")})
     };
 
@@ -283,11 +1429,15 @@ fn handle_square_bracket_closed_token(stack: &mut Vec<Token>, out: &mut Vec<Exp>
             }
             Option::Some(Token::Operator(op)) => push_operator_to_out(&op, out)?,
             Option::Some(Token::Comma) => len += 1,
-            Option::Some(Token::Let) => return Result::Err(SyntaxError{msg: String::from("Unexpected let statement in round brackets")}),
+            Option::Some(Token::Let | Token::LetPattern(_)) => return Result::Err(SyntaxError{msg: String::from("Unexpected let statement in round brackets")}),
             Option::Some(Token::Fn) => return Result::Err(SyntaxError{msg: String::from("Unexpected `fn` token in round brackets")}),
+            Option::Some(Token::FunctionDecl(_, _)) => return Result::Err(SyntaxError{msg: String::from("Unexpected function declaration in square brackets")}),
             Option::Some(Token::FunctionCallOpen) => return Result::Err(SyntaxError{msg: String::from("Round brackets mismatch")}),
             Option::Some(Token::RoundBracketOpen) => return Result::Err(SyntaxError{msg: String::from("Round brackets mismatch")}),
             Option::Some(Token::CurlyBracketOpen) => return Result::Err(SyntaxError{msg: String::from("Curly brackets mismatch")}),
+            Option::Some(Token::MapOpen) => return Result::Err(SyntaxError{msg: String::from("Curly brackets mismatch")}),
+            Option::Some(Token::Colon) => return Result::Err(SyntaxError{msg: String::from("Unexpected `:` in square brackets")}),
+            Option::Some(Token::MapClosed) => panic!("Found MapClosed in parser operator stack"),
             Option::None => return Result::Err(SyntaxError{msg: String::from("Mismatched round brackets")}),
             Option::Some(Token::RoundBracketClosed) => panic!("Found RoundBracketClosed in parser operator stack"),
             Option::Some(Token::CurlyBracketClosed) => panic!("Found CurlyBracketClosed in parser operator stack"),
@@ -299,7 +1449,8 @@ fn handle_square_bracket_closed_token(stack: &mut Vec<Token>, out: &mut Vec<Exp>
             Option::Some(Token::Try) => panic!("Found try in parser operator stack"),
             Option::Some(Token::Catch) => panic!("Found catch in parser operator stack"),
             Option::Some(Token::Callcc) => panic!("Found callcc in parser operator stack"),
-            Option::Some(Token::In) => panic!("Found in in parser operator stack")
+            Option::Some(Token::In) => panic!("Found in in parser operator stack"),
+            Option::Some(token @ (Token::Struct | Token::Dot | Token::Enum | Token::Match | Token::For | Token::Break | Token::Continue | Token::Return | Token::Import | Token::Label(_) | Token::FatArrow | Token::Reset | Token::Shift | Token::Yield | Token::Perform)) => panic!("Found {} in parser operator stack", token)
         }
     };
     if is_selection {
@@ -324,6 +1475,36 @@ fn handle_square_bracket_closed_token(stack: &mut Vec<Token>, out: &mut Vec<Exp>
     Result::Ok(())
 }
 
+/**
+ * This function is called when the closing bracket of a map literal is found.
+ * Every entry contains exactly one `:`, and entries are separated by `,`
+ */
+fn handle_map_closed_token(stack: &mut Vec<Token>, out: &mut Vec<Exp>) -> Result<(), SyntaxError> {
+    let mut num_entries: usize = 0;
+    let mut num_commas: usize = 0;
+    loop {
+        match stack.pop() {
+            Option::Some(Token::MapOpen) => break,
+            Option::Some(Token::Operator(op)) => push_operator_to_out(&op, out)?,
+            Option::Some(Token::Colon) => num_entries += 1,
+            Option::Some(Token::Comma) => num_commas += 1,
+            _ => return Result::Err(SyntaxError{msg: String::from("Curly brackets mismatch")})
+        }
+    };
+    if num_entries != num_commas + 1 {
+        return Result::Err(SyntaxError{msg: String::from("Malformed map, expected `key: value` entries separated by `,`")})
+    }
+    let mut entries: Vec<(Exp, Exp)> = Vec::with_capacity(num_entries);
+    for _ in 0..num_entries {
+        let value: Exp = out.pop().ok_or(SyntaxError{msg: String::from("Malformed map")})?;
+        let key: Exp = out.pop().ok_or(SyntaxError{msg: String::from("Malformed map")})?;
+        entries.push((key, value))
+    }
+    entries.reverse();
+    out.push(Exp::Map(entries));
+    Result::Ok(())
+}
+
 /**
  * This function is called when a CurlyBracketClosed Token is found.
  * While popping elements from the operator stack, we decrement the scope by 1 every time we find
@@ -346,7 +1527,19 @@ fn handle_curly_bracket_closed_token(stack: &mut Vec<Token>, out: &mut Vec<Exp>,
                 function_stack.last_mut().unwrap().var_scope -= 1;
                 push_let_expr_to_out(out, function_stack.last().unwrap().var_scope)?
             },
+            Option::Some(Token::LetPattern(pattern)) => {
+                function_stack.last_mut().unwrap().var_scope -= pattern.num_vars();
+                push_let_pattern_expr_to_out(out, pattern)?
+            },
+            // Function declarations are removed from the scope like let declarations
+            Option::Some(Token::FunctionDecl(vars, functions)) => {
+                function_stack.last_mut().unwrap().var_scope -= vars.len();
+                push_function_decl_expr_to_out(out, vars, functions)?
+            },
             Option::Some(Token::Comma) => return Result::Err(SyntaxError{msg: String::from("Unexpected `,`")}),
+            Option::Some(Token::Colon) => return Result::Err(SyntaxError{msg: String::from("Unexpected `:`")}),
+            Option::Some(Token::MapOpen) => return Result::Err(SyntaxError{msg: String::from("Curly brackets mismatch")}),
+            Option::Some(Token::MapClosed) => panic!("Found MapClosed in parser operator stack"),
             Option::None => return Result::Err(SyntaxError{msg: String::from("Curly brackets mismatch")}),
             Option::Some(Token::FunctionCallOpen) => return Result::Err(SyntaxError{msg: String::from("Round brackets mismatch")}),
             Option::Some(Token::RoundBracketOpen) => return Result::Err(SyntaxError{msg: String::from("Round brackets mismatch")}),
@@ -363,9 +1556,13 @@ fn handle_curly_bracket_closed_token(stack: &mut Vec<Token>, out: &mut Vec<Exp>,
             Option::Some(Token::Try) => panic!("Found try in parser operator stack"),
             Option::Some(Token::Catch) => panic!("Found catch in parser operator stack"),
             Option::Some(Token::Callcc) => panic!("Found callcc in parser operator stack"),
-            Option::Some(Token::In) => panic!("Found in in parser operator stack")
+            Option::Some(Token::In) => panic!("Found in in parser operator stack"),
+            Option::Some(token @ (Token::Struct | Token::Dot | Token::Enum | Token::Match | Token::For | Token::Break | Token::Continue | Token::Return | Token::Import | Token::Label(_) | Token::FatArrow | Token::Reset | Token::Shift | Token::Yield | Token::Perform)) => panic!("Found {} in parser operator stack", token)
         }
     };
+    // The variables declared in the block are forgotten
+    let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
+    function_scope.variable_map = function_scope.block_variable_maps.pop().unwrap();
     match stack.last() {
         // Check if this curly bracket closes a while scope
         Option::Some(Token::While) => {
@@ -373,7 +1570,8 @@ fn handle_curly_bracket_closed_token(stack: &mut Vec<Token>, out: &mut Vec<Exp>,
             if out.len() < 2 { return Result::Err(SyntaxError{msg: String::from("Malformed while")}) }
             let while_body: Exp = out.pop().unwrap();
             let guard: Exp = out.pop().unwrap();
-            out.push(Exp::While(Box::new(guard), Box::new(while_body)))
+            let label: Option<String> = function_stack.last_mut().unwrap().loop_labels.pop().flatten();
+            out.push(Exp::While(Box::new(guard), Box::new(while_body), label))
         },
         // Check if this curly bracket closes an if scope
         Option::Some(Token::If) => {
@@ -405,7 +1603,7 @@ fn handle_curly_bracket_closed_token(stack: &mut Vec<Token>, out: &mut Vec<Exp>,
         // Check if this curly bracket closes a Try scope
         Option::Some(Token::Try) => {
             stack.pop();
-            if out.len() < 1 { return Result::Err(SyntaxError{msg: String::from("Malformed Try")}) }
+            if out.is_empty() { return Result::Err(SyntaxError{msg: String::from("Malformed Try")}) }
             let mut try_block: Exp = out.pop().unwrap();
             match stack.last() {
                 Option::Some(Token::Operator(Operator::Throw)) => try_block=Exp::Throw(Box::new(try_block)),
@@ -416,14 +1614,16 @@ fn handle_curly_bracket_closed_token(stack: &mut Vec<Token>, out: &mut Vec<Exp>,
         // Check if this curly bracket closes a Catch scope
         Option::Some(Token::Catch) => {
             stack.pop();
+            let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
+            function_scope.variable_map = function_scope.block_variable_maps.pop().unwrap();
+            function_scope.var_scope -= 1;
             if out.len() < 2 { return Result::Err(SyntaxError{msg: String::from("Malformed Try-Catch")}) }
             let exc_handler: Exp = out.pop().unwrap();
             let exc_exp: Exp = out.pop().unwrap();  //This is the expression representing the excpetion label (it's a variable for the interpreter)
-            let exc: Var;                           
-            match exc_exp {
-                Exp::Var(v) => exc=v,               //In fact we cast it to a Var
+            let exc: Var = match exc_exp {
+                Exp::Var(v) => v,                   //In fact we cast it to a Var
                 _ => return Result::Err(SyntaxError{msg: String::from("Malformed Try-Catch")}) 
-            }
+            };
             match out.pop() {
                 Option::Some(Exp::Try(try_block)) => {
                     //if we have previously found a Try then we have a TryCatch expression
@@ -441,29 +1641,15 @@ fn handle_curly_bracket_closed_token(stack: &mut Vec<Token>, out: &mut Vec<Exp>,
             for arg in function.input_vars {
                 args.push(arg)
             }
-            out.push(Exp::Function(args, Box::new(body)))
-        },
-        Option::Some(Token::In) => {
-            //function_stack.last_mut().unwrap().var_scope -= 1;
-            stack.pop();
-            match stack.last() {
-                Option::Some(Token::Callcc) => {
-                    stack.pop();
-                    if out.len() < 2 { return Result::Err(SyntaxError{msg: String::from("Malformed call/cc")}) };
-                    let expr: Exp = out.pop().unwrap();
-                    let tmp: Exp = out.pop().unwrap();  //This is the expression representing the continuation label 
-                                                        //(it's a variable for the interpreter)
-                    let context: Var;
-                    match tmp {
-                        Exp::Var(v) => context = v,     //In fact we cast it to a Var
-                        _ => { return Result::Err(SyntaxError{msg: String::from("Malformed call/cc")}) }
-                    };
-                    out.push(Exp::Callcc(context.clone(), Box::new(expr)));
-                    //push_callcc_expr_to_out(out, function_stack.last().unwrap().var_scope)?
-                },
-                //If there's not a Callcc token there shouldn't be a In token 
-                _ => return Result::Err(SyntaxError{msg: String::from("Malformed call/cc")})
+            // Destructure the arguments at the beginning of the body. The first pattern is the outermost declaration
+            let mut body: Exp = body;
+            for (var, pattern) in function.param_patterns.into_iter().rev() {
+                body = Exp::Decl(pattern, Box::new(Exp::Var(var)), Box::new(body))
             }
+            if function.is_generator {
+                body = Exp::Generator(Box::new(body))
+            }
+            out.push(Exp::Function(args, function.external_variables, Box::new(body)))
         },
         _ => ()
     };
@@ -476,7 +1662,7 @@ fn handle_curly_bracket_closed_token(stack: &mut Vec<Token>, out: &mut Vec<Exp>,
  * a RoundBracketOpen. If the RoundBracketOpen position in the operator stack matches the one in the call scope,
  * then this is a function call, otherwise these are just a regular grouping brackets.
  */
-fn handle_round_bracket_closed_token(tokens: &mut Vec<Token>, stack: &mut Vec<Token>, out: &mut Vec<Exp>, args: bool) -> Result<(), SyntaxError> {
+fn handle_round_bracket_closed_token(stack: &mut Vec<Token>, out: &mut Vec<Exp>, args: bool) -> Result<(), SyntaxError> {
     let is_function_call: bool;
     // Case of functions and function calls with zero arguments is a special case covered when an open round bracket is found
     let mut num_arguments: usize = if args { 1 } else { 0 };
@@ -493,11 +1679,15 @@ fn handle_round_bracket_closed_token(tokens: &mut Vec<Token>, stack: &mut Vec<To
             },
             Option::Some(Token::Operator(op)) => push_operator_to_out(&op, out)?,
             Option::Some(Token::Comma) => num_arguments += 1,
-            Option::Some(Token::Let) => return Result::Err(SyntaxError{msg: String::from("Unexpected let statement in round brackets")}),
+            Option::Some(Token::Let | Token::LetPattern(_)) => return Result::Err(SyntaxError{msg: String::from("Unexpected let statement in round brackets")}),
             Option::Some(Token::Fn) => return Result::Err(SyntaxError{msg: String::from("Unexpected `fn` token in round brackets")}),
+            Option::Some(Token::FunctionDecl(_, _)) => return Result::Err(SyntaxError{msg: String::from("Unexpected function declaration in round brackets")}),
             Option::Some(Token::SquareBracketOpen) => return Result::Err(SyntaxError{msg: String::from("Square brackets mismatch")}),
             Option::Some(Token::ListSelectionOpen) => return Result::Err(SyntaxError{msg: String::from("Square brackets mismatch")}),
             Option::Some(Token::CurlyBracketOpen) => return Result::Err(SyntaxError{msg: String::from("Round brackets mismatch")}),
+            Option::Some(Token::MapOpen) => return Result::Err(SyntaxError{msg: String::from("Round brackets mismatch")}),
+            Option::Some(Token::Colon) => return Result::Err(SyntaxError{msg: String::from("Unexpected `:` in round brackets")}),
+            Option::Some(Token::MapClosed) => panic!("Found MapClosed in parser operator stack"),
             Option::Some(Token::RoundBracketClosed) => panic!("Found RoundBracketClosed in parser operator stack"),
             Option::Some(Token::CurlyBracketClosed) => panic!("Found CurlyBracketClosed in parser operator stack"),
             Option::Some(Token::SquareBracketClosed) => panic!("Found SquareBracketClosed in parser operator stack"),
@@ -508,7 +1698,8 @@ fn handle_round_bracket_closed_token(tokens: &mut Vec<Token>, stack: &mut Vec<To
             Option::Some(Token::Try) => panic!("Found try in parser operator stack"),
             Option::Some(Token::Catch) => panic!("Found catch in parser operator stack"),
             Option::Some(Token::Callcc) => panic!("Found callcc in parser operator stack"),
-            Option::Some(Token::In) => panic!("Found in in parser operator stack")
+            Option::Some(Token::In) => panic!("Found in in parser operator stack"),
+            Option::Some(token @ (Token::Struct | Token::Dot | Token::Enum | Token::Match | Token::For | Token::Break | Token::Continue | Token::Return | Token::Import | Token::Label(_) | Token::FatArrow | Token::Reset | Token::Shift | Token::Yield | Token::Perform)) => panic!("Found {} in parser operator stack", token)
         }
     };
     if is_function_call {
@@ -524,7 +1715,14 @@ fn handle_round_bracket_closed_token(tokens: &mut Vec<Token>, stack: &mut Vec<To
         out.push(Exp::FunctionCall(Box::new(callable_exp), args));
     }
     else if num_arguments > 1 {
-        return  Result::Err(SyntaxError{msg: String::from("Unexpected `,` inside round brackets")})
+        // Round brackets containing more than one expression build a tuple
+        let mut elements: Vec<Exp> = Vec::with_capacity(num_arguments);
+        for _ in 0..num_arguments {
+            let elem: Exp = out.pop().ok_or(SyntaxError{msg: String::from("Malformed tuple")})?;
+            elements.push(elem)
+        }
+        elements.reverse();
+        out.push(Exp::Tuple(elements));
     }
     Result::Ok(())
 }
@@ -543,15 +1741,20 @@ fn handle_operator_token(op: Operator, stack: &mut Vec<Token>, out: &mut Vec<Exp
                 Token::If |
                 Token::Else |
                 Token::Let |
+                Token::LetPattern(_) |
                 Token::Fn |
                 Token::Try |
                 Token::Catch |
                 Token::Comma |
+                Token::MapOpen |
+                Token::Colon |
                 Token::Callcc |
-                Token::In
+                Token::In |
+                Token::FunctionDecl(_, _)
             ) => break,
             Option::Some(Token::Operator(o2)) => {
-                if o2.precedence() >= op.precedence() {
+                // Operators with the same precedence are grouped from the left, unless they are right associative
+                if o2.precedence() > op.precedence() || (o2.precedence() == op.precedence() && (o2.is_right_associative() || op.is_right_associative())) {
                     break
                 } else {
                     push_operator_to_out(o2, out)?;
@@ -561,6 +1764,8 @@ fn handle_operator_token(op: Operator, stack: &mut Vec<Token>, out: &mut Vec<Exp
             Option::Some(Token::CurlyBracketClosed) => panic!("Found CurlyBracketClosed in parser operator stack"),
             Option::Some(Token::SquareBracketClosed) => panic!("Found SquareBracketClosed in parser operator stack"),
             Option::Some(Token::RoundBracketClosed) => panic!("Found RoundBracketClosed in parser operator stack"),
+            Option::Some(Token::MapClosed) => panic!("Found MapClosed in parser operator stack"),
+            Option::Some(token @ (Token::Struct | Token::Dot | Token::Enum | Token::Match | Token::For | Token::Break | Token::Continue | Token::Return | Token::Import | Token::Label(_) | Token::FatArrow | Token::Reset | Token::Shift | Token::Yield | Token::Perform)) => panic!("Found {} in parser operator stack", token),
             Option::Some(Token::Operand(_) ) => panic!("Found Operand in parser operator stack"),
         }
     }
@@ -590,17 +1795,20 @@ fn handle_operator_token(op: Operator, stack: &mut Vec<Token>, out: &mut Vec<Exp
     Result::Ok(())
 }
 
-fn parse_function_def(tokens: &mut Vec<Token>) -> Result<Vec<String>, SyntaxError> {
+fn parse_function_def(tokens: &mut Vec<Token>) -> Result<Vec<Pattern>, SyntaxError> {
     match tokens.pop() {
         Option::Some(Token::RoundBracketOpen) => (),
         _ => return Result::Err(SyntaxError{msg: String::from("")})
     };
-    let mut input_vars: Vec<String> = Vec::new();
+    let mut input_vars: Vec<Pattern> = Vec::new();
     loop {
-        match tokens.pop() {
-            Option::Some(Token::RoundBracketClosed) => break,
-            Option::Some(Token::Operand(Operand::Var(name))) => {
-                input_vars.push(name);
+        match tokens.last() {
+            Option::Some(Token::RoundBracketClosed) => {
+                tokens.pop();
+                break
+            },
+            Option::Some(Token::Operand(Operand::Var(_)) | Token::RoundBracketOpen) => {
+                input_vars.push(parse_pattern(tokens)?);
                 match tokens.pop() {
                     Option::Some(Token::RoundBracketClosed) => break,
                     Option::Some(Token::Comma) => (),
@@ -613,6 +1821,57 @@ fn parse_function_def(tokens: &mut Vec<Token>) -> Result<Vec<String>, SyntaxErro
     Result::Ok(input_vars)
 }
 
+/**
+ * Parses the pattern of a let declaration or of a function parameter. Eg: `x`, `(q, r)`, `((x, y), z)`
+ * The scopes of the variables are assigned later by `declare_pattern`
+ */
+pub fn parse_pattern(tokens: &mut Vec<Token>) -> Result<Pattern, SyntaxError> {
+    match tokens.pop() {
+        Option::Some(Token::Operand(Operand::Var(name))) => Result::Ok(Pattern::Var(Var{name, scope: 0})),
+        Option::Some(Token::RoundBracketOpen) => {
+            let mut patterns: Vec<Pattern> = Vec::new();
+            let mut comma: bool = false;
+            while !matches!(tokens.last(), Option::Some(Token::RoundBracketClosed)) {
+                patterns.push(parse_pattern(tokens)?);
+                match tokens.last() {
+                    Option::Some(Token::Comma) => {
+                        tokens.pop();
+                        comma = true
+                    },
+                    Option::Some(Token::RoundBracketClosed) => (),
+                    _ => return Result::Err(SyntaxError{msg: String::from("Expected `,` or `)` in pattern")})
+                }
+            }
+            tokens.pop();
+            // Brackets around a single pattern are only used for grouping, unless it is followed by a comma. Eg: (x,)
+            if patterns.len() == 1 && !comma {
+                return Result::Ok(patterns.pop().unwrap())
+            }
+            Result::Ok(Pattern::Tuple(patterns))
+        },
+        _ => Result::Err(SyntaxError{msg: String::from("Expected variable name or tuple pattern")})
+    }
+}
+
+/**
+ * Adds the variables of a pattern to the function scope, from left to right
+ */
+pub fn declare_pattern(pattern: &mut Pattern, function_scope: &mut FunctionScope) {
+    match pattern {
+        Pattern::Var(var) => {
+            function_scope.variable_map.insert(var.name.clone(), function_scope.var_scope);
+            var.scope = function_scope.var_scope;
+            function_scope.var_scope += 1;
+        },
+        Pattern::Wildcard | Pattern::Literal(_) => (),
+        Pattern::Tuple(patterns) | Pattern::List(patterns) | Pattern::Constructor(_, _, patterns) => {
+            for pattern in patterns {
+                declare_pattern(pattern, function_scope)
+            }
+        }
+    }
+}
+
 fn push_let_expr_to_out(out: &mut Vec<Exp>, scope: usize) -> Result<(), SyntaxError> {
     let (exp1, exp2) : (Exp, Box<Exp>) = match out.pop() {
         // If the next element in the queue is not `;` return error
@@ -628,26 +1887,40 @@ fn push_let_expr_to_out(out: &mut Vec<Exp>, scope: usize) -> Result<(), SyntaxEr
             };
             if var.scope != scope {
                 return Result::Err(SyntaxError{
-                    msg: String::from(format!("Variable {} has scope {}, but was expecting scope {}", var.name, var.scope, scope))
+                    msg: format!("Variable {} has scope {}, but was expecting scope {}", var.name, var.scope, scope)
                 })
             }
-            out.push(Exp::Decl(var, right_exp, exp2));
+            out.push(Exp::Decl(Pattern::Var(var), right_exp, exp2));
         },
         // Case when variable is declared but not assigned
         Exp::Var(var) => {
             if var.scope != scope {
                 return Result::Err(SyntaxError{
-                    msg: String::from(format!("Variable {} has scope {}, but was expecting scope {}", var.name, var.scope, scope))
+                    msg: format!("Variable {} has scope {}, but was expecting scope {}", var.name, var.scope, scope)
                 })
             }
             let none = Box::new(Exp::Const(Const::None));
-            out.push(Exp::Decl(var, none, exp2));
+            out.push(Exp::Decl(Pattern::Var(var), none, exp2));
         },
         _ => return Result::Err(SyntaxError{msg: String::from("Expecting variable or assignment after let")})
     };
     Result::Ok(())
 }
 
+fn push_let_pattern_expr_to_out(out: &mut Vec<Exp>, pattern: Pattern) -> Result<(), SyntaxError> {
+    match out.pop() {
+        Option::Some(Exp::Seq(exp1, exp2)) => out.push(Exp::Decl(pattern, exp1, exp2)),
+        _ => return Result::Err(SyntaxError { msg:  String::from("Expected ; after let")})
+    };
+    Result::Ok(())
+}
+
+fn push_function_decl_expr_to_out(out: &mut Vec<Exp>, vars: Vec<Var>, functions: Vec<Exp>) -> Result<(), SyntaxError> {
+    let exp: Exp = out.pop().ok_or(SyntaxError{msg: String::from("Missing expression after function declaration")})?;
+    out.push(Exp::FunctionDecl(vars, functions, Box::new(exp)));
+    Result::Ok(())
+}
+
 fn push_operator_to_out(op: &Operator, out: &mut Vec<Exp>) -> Result<(), SyntaxError> {
     match op {
         Operator::Seq => {
@@ -666,6 +1939,11 @@ fn push_operator_to_out(op: &Operator, out: &mut Vec<Exp>) -> Result<(), SyntaxE
             let (o2, o1) = (out.pop().unwrap(), out.pop().unwrap());
             out.push(Exp::Mul(Box::new(o1), Box::new(o2)))
         },
+        Operator::Pow => {
+            if out.len() < 2 { return Result::Err(SyntaxError{msg: format!("Unexpected operator {}", op)}) }
+            let (o2, o1) = (out.pop().unwrap(), out.pop().unwrap());
+            out.push(Exp::Pow(Box::new(o1), Box::new(o2)))
+        },
         Operator::Div => {
             if out.len() < 2 { return Result::Err(SyntaxError{msg: format!("Unexpected operator {}", op)}) }
             let (o2, o1) = (out.pop().unwrap(), out.pop().unwrap());
@@ -716,6 +1994,21 @@ fn push_operator_to_out(op: &Operator, out: &mut Vec<Exp>) -> Result<(), SyntaxE
             let (o2, o1) = (out.pop().unwrap(), out.pop().unwrap());
             out.push(Exp::Neq(Box::new(o1), Box::new(o2)))
         },
+        Operator::In => {
+            if out.len() < 2 { return Result::Err(SyntaxError{msg: format!("Unexpected operator {}", op)}) }
+            let (o2, o1) = (out.pop().unwrap(), out.pop().unwrap());
+            out.push(Exp::In(Box::new(o1), Box::new(o2)))
+        },
+        Operator::Range => {
+            if out.len() < 2 { return Result::Err(SyntaxError{msg: format!("Unexpected operator {}", op)}) }
+            let (o2, o1) = (out.pop().unwrap(), out.pop().unwrap());
+            out.push(Exp::Range(Box::new(o1), Box::new(o2), false))
+        },
+        Operator::RangeInclusive => {
+            if out.len() < 2 { return Result::Err(SyntaxError{msg: format!("Unexpected operator {}", op)}) }
+            let (o2, o1) = (out.pop().unwrap(), out.pop().unwrap());
+            out.push(Exp::Range(Box::new(o1), Box::new(o2), true))
+        },
         Operator::And => {
             if out.len() < 2 { return Result::Err(SyntaxError{msg: format!("Unexpected operator {}", op)}) }
             let (o2, o1) = (out.pop().unwrap(), out.pop().unwrap());
@@ -727,12 +2020,12 @@ fn push_operator_to_out(op: &Operator, out: &mut Vec<Exp>) -> Result<(), SyntaxE
             out.push(Exp::Or(Box::new(o1), Box::new(o2)))
         },
         Operator::Not => {
-            if out.len() < 1 { return Result::Err(SyntaxError{msg: format!("Unexpected operator {}", op)}) }
+            if out.is_empty() { return Result::Err(SyntaxError{msg: format!("Unexpected operator {}", op)}) }
             let o = out.pop().unwrap();
             out.push(Exp::Not(Box::new(o)))
         },
         Operator::Throw => {
-            if out.len() < 1 { return Result::Err(SyntaxError{msg: format!("Unexpected operator {}", op)}) }
+            if out.is_empty() { return Result::Err(SyntaxError{msg: format!("Unexpected operator {}", op)}) }
             let o = out.pop().unwrap();
             out.push(Exp::Throw(Box::new(o)))            
         },
@@ -741,9 +2034,9 @@ fn push_operator_to_out(op: &Operator, out: &mut Vec<Exp>) -> Result<(), SyntaxE
             let e = out.pop().unwrap();
             let k = out.pop().unwrap();
             match k {
-                Exp::Var(v) => out.push(Exp::Throwcc(v,Box::new(e))),
-                _ => return Result::Err(SyntaxError{msg: format!("Expected var after callcc token")})   
-            }    
+                Exp::Var(_) | Exp::ExternalVar(_) => out.push(Exp::Throwcc(Box::new(k), Box::new(e))),
+                _ => return Result::Err(SyntaxError{msg: String::from("Expected var after callcc token")})
+            }
         }
     }
     Result::Ok(())
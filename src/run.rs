@@ -1,56 +1,89 @@
-use std::collections::HashMap;
-use std::ops::Add;
-
-use rustyline::error::ReadlineError;
-use rustyline::{Editor};
-
 use crate::lexer::tokenize;
 use crate::parser::parse;
 use crate::semantics::eval;
-use crate::parser::{parse_tokens, FunctionScope};
-use crate::semantics::{eval_expression};
-use crate::value::{Value, StackValue, V};
+use crate::module::set_main_file;
 
 use crate::expression::Exp;
 use crate::expression::Const;
 use crate::expression::Var;
+use crate::expression::Pattern;
 
 use crate::token::Token;
-use crate::token::Operand;
-use crate::token::Operator;
 
 use std::fs;
-
-pub fn run_file(file_path: String) {
-    let text = fs::read_to_string(file_path)
-        .expect("Should have been able to read the file");
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::ExitCode;
+
+/**
+ * Runs a program and returns the exit code of the process, which is not zero when the program fails
+ */
+pub fn run_file(file_path: String) -> ExitCode {
+    set_main_file(Path::new(&file_path));
+    let text = match fs::read_to_string(&file_path) {
+        Result::Ok(text) => text,
+        Result::Err(err) => {
+            eprintln!("Can not read file {}: {}", file_path, err);
+            return ExitCode::FAILURE
+        }
+    };
     run_string(text)
 }
 
-pub fn run_string(text: String) {
-    let mut tokens: Vec<Token> = tokenize(text).unwrap_or_else(|err| {
-        panic!("TokenizerError")
-    });
-
-    // Parse tokens to exp
-    let exp: Exp = parse(&mut tokens).unwrap_or_else(|err| {
-        panic!("ParserError: {}", err.msg)
-    });
-
-    println!("{}", exp_to_string(&exp));
-    println!("########");
+/**
+ * Runs the program read from stdin. Eg: `cat script.epi | epilang -`
+ */
+pub fn run_stdin() -> ExitCode {
+    let mut text = String::new();
+    match io::stdin().read_to_string(&mut text) {
+        Result::Ok(_) => run_string(text),
+        Result::Err(err) => {
+            eprintln!("Can not read the program from stdin: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    // Evaluate expression
-    let val = eval(&exp).unwrap_or_else(|err| {
-        panic!("RuntimeError: {}", err.msg)
-    });
+pub fn run_string(text: String) -> ExitCode {
+    let mut tokens: Vec<Token> = match tokenize(text) {
+        Result::Ok(tokens) => tokens,
+        Result::Err(err) => {
+            eprintln!("TokenizerError: {}", err.msg);
+            return ExitCode::FAILURE
+        }
+    };
 
-    println!("Result: {}", val);
+    // Parse tokens to exp
+    let exp: Exp = match parse(&mut tokens) {
+        Result::Ok(exp) => exp,
+        Result::Err(err) => {
+            eprintln!("ParserError: {}", err.msg);
+            return ExitCode::FAILURE
+        }
+    };
+
+    // Only the program writes to stdout, so that it can be used in a pipeline
+    eprintln!("{}", exp_to_string(&exp));
+    eprintln!("########");
+
+    // Evaluate expression. Uncaught exceptions are runtime errors too
+    match eval(exp) {
+        Result::Ok(val) => {
+            eprintln!("Result: {}", val);
+            ExitCode::SUCCESS
+        },
+        Result::Err(err) => {
+            eprintln!("RuntimeError: {}", err.msg);
+            ExitCode::FAILURE
+        }
+    }
 }
 
 fn const_to_string(c: &Const) -> String {
     match c {
         Const::Integer(i) => i.to_string(),
+        Const::BigInt(i) => i.to_string(),
+        Const::Float(x) => format!("{:?}", x),
         Const::Boolean(b) => b.to_string(),
         Const::String(s) => format!("\"{}\"", s),
         Const::None => String::from("None")
@@ -61,15 +94,39 @@ fn var_to_string(var: &Var) -> String {
     format!("{}_{}", var.name, var.scope)
 }
 
-fn vars_to_string(vars: &Vec<Var>) -> String {
+fn vars_to_string(vars: &[Var]) -> String {
     let names: Vec<String> = vars.iter().map(|var| {var_to_string(var)}).collect();
-    format!("{}", names.join(", "))
+    names.join(", ")
+}
+
+fn pattern_to_string(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Var(var) => var_to_string(var),
+        Pattern::Wildcard => String::from("_"),
+        Pattern::Literal(c) => const_to_string(c),
+        Pattern::Tuple(patterns) => format!("({})", patterns_to_string(patterns)),
+        Pattern::List(patterns) => format!("[{}]", patterns_to_string(patterns)),
+        Pattern::Constructor(_, variant, patterns) => format!("{}({})", variant, patterns_to_string(patterns))
+    }
+}
+
+fn label_to_string(label: &Option<String>) -> String {
+    match label {
+        Option::Some(label) => format!("'{}: ", label),
+        Option::None => String::new()
+    }
+}
+
+fn patterns_to_string(patterns: &[Pattern]) -> String {
+    let patterns: Vec<String> = patterns.iter().map(pattern_to_string).collect();
+    patterns.join(", ")
 }
 
 fn exp_to_string(exp: &Exp) -> String {
     match exp {
         Exp::Const(c) => const_to_string(c),
         Exp::Var(x) => var_to_string(x),
+        Exp::ExternalVar(x) => format!("{}_ext{}", x.name, x.scope),
         Exp::List(list) => {
             let mut s = String::from("[");
             for exp in list {
@@ -78,14 +135,46 @@ fn exp_to_string(exp: &Exp) -> String {
             s.push_str("]\n");
             s
         },
+        Exp::Map(entries) => {
+            let entries: Vec<String> = entries.iter().map(|(key, value)| {
+                format!("{}: {}", exp_to_string(key), exp_to_string(value))
+            }).collect();
+            format!("#{{{}}}", entries.join(", "))
+        },
+        Exp::Tuple(elements) if elements.len() == 1 => format!("({},)", args_to_string(elements)),
+        Exp::Tuple(elements) => format!("({})", args_to_string(elements)),
+        Exp::StructInstance(name, fields) => {
+            let fields: Vec<String> = fields.iter().map(|(field, value)| {
+                format!("{}: {}", field, exp_to_string(value))
+            }).collect();
+            format!("{} {{ {} }}", name, fields.join(", "))
+        },
+        Exp::FieldAccess(exp, field) => format!("{}.{}", exp_to_string(exp), field),
+        Exp::EnumInstance(_, variant, values) => format!("{}({})", variant, args_to_string(values)),
+        Exp::Match(exp, arms) => {
+            let arms: Vec<String> = arms.iter().map(|(pattern, guard, body)| match guard {
+                Option::Some(guard) => format!("{} if {} => {}", pattern_to_string(pattern), exp_to_string(guard), exp_to_string(body)),
+                Option::None => format!("{} => {}", pattern_to_string(pattern), exp_to_string(body))
+            }).collect();
+            format!("match {} {{\n{}\n}}", exp_to_string(exp), arms.join(",\n"))
+        },
         Exp::ListSelection(list, index) => format!("{}[{}]", exp_to_string(list), exp_to_string(index)),
-        Exp::Decl(x, val, scope) => format!("let {} = {};\n{}", var_to_string(x), exp_to_string(val), exp_to_string(scope)),
-        Exp::Function(args, body) => format!("fn ({}){{\n{}\n}}", vars_to_string(args), exp_to_string(body)),
+        Exp::Decl(pattern, val, scope) => format!("let {} = {};\n{}", pattern_to_string(pattern), exp_to_string(val), exp_to_string(scope)),
+        Exp::FunctionDecl(vars, functions, scope) => {
+            let mut s = String::new();
+            for (var, function) in vars.iter().zip(functions) {
+                s.push_str(&format!("let {} = {};\n", var_to_string(var), exp_to_string(function)))
+            };
+            s.push_str(&exp_to_string(scope));
+            s
+        },
+        Exp::Function(args, captures, body) => format!("fn ({}) [{}] {{\n{}\n}}", vars_to_string(args), args_to_string(captures), exp_to_string(body)),
         Exp::Assign(lexp, rexp) => format!("{} = {}", exp_to_string(lexp), exp_to_string(rexp)),
         Exp::Seq(e1, e2) => format!("{};\n{}", exp_to_string(e1), exp_to_string(e2)),
         Exp::Sum(e1, e2) => format!("{} + {}", exp_to_string(e1), exp_to_string(e2)),
         Exp::Sub(e1, e2) => format!("{} - {}", exp_to_string(e1), exp_to_string(e2)),
         Exp::Mul(e1, e2) => format!("{} * {}", exp_to_string(e1), exp_to_string(e2)),
+        Exp::Pow(e1, e2) => format!("{} ** {}", exp_to_string(e1), exp_to_string(e2)),
         Exp::Div(e1, e2) => format!("{} / {}", exp_to_string(e1), exp_to_string(e2)),
         Exp::Mod(e1, e2) => format!("{} % {}", exp_to_string(e1), exp_to_string(e2)),
         Exp::Lt(e1, e2) => format!("{} < {}", exp_to_string(e1), exp_to_string(e2)),
@@ -94,21 +183,41 @@ fn exp_to_string(exp: &Exp) -> String {
         Exp::Gte(e1, e2) => format!("{} >= {}", exp_to_string(e1), exp_to_string(e2)),
         Exp::Eq(e1, e2) => format!("{} == {}", exp_to_string(e1), exp_to_string(e2)),
         Exp::Neq(e1, e2) => format!("{} != {}", exp_to_string(e1), exp_to_string(e2)),
+        Exp::In(e1, e2) => format!("{} in {}", exp_to_string(e1), exp_to_string(e2)),
+        Exp::Range(e1, e2, inclusive) => format!("{}{}{}", exp_to_string(e1), if *inclusive {"..="} else {".."}, exp_to_string(e2)),
         Exp::And(e1, e2) => format!("{} && {}", exp_to_string(e1), exp_to_string(e2)),
         Exp::Or(e1, e2) => format!("{} || {}", exp_to_string(e1), exp_to_string(e2)),
         Exp::Not(e) => format!("!{}", exp_to_string(e)),
-        Exp::While(guard, exp) => format!("while {} {{ {} }}", exp_to_string(guard), exp_to_string(exp)),
+        Exp::While(guard, exp, label) => format!("{}while {} {{ {} }}", label_to_string(label), exp_to_string(guard), exp_to_string(exp)),
+        Exp::For(pattern, e, body, label) => format!("{}for {} in {} {{ {} }}", label_to_string(label), pattern_to_string(pattern), exp_to_string(e), exp_to_string(body)),
+        Exp::Break(Option::Some(label), e) => format!("break '{} {}", label, exp_to_string(e)),
+        Exp::Break(Option::None, e) => format!("break {}", exp_to_string(e)),
+        Exp::Continue(Option::Some(label)) => format!("continue '{}", label),
+        Exp::Continue(Option::None) => String::from("continue"),
+        Exp::Return(e) => format!("return {}", exp_to_string(e)),
+        Exp::Import(path) => format!("import \"{}\"", path),
         Exp::IfThenElse(e, e1, e2) => format!("if {} {{ {} }} else {{ {} }}", exp_to_string(e), exp_to_string(e1), exp_to_string(e2)),
         Exp::FunctionCall(e, args) => format!("{}({})", exp_to_string(e), args_to_string(args)),
         Exp::Throw(e) => format!("throw {}",exp_to_string(e)),
-        Exp::Throwcc(k,e) => format!("throw {} {}",var_to_string(k),exp_to_string(e)),
+        Exp::Throwcc(k,e) => format!("throw {} {}",exp_to_string(k),exp_to_string(e)),
         Exp::Try(e) => format!("try {{ {} }}",exp_to_string(e)),
         Exp::TryCatch(e1,exc,e2) => format!("try {{ {} }} catch ( {} ) {{ {} }}",exp_to_string(e1),var_to_string(exc),exp_to_string(e2)),
-        Exp::Callcc(k,e) => format!("callcc {} in {}",var_to_string(k),exp_to_string(e))
+        Exp::Callcc(k,e) => format!("callcc {} in {}",var_to_string(k),exp_to_string(e)),
+        Exp::Reset(e) => format!("reset {}",exp_to_string(e)),
+        Exp::Perform(effect, args) => format!("perform {}({})", effect, args.iter().map(exp_to_string).collect::<Vec<String>>().join(", ")),
+        Exp::Handle(e, handlers) => {
+            let handlers: Vec<String> = handlers.iter().map(|(effect, pattern, k, body)| {
+                format!("{}{}, {} => {}", effect, pattern_to_string(pattern), var_to_string(k), exp_to_string(body))
+            }).collect();
+            format!("handle {} with {{{}}}", exp_to_string(e), handlers.join(", "))
+        },
+        Exp::Yield(e) => format!("yield {}",exp_to_string(e)),
+        Exp::Generator(e) => exp_to_string(e),
+        Exp::Shift(k,e) => format!("shift {} {}",var_to_string(k),exp_to_string(e))
     }
 }
 
-fn args_to_string(args: &Vec<Exp>) -> String {
+fn args_to_string(args: &[Exp]) -> String {
     args.iter().map(|exp| {exp_to_string(exp)}).reduce(|mut a, b| {
         a.push_str(&format!(", {}", b));
         return a;
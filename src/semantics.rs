@@ -1,334 +1,1221 @@
-use crate::expression::{Exp, Const};
-use crate::parser::SyntaxError;
-use crate::value::{Value, StackValue, Function, V};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::mem;
+
+use crate::expression::{Exp, Pattern, Handler};
+use crate::value::{Value, StackValue, Slot, Function, Struct, Variant, Continuation, Generator, V};
 use crate::value::Value::Unit;
+use crate::builtins::builtin_values;
+use crate::module::{MODULE_STRUCT, import};
+use crate::threads::{self, Op};
 use crate::value::V::Val;
-use crate::value::V::Ptr;
-use substring::Substring;
+use num_bigint::BigInt;
+use indexmap::IndexMap;
+use num_traits::{ToPrimitive, Zero};
 
 pub struct Error {
     pub msg: String,
     pub v: V
 }
 
-fn exp_to_string(exp: &Exp) -> String {
-    match exp {
-        Exp::Var(x) => format!("{}", x.name),
-        _ => "".to_string()
-    }
-}
-
-pub fn list_convert(mut l: Vec<StackValue>) -> Vec<Exp> {
-    let mut nl=Vec::new();
-    loop{
-        match l.pop(){
-            Option::None => break,
-            Option::Some(sv) => match sv.as_ref(){
-                Value::Unit => nl.push(Exp::Const(Const::None)),
-                Value::Bool(b) => nl.push(Exp::Const(Const::Boolean(*b))),
-                Value::Int(i) => {
-                    let j: i32=*i as i32;
-                    nl.push(Exp::Const(Const::Integer(j)))
+/**
+ * Reasons why the evaluation of an expression stops before producing its value.
+ * Signals other than errors are caught by the enclosing loop, which the parser guarantees to exist
+ */
+pub enum Signal {
+    Error(Error),
+    // Exits the loop with the given label, or the innermost loop, which evaluates to the value
+    Break(Option<String>, V),
+    // Skips to the next iteration of the loop with the given label, or of the innermost loop
+    Continue(Option<String>),
+    // Exits the function that is being executed, which evaluates to the value
+    Return(V),
+    // Resumes a continuation captured by an enclosing machine, which the current machine leaves immediately
+    Escape(Box<Continuation>, StackValue),
+    // Suspends the generator whose body is being evaluated, with the rest of the body and the yielded value
+    Yield(Box<Continuation>, V),
+    // Suspends the thread that is running, with the rest of the thread and the operation it waits to perform
+    Wait(Box<Continuation>, Op)
+}
+
+impl Signal {
+    pub fn into_error(self) -> Error {
+        match self {
+            Signal::Error(err) => err,
+            Signal::Break(_, _) => Error{msg: String::from("`break` outside of a loop"), v:Val(Unit)},
+            Signal::Continue(_) => Error{msg: String::from("`continue` outside of a loop"), v:Val(Unit)},
+            Signal::Return(_) => Error{msg: String::from("`return` outside of a function"), v:Val(Unit)},
+            Signal::Escape(_, _) => Error{msg: String::from("Continuation resumed outside of the evaluation that captured it"), v:Val(Unit)},
+            Signal::Yield(_, _) => Error{msg: String::from("`yield` outside of a generator"), v:Val(Unit)},
+            Signal::Wait(_, _) => Error{msg: String::from("Thread suspended outside of the scheduler"), v:Val(Unit)}
+        }
+    }
+}
+
+impl From<Error> for Signal {
+    fn from(err: Error) -> Signal {
+        Signal::Error(err)
+    }
+}
+
+thread_local! {
+    // Ids of the machines that are running, from the outermost one, and the id of the next machine
+    static MACHINES: RefCell<(Vec<usize>, usize)> = const { RefCell::new((Vec::new(), 0)) };
+    // Continuation of an enclosing machine resumed by a function called from a native function.
+    // The native function fails, and the machine that called it resumes the continuation
+    static ESCAPE: RefCell<Option<(Box<Continuation>, StackValue)>> = const { RefCell::new(Option::None) };
+}
+
+/**
+ * Where the variables of the expression that is being evaluated are.
+ * `closure` points to the function whose body is being evaluated, or is unit in the main scope.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct Env {
+    stack_start: usize,
+    closure: StackValue
+}
+
+impl Env {
+    fn main() -> Env {
+        Env { stack_start: 0, closure: StackValue::unit() }
+    }
+}
+
+/**
+ * What is left to do once the expression that is being evaluated produces its value.
+ * Frames refer to the nodes of the program they belong to, and hold the values computed so far
+ */
+#[derive(Clone, Debug)]
+pub enum Frame {
+    // Evaluates the second expression of a sequence
+    Seq(&'static Exp, Env),
+    // Binds the value to the pattern, whose slots start at the given one, and evaluates the rest of the block
+    Decl(&'static Pattern, &'static Exp, usize, Env),
+    // Pops the variables of a block
+    Truncate(usize),
+    // Evaluates the right operand of a binary operator
+    Operand(&'static Exp, Env),
+    // Applies a binary operator to the value of its left operand and to the value
+    Operator(&'static Exp, StackValue),
+    And(&'static Exp, Env),
+    Or(&'static Exp, Env),
+    Not,
+    Bool,
+    If(&'static Exp, &'static Exp, Env),
+    // Evaluates the next element of a list, tuple, map, struct, enum variant or function call
+    Elements(&'static Exp, Vec<StackValue>, Env),
+    // Returns from a function whose variables start at the given slot
+    Call(usize),
+    // Evaluates the body of a while loop if the guard is true. The value is the one of the last iteration
+    WhileGuard(&'static Exp, StackValue, Env),
+    // Evaluates the guard again, or catches the `break` and `continue` of the body
+    WhileBody(&'static Exp, StackValue, usize, Env),
+    ForIterable(&'static Exp, Env),
+    ForBody(&'static Exp, Items, usize, Env),
+    Match(&'static Exp, Env),
+    // Evaluates the arm with the given index if its guard is true, or tries the next arms
+    MatchGuard(&'static Exp, usize, StackValue, usize, Env),
+    Field(&'static String),
+    // Assigns the value to the left-hand side of an assignment
+    Assign(&'static Exp, Env),
+    // Assigns the right value to a field or an element of the value
+    AssignTarget(&'static Exp, StackValue, Env),
+    // Assigns the right value to the element of the list or map at the index
+    AssignIndex(StackValue, StackValue),
+    Break(&'static Option<String>),
+    Return,
+    Yield,
+    Throw,
+    // Evaluates the handler of a `try` with the exception bound to the variable at the given slot
+    Catch(&'static Exp, usize, Env),
+    // Evaluates the value passed to the continuation in `throw k exp`
+    ThrowccValue(&'static Exp, Env),
+    ThrowccContinuation(StackValue),
+    // Handles the effects performed by the body of handle, whose variables start at the given slot
+    Handle(&'static Exp, usize, Env),
+    // Delimits the continuations captured by shift, and pops the variables of the body of reset
+    Reset(usize),
+    // Delimits the continuations captured by shift, and gives the stack back to the caller of a delimited continuation
+    Resume(Vec<Slot>)
+}
+
+/**
+ * Elements of a for loop that are not iterated yet
+ */
+#[derive(Clone, Debug)]
+pub enum Items {
+    Values(Vec<StackValue>, usize),
+    Range(i64, i64),
+    // Values are produced by the generator when they are needed
+    Generator(StackValue)
+}
+
+impl Items {
+    fn new(value: StackValue) -> Result<Items, Error> {
+        if value.is_unit() {
+            return Result::Err(Error{msg: String::from("Cannot iterate over unit"), v:Val(Unit)})
+        }
+        let values: Vec<StackValue> = match value.as_ref() {
+            // The elements are copied first, so the body can modify the list
+            Value::List(list) => list.clone(),
+            Value::Str(s) => s.chars().map(|c| StackValue::from_box(Box::new(Value::Str(c.to_string())))).collect(),
+            Value::Map(map) => map.keys().filter_map(Value::clone_key).map(|key| StackValue::from_box(Box::new(key))).collect(),
+            Value::Range(start, end) => return Result::Ok(Items::Range(*start, *end)),
+            Value::Generator(_) => return Result::Ok(Items::Generator(value)),
+            value => return Result::Err(Error{msg: format!("Cannot iterate over value {}", value), v:Val(Unit)})
+        };
+        Result::Ok(Items::Values(values, 0))
+    }
+
+    fn next(&mut self) -> Result<Option<StackValue>, Error> {
+        match self {
+            Items::Values(values, i) => match values.get(*i) {
+                Option::Some(value) => {
+                    *i += 1;
+                    Result::Ok(Option::Some(*value))
                 },
-                Value::Str(s) => nl.push(Exp::Const(Const::String(s.clone()))),
-                Value::List(ol) => nl.push(Exp::List(list_convert(ol.clone()))),
-                Value::Fn(f) => nl.push(Exp::Function(Vec::new(),f.body.clone()))
-            }
+                Option::None => Result::Ok(Option::None)
+            },
+            Items::Range(start, end) if *start < *end => {
+                *start += 1;
+                Result::Ok(Option::Some(StackValue::from_box(Box::new(Value::Int(*start - 1)))))
+            },
+            Items::Range(_, _) => Result::Ok(Option::None),
+            Items::Generator(generator) => Result::Ok(next_value(*generator)?.map(V::into_stack_value))
         }
-    };
-    let mut res_stack=Vec::new();
-    loop {
-        match nl.pop(){
-            Option::None => break,
-            Option::Some(x) => res_stack.push(x) 
+    }
+}
+
+enum Control {
+    Eval(&'static Exp, Env),
+    Value(V),
+    Signal(Signal)
+}
+
+impl From<Result<V, Error>> for Control {
+    fn from(result: Result<V, Error>) -> Control {
+        match result {
+            Result::Ok(v) => Control::Value(v),
+            Result::Err(err) => Control::Signal(err.into())
         }
-    };
-    return res_stack;
+    }
 }
 
-pub fn eval(exp: &Exp) -> Result<V, Error> {
-    let mut stack: Vec<StackValue> = Vec::new();
-    eval_expression(exp, &mut stack, 0, false, "".to_string())
+/**
+ * Evaluates expressions without using the Rust stack: what is left to do is kept in a list of frames,
+ * so the evaluation can be captured in a continuation at any point and resumed later
+ */
+struct Machine {
+    id: usize,
+    stack: Vec<Slot>,
+    frames: Vec<Frame>,
+    // Whether the machine runs a thread, which is suspended when it waits
+    thread: bool
 }
 
-pub fn eval_expression(exp: &Exp, stack: &mut Vec<StackValue>, stack_start: usize, in_call: bool, param: String) -> Result<V, Error> {
-    match exp {
-        Exp::Const(c) => Result::Ok(V::Val(Value::from_const(&c))),
+pub fn eval(exp: Exp) -> Result<V, Error> {
+    // An imported file shares the threads of the program that imports it
+    if MACHINES.with(|machines| machines.borrow().0.is_empty()) {
+        threads::reset()
+    }
+    let mut stack: Vec<Slot> = builtin_values();
+    eval_in_scope(exp, &mut stack)
+}
 
-        Exp::Var(x) => {
-            Result::Ok(V::Ptr(stack[x.scope + stack_start]))
+/**
+ * Evaluates an expression of the main scope, whose variables are already on the stack. Eg: a line of the shell.
+ * The expression is leaked, because functions, continuations and threads created by it refer to it and can
+ * outlive the evaluation: a function declared in a line of the shell is called by the following lines.
+ * So the shell leaks every line it evaluates, and imports leak every file once, because modules are cached
+ */
+pub fn eval_in_scope(exp: Exp, stack: &mut Vec<Slot>) -> Result<V, Error> {
+    let exp: &'static Exp = Box::leak(Box::new(exp));
+    let num_vars: usize = stack.len();
+    let mut machine: Machine = Machine::new(mem::take(stack));
+    let result = machine.run(Control::Eval(exp, Env::main()));
+    *stack = machine.stack;
+    stack.truncate(num_vars);
+    // The threads that have not ended run before the program ends, but not before an imported file returns
+    let result: Result<V, Error> = result.map_err(Signal::into_error);
+    if MACHINES.with(|machines| machines.borrow().0.is_empty()) {
+        // A thread that nobody joined ends the program with its error
+        let remaining: Result<(), Error> = threads::run_remaining();
+        return result.and_then(|value| remaining.map(|()| value))
+    }
+    result
+}
+
+/**
+ * Calls a function value from a native function. Eg: the function passed to `map`
+ */
+pub fn call_function(function_ptr: StackValue, arg_values: Vec<StackValue>) -> Result<V, Error> {
+    check_call(function_ptr, arg_values.len())?;
+    let mut machine: Machine = Machine::new(Vec::new());
+    let control: Control = machine.apply(function_ptr, arg_values);
+    match machine.run(control) {
+        Result::Ok(v) => Result::Ok(v),
+        Result::Err(Signal::Escape(continuation, value)) => Result::Err(escape(continuation, value)),
+        Result::Err(signal) => Result::Err(signal.into_error())
+    }
+}
+
+/**
+ * Evaluates the body of a generator until the next yield, and returns the yielded value.
+ * Returns None once the body has ended
+ */
+pub fn next_value(mut generator_ptr: StackValue) -> Result<Option<V>, Error> {
+    if generator_ptr.is_unit() {
+        return Result::Err(Error{msg: String::from("Expected a generator, found unit"), v:Val(Unit)})
+    }
+    let generator: &mut Generator = match generator_ptr.as_mut_ref() {
+        Value::Generator(generator) => generator,
+        other => return Result::Err(Error{msg: format!("Expected a generator, found {}", other), v:Val(Unit)})
+    };
+    let (mut machine, control) = match mem::replace(generator, Generator::Running) {
+        Generator::Start(body, stack, closure) => (Machine::new(stack), Control::Eval(body, Env { stack_start: 0, closure })),
+        Generator::Suspended(continuation) => {
+            let mut machine: Machine = Machine::new(continuation.stack);
+            machine.frames = continuation.frames;
+            // `yield` evaluates to unit
+            (machine, Control::Value(V::Val(Unit)))
+        },
+        state => {
+            let is_running: bool = matches!(state, Generator::Running);
+            *generator = state;
+            return if is_running {
+                Result::Err(Error{msg: String::from("Generator is already running"), v:Val(Unit)})
+            } else {
+                Result::Ok(Option::None)
+            }
+        }
+    };
+    let result = machine.run(control);
+    // The body may have read the generator, so it is borrowed again
+    let generator: &mut Generator = match generator_ptr.as_mut_ref() {
+        Value::Generator(generator) => generator,
+        other => return Result::Err(Error{msg: format!("The generator has been replaced by {} while running", other), v:Val(Unit)})
+    };
+    *generator = Generator::Done;
+    match result {
+        Result::Ok(_) | Result::Err(Signal::Return(_)) => Result::Ok(Option::None),
+        Result::Err(Signal::Yield(continuation, v)) => {
+            *generator = Generator::Suspended(*continuation);
+            Result::Ok(Option::Some(v))
         },
+        Result::Err(Signal::Escape(continuation, value)) => Result::Err(escape(continuation, value)),
+        Result::Err(signal) => Result::Err(signal.into_error())
+    }
+}
+
+/**
+ * How a thread starts or continues: by calling its function, or by resuming the thread where it waited
+ * with the result of the operation it waited for
+ */
+pub enum Resume {
+    Start(StackValue),
+    Continue(Continuation, Result<V, Error>)
+}
+
+pub enum Outcome {
+    Done(Result<V, Error>),
+    Waiting(Continuation, Op)
+}
 
-        Exp::Decl(_, val_exp, exp2) => {
-            match eval_expression(val_exp, stack, stack_start, false, "".to_string())? {
-                V::Ptr(ptr) => stack.push(ptr),
-                V::Val(value) => stack.push(StackValue::from_box(Box::new(value)))
+/**
+ * Runs a thread until it ends or has to wait
+ */
+pub fn run_thread(resume: Resume) -> Outcome {
+    let (mut machine, control) = match resume {
+        Resume::Start(function_ptr) => {
+            let mut machine: Machine = Machine::new(Vec::new());
+            machine.thread = true;
+            let control: Control = match check_call(function_ptr, 0) {
+                Result::Ok(()) => machine.apply(function_ptr, Vec::new()),
+                Result::Err(err) => Control::Signal(err.into())
             };
-            let result = eval_expression(exp2, stack, stack_start, false, "".to_string());
-            stack.pop();
-            result
+            (machine, control)
         },
+        Resume::Continue(continuation, result) => {
+            let mut machine: Machine = Machine::new(continuation.stack);
+            machine.frames = continuation.frames;
+            machine.thread = true;
+            (machine, Control::from(result))
+        }
+    };
+    match machine.run(control) {
+        Result::Ok(v) => Outcome::Done(Result::Ok(v)),
+        Result::Err(Signal::Wait(continuation, op)) => Outcome::Waiting(*continuation, op),
+        Result::Err(signal) => Outcome::Done(Result::Err(signal.into_error()))
+    }
+}
+
+/**
+ * Stores a continuation resumed inside a nested machine, for the machine that called the native function
+ */
+fn escape(continuation: Box<Continuation>, value: StackValue) -> Error {
+    ESCAPE.with(|escape| *escape.borrow_mut() = Option::Some((continuation, value)));
+    Error{msg: String::from("Continuation resumed from a native function"), v:Val(Unit)}
+}
+
+fn is_running(machine: usize) -> bool {
+    MACHINES.with(|machines| machines.borrow().0.contains(&machine))
+}
+
+impl Machine {
+    fn new(stack: Vec<Slot>) -> Machine {
+        let id: usize = MACHINES.with(|machines| {
+            let mut machines = machines.borrow_mut();
+            machines.1 += 1;
+            machines.1
+        });
+        Machine { id, stack, frames: Vec::new(), thread: false }
+    }
+
+    fn run(&mut self, control: Control) -> Result<V, Signal> {
+        MACHINES.with(|machines| machines.borrow_mut().0.push(self.id));
+        let mut control: Control = control;
+        let result = loop {
+            control = match control {
+                Control::Eval(exp, env) => self.eval(exp, env),
+                Control::Value(v) => match self.frames.pop() {
+                    Option::Some(frame) => self.resume(frame, v),
+                    Option::None => break Result::Ok(v)
+                },
+                Control::Signal(signal @ (Signal::Escape(_, _) | Signal::Yield(_, _) | Signal::Wait(_, _))) => break Result::Err(signal),
+                Control::Signal(signal) => match self.unwind(signal) {
+                    Result::Ok(control) => control,
+                    Result::Err(signal) => break Result::Err(signal)
+                }
+            }
+        };
+        MACHINES.with(|machines| machines.borrow_mut().0.pop());
+        result
+    }
+
+    fn eval(&mut self, exp: &'static Exp, env: Env) -> Control {
+        match exp {
+            Exp::Const(c) => Control::Value(V::Val(Value::from_const(c))),
+
+            Exp::Var(_) | Exp::ExternalVar(_) => Control::Value(V::Ptr(read_variable(exp, &self.stack, env))),
+
+            Exp::Decl(pattern, val_exp, exp2) => {
+                // The variables are already in scope while the value is parsed, so their slots are reserved
+                // before evaluating the value: variables declared inside it get the slots the parser expects
+                let slot: usize = self.stack.len();
+                for _ in 0..pattern.num_vars() {
+                    self.stack.push(Slot::new(StackValue::unit()));
+                }
+                self.frames.push(Frame::Decl(pattern, exp2, slot, env));
+                Control::Eval(val_exp, env)
+            },
+
+            Exp::FunctionDecl(_, functions, exp) => {
+                let slot: usize = self.stack.len();
+                if let Result::Err(err) = push_function_decls(functions, &mut self.stack, env.stack_start, env.closure) {
+                    return Control::Signal(err.into())
+                }
+                self.frames.push(Frame::Truncate(slot));
+                Control::Eval(exp, env)
+            },
+
+            Exp::List(_) | Exp::Tuple(_) | Exp::Map(_) | Exp::StructInstance(_, _) | Exp::EnumInstance(_, _, _)
+                | Exp::FunctionCall(_, _) | Exp::Perform(_, _) => self.next_element(exp, Vec::new(), env),
+
+            Exp::Handle(body, _) => {
+                self.frames.push(Frame::Handle(exp, self.stack.len(), env));
+                Control::Eval(body, env)
+            },
+
+            Exp::Match(exp1, _) => {
+                self.frames.push(Frame::Match(exp, env));
+                Control::Eval(exp1, env)
+            },
 
-        Exp::List(list) => {
-            let mut values: Vec<StackValue> = Vec::with_capacity(list.len());
-            for exp in list {
-                let value = match eval_expression(exp, stack, stack_start, false, "".to_string())? {
-                    V::Ptr(ptr) => ptr,
-                    V::Val(value) => StackValue::from_box(Box::new(value))
+            Exp::FieldAccess(exp, field) => {
+                self.frames.push(Frame::Field(field));
+                Control::Eval(exp, env)
+            },
+
+            Exp::Assign(_, right_exp) => {
+                self.frames.push(Frame::Assign(exp, env));
+                Control::Eval(right_exp, env)
+            },
+
+            Exp::While(_, _, _) => self.while_guard(exp, StackValue::from_box(Box::new(Value::Unit)), env),
+
+            Exp::For(_, iterable, _, _) => {
+                self.frames.push(Frame::ForIterable(exp, env));
+                Control::Eval(iterable, env)
+            },
+
+            Exp::Break(label, exp) => {
+                self.frames.push(Frame::Break(label));
+                Control::Eval(exp, env)
+            },
+
+            Exp::Continue(label) => Control::Signal(Signal::Continue(label.clone())),
+
+            Exp::Return(exp) => {
+                self.frames.push(Frame::Return);
+                Control::Eval(exp, env)
+            },
+
+            Exp::Yield(exp) => {
+                self.frames.push(Frame::Yield);
+                Control::Eval(exp, env)
+            },
+
+            Exp::Generator(body) => {
+                let generator: Generator = Generator::Start(body, self.stack[env.stack_start..].to_vec(), env.closure);
+                Control::Value(V::Val(Value::Generator(generator)))
+            },
+
+            Exp::Import(path) => match import(path) {
+                Result::Ok(module) => Control::Value(V::Ptr(module)),
+                Result::Err(err) => Control::Signal(err.into())
+            },
+
+            Exp::IfThenElse(condition, exp1, exp2) => {
+                self.frames.push(Frame::If(exp1, exp2, env));
+                Control::Eval(condition, env)
+            },
+
+            Exp::Try(exp1) => Control::Eval(exp1, env),
+
+            Exp::TryCatch(exp1, _, exp2) => {
+                self.frames.push(Frame::Catch(exp2, self.stack.len(), env));
+                Control::Eval(exp1, env)
+            },
+
+            //evaluate the exception then returns the string containing the value. Try-Catch, if present, will handle the exception thrown
+            Exp::Throw(exp) => {
+                self.frames.push(Frame::Throw);
+                Control::Eval(exp, env)
+            },
+
+            //E.g. throw k 2 => resumes the continuation k with the value 2
+            Exp::Throwcc(k, exp) => {
+                self.frames.push(Frame::ThrowccValue(exp, env));
+                Control::Eval(k, env)
+            },
+
+            //binds the current continuation to k and then evaluates the expression e.
+            //If k is resumed with `throw k m` or `k(m)`, even after e has been evaluated, then `callcc k in e` evaluates to m
+            Exp::Callcc(_, exp) => {
+                let continuation: Continuation = Continuation {
+                    machine: self.id, frames: self.frames.clone(), stack: self.stack.clone(), delimited: false
+                };
+                let slot: usize = self.stack.len();
+                self.stack.push(Slot::new(StackValue::from_box(Box::new(Value::Continuation(continuation)))));
+                self.frames.push(Frame::Truncate(slot));
+                Control::Eval(exp, env)
+            },
+
+            Exp::Reset(exp) => {
+                self.frames.push(Frame::Reset(self.stack.len()));
+                Control::Eval(exp, env)
+            },
+
+            // The frames up to the enclosing reset are moved to the continuation, so the body
+            // of shift gives the value of the reset
+            Exp::Shift(_, exp) => {
+                let delimiter: usize = match self.frames.iter().rposition(|frame| matches!(frame, Frame::Reset(_) | Frame::Resume(_))) {
+                    Option::Some(delimiter) => delimiter,
+                    Option::None => return Control::Signal(Error{msg: String::from("`shift` outside of reset"), v:Val(Unit)}.into())
+                };
+                let continuation: Continuation = Continuation {
+                    machine: self.id, frames: self.frames.split_off(delimiter + 1), stack: self.stack.clone(), delimited: true
                 };
-                values.push(value)
+                let slot: usize = self.stack.len();
+                self.stack.push(Slot::new(StackValue::from_box(Box::new(Value::Continuation(continuation)))));
+                self.frames.push(Frame::Truncate(slot));
+                Control::Eval(exp, env)
+            },
+
+            Exp::Function(_, _, _) => Control::Value(V::Val(new_function(exp, &self.stack, env))),
+
+            Exp::Seq(exp1, exp2) => {
+                self.frames.push(Frame::Seq(exp2, env));
+                Control::Eval(exp1, env)
+            },
+
+            Exp::ListSelection(exp1, _) | Exp::Sum(exp1, _) | Exp::Sub(exp1, _) | Exp::Mul(exp1, _) | Exp::Pow(exp1, _)
+                | Exp::Mod(exp1, _) | Exp::Div(exp1, _) | Exp::Lt(exp1, _) | Exp::Lte(exp1, _) | Exp::Gt(exp1, _)
+                | Exp::Gte(exp1, _) | Exp::Eq(exp1, _) | Exp::Neq(exp1, _) | Exp::In(exp1, _) | Exp::Range(exp1, _, _) => {
+                self.frames.push(Frame::Operand(exp, env));
+                Control::Eval(exp1, env)
+            },
+
+            Exp::And(exp1, exp2) => {
+                self.frames.push(Frame::And(exp2, env));
+                Control::Eval(exp1, env)
+            },
+
+            Exp::Or(exp1, exp2) => {
+                self.frames.push(Frame::Or(exp2, env));
+                Control::Eval(exp1, env)
+            },
+
+            Exp::Not(exp1) => {
+                self.frames.push(Frame::Not);
+                Control::Eval(exp1, env)
             }
-            Result::Ok(V::Val(Value::List(values)))
         }
+    }
 
-        Exp::ListSelection(list, index) => {
-            let list: V = eval_expression(list, stack, stack_start, false, "".to_string())?;
-            let index: V = eval_expression(index, stack, stack_start, false, "".to_string())?;
-            let value = match (list.as_ref(), index.as_ref()) {
-                (Value::List(values), Value::Int(i)) => values.get(*i as usize)
-                    .ok_or(Error{msg: String::from("List index out of range"), v:Val(Unit)})?,
-                _ => return Result::Err(Error{msg: String::from("q"), v:Val(Unit)})
-            };
-            Result::Ok(V::Ptr(*value))
-        }
+    /**
+     * Continues the evaluation of the expression of the frame, now that the expression it was waiting for has the value `v`
+     */
+    fn resume(&mut self, frame: Frame, v: V) -> Control {
+        match frame {
+            Frame::Seq(exp2, env) => Control::Eval(exp2, env),
 
-        Exp::Assign(left_exp, right_exp) => {
-            let right_value: V = eval_expression(right_exp, stack, stack_start, false, "".to_string())?;
-            match (*left_exp).as_ref() {
-                Exp::Var(var) => match right_value {
-                    V::Ptr(ptr) => stack[var.scope + stack_start] = ptr,
-                    V::Val(value) => stack[var.scope + stack_start] = StackValue::from_box(Box::new(value))
+            Frame::Decl(pattern, exp2, slot, env) => match bind_pattern(pattern, v, &mut self.stack, env.stack_start) {
+                Result::Ok(()) => {
+                    self.frames.push(Frame::Truncate(slot));
+                    Control::Eval(exp2, env)
                 },
-                Exp::ListSelection(list, index) => {
-                    let mut list = eval_expression(list.as_ref(), stack, stack_start, false, "".to_string())?;
-                    let list: &mut Vec<StackValue> = match list.as_mut_ref() {
-                        Value::List(list) => list,
-                        _ => return Result::Err(Error{msg: String::from("Expected list value before list selection"), v:Val(Unit)})
+                Result::Err(err) => Control::Signal(err.into())
+            },
+
+            Frame::Truncate(slot) | Frame::Call(slot) | Frame::Reset(slot) => {
+                self.stack.truncate(slot);
+                Control::Value(v)
+            },
+
+            Frame::Operand(exp, env) => {
+                let exp2: &Exp = match exp {
+                    Exp::ListSelection(_, exp2) | Exp::Sum(_, exp2) | Exp::Sub(_, exp2) | Exp::Mul(_, exp2) | Exp::Pow(_, exp2)
+                        | Exp::Mod(_, exp2) | Exp::Div(_, exp2) | Exp::Lt(_, exp2) | Exp::Lte(_, exp2) | Exp::Gt(_, exp2)
+                        | Exp::Gte(_, exp2) | Exp::Eq(_, exp2) | Exp::Neq(_, exp2) | Exp::In(_, exp2) | Exp::Range(_, exp2, _) => exp2,
+                    _ => panic!("Expected a binary operator")
+                };
+                self.frames.push(Frame::Operator(exp, v.into_stack_value()));
+                Control::Eval(exp2, env)
+            },
+
+            Frame::Operator(exp, val1) => binary_operator(exp, V::Ptr(val1), v).into(),
+
+            Frame::And(exp2, env) => {
+                if !v.as_bool() {
+                    return Control::Value(V::Val(Value::Bool(false)))
+                }
+                self.frames.push(Frame::Bool);
+                Control::Eval(exp2, env)
+            },
+
+            Frame::Or(exp2, env) => {
+                if v.as_bool() {
+                    return Control::Value(V::Val(Value::Bool(true)))
+                }
+                self.frames.push(Frame::Bool);
+                Control::Eval(exp2, env)
+            },
+
+            Frame::Not => Control::Value(V::Val(Value::Bool(!v.as_bool()))),
+
+            Frame::Bool => Control::Value(V::Val(Value::Bool(v.as_bool()))),
+
+            Frame::If(exp1, exp2, env) => Control::Eval(if v.as_bool() {exp1} else {exp2}, env),
+
+            Frame::Elements(exp, mut values, env) => {
+                match exp {
+                    // The key of an entry must be hashable
+                    Exp::Map(_) if values.len().is_multiple_of(2) => if let Result::Err(err) = map_key(v.as_ref()) {
+                        return Control::Signal(err.into())
+                    },
+                    // The function value must have a stable address, since its body can update the captured values
+                    Exp::FunctionCall(_, args) if values.is_empty() => {
+                        let function_ptr: StackValue = v.into_stack_value();
+                        if let Result::Err(err) = check_call(function_ptr, args.len()) {
+                            return Control::Signal(err.into())
+                        }
+                        values.push(function_ptr);
+                        return self.next_element(exp, values, env)
+                    },
+                    _ => ()
+                }
+                values.push(v.into_stack_value());
+                self.next_element(exp, values, env)
+            },
+
+            Frame::WhileGuard(exp, last, env) => match exp {
+                Exp::While(_, body, _) if v.as_bool() => {
+                    self.frames.push(Frame::WhileBody(exp, last, self.stack.len(), env));
+                    Control::Eval(body, env)
+                },
+                // The loop evaluates to the value of its last iteration, or to the value of a `break`
+                _ => Control::Value(V::Ptr(last))
+            },
+
+            Frame::WhileBody(exp, _, _, env) => self.while_guard(exp, v.into_stack_value(), env),
+
+            Frame::ForIterable(exp, env) => match Items::new(v.into_stack_value()) {
+                Result::Ok(items) => self.next_iteration(exp, items, self.stack.len(), env),
+                Result::Err(err) => Control::Signal(err.into())
+            },
+
+            Frame::ForBody(exp, items, slot, env) => self.next_iteration(exp, items, slot, env),
+
+            Frame::Match(exp, env) => self.match_arms(exp, v.into_stack_value(), 0, env),
+
+            Frame::MatchGuard(exp, arm, value, slot, env) => match exp {
+                Exp::Match(_, arms) if v.as_bool() => {
+                    self.frames.push(Frame::Truncate(slot));
+                    Control::Eval(&arms[arm].2, env)
+                },
+                _ => {
+                    self.stack.truncate(slot);
+                    self.match_arms(exp, value, arm + 1, env)
+                }
+            },
+
+            Frame::Field(field) => {
+                let mut value: V = v;
+                match struct_field(&mut value, field) {
+                    Result::Ok(ptr) => Control::Value(V::Ptr(*ptr)),
+                    Result::Err(err) => Control::Signal(err.into())
+                }
+            },
+
+            Frame::Assign(exp, env) => {
+                let left_exp: &Exp = match exp {
+                    Exp::Assign(left_exp, _) => left_exp,
+                    _ => panic!("Expected an assignment")
+                };
+                let right_value: StackValue = v.into_stack_value();
+                match left_exp {
+                    Exp::Var(var) => self.stack[var.scope + env.stack_start].set(right_value),
+                    Exp::ExternalVar(var) => external_slots(&env.closure)[var.scope].set(right_value),
+                    Exp::ListSelection(exp, _) | Exp::FieldAccess(exp, _) => {
+                        self.frames.push(Frame::AssignTarget(left_exp, right_value, env));
+                        return Control::Eval(exp, env)
+                    },
+                    _ => return Control::Signal(Error{msg: String::from("Invalid left-hand side in assignment"), v:Val(Unit)}.into())
+                };
+                Control::Value(V::Ptr(StackValue::unit()))
+            },
+
+            Frame::AssignTarget(left_exp, right_value, env) => match left_exp {
+                Exp::ListSelection(_, index) => {
+                    let list: StackValue = v.into_stack_value();
+                    match if list.is_unit() { &Value::Unit } else { list.as_ref() } {
+                        Value::List(_) | Value::Map(_) => (),
+                        _ => return Control::Signal(Error{msg: String::from("Expected list value before list selection"), v:Val(Unit)}.into())
                     };
-                    let index: usize = match eval_expression(index.as_ref(), stack, stack_start, false, "".to_string())?.as_ref() {
-                        Value::Int(i) => *i as usize,
-                        _ => return Result::Err(Error{msg: String::from("Expected number in list selection"), v:Val(Unit)})
+                    self.frames.push(Frame::AssignIndex(list, right_value));
+                    Control::Eval(index, env)
+                },
+                Exp::FieldAccess(_, field) => {
+                    let mut value: V = v;
+                    match struct_field(&mut value, field) {
+                        Result::Ok(ptr) => *ptr = right_value,
+                        Result::Err(err) => return Control::Signal(err.into())
                     };
-                    if index >= list.len() {
-                        return Result::Err(Error{msg: String::from("List index out of range"), v:Val(Unit)})
-                    }
-                    list[index] = match right_value {
-                        V::Ptr(ptr) => ptr,
-                        V::Val(value) => StackValue::from_box(Box::new(value))
-                    }
+                    Control::Value(V::Ptr(StackValue::unit()))
                 },
-                _ => return Result::Err(Error{msg: String::from("Invalid left-hand side in assignment"), v:Val(Unit)})
+                _ => panic!("Expected a list selection or a field access")
+            },
+
+            Frame::AssignIndex(mut list, right_value) => assign_index(&mut list, v, right_value).into(),
+
+            Frame::Break(label) => Control::Signal(Signal::Break(label.clone(), v)),
+
+            Frame::Return => Control::Signal(Signal::Return(v)),
+
+            // The generator runs in its own machine, so the frames are the rest of its body
+            Frame::Yield => {
+                let continuation: Continuation = Continuation {
+                    machine: self.id, frames: mem::take(&mut self.frames), stack: self.stack.clone(), delimited: true
+                };
+                Control::Signal(Signal::Yield(Box::new(continuation), v))
+            },
+
+            Frame::Throw => Control::Signal(thrown(v).into()),
+
+            Frame::Catch(_, _, _) | Frame::Handle(_, _, _) => Control::Value(v),
+
+            Frame::Resume(stack) => {
+                self.stack = stack;
+                Control::Value(v)
+            },
+
+            Frame::ThrowccValue(exp, env) => {
+                self.frames.push(Frame::ThrowccContinuation(v.into_stack_value()));
+                Control::Eval(exp, env)
+            },
+
+            Frame::ThrowccContinuation(k) => match if k.is_unit() { &Value::Unit } else { k.as_ref() } {
+                Value::Continuation(continuation) => self.resume_continuation(Box::new(continuation.clone()), v.into_stack_value()),
+                other => Control::Signal(Error{msg: format!("Expected a continuation after throw, found {}", other), v:Val(Unit)}.into())
             }
-            Result::Ok(V::Ptr(StackValue::unit()))
         }
+    }
 
-        Exp::While(guard, exp) => {
-            let condition = eval_expression(guard, stack, stack_start, false, "".to_string())?.as_bool();
-            if !condition {
-                Result::Ok(V::Val(Value::Unit))
-            } else {
-                loop {
-                    let v: V = eval_expression(exp, stack, stack_start, false, "".to_string())?;
-                    if !eval_expression(guard, stack, stack_start, false, "".to_string())?.as_bool() {
-                        break Result::Ok(v)
-                    }
-                }
+    /**
+     * Pops the frames until the one that handles the signal. Returns the signal if no frame handles it
+     */
+    fn unwind(&mut self, signal: Signal) -> Result<Control, Signal> {
+        let mut signal: Signal = signal;
+        while let Option::Some(frame) = self.frames.pop() {
+            signal = match self.handle(frame, signal) {
+                Result::Ok(control) => return Result::Ok(control),
+                Result::Err(signal) => signal
             }
         }
+        Result::Err(signal)
+    }
 
-        Exp::IfThenElse(condition, exp1, exp2) => {
-            let is_true: bool = eval_expression(condition, stack, stack_start, false, "".to_string())?.as_bool();
-            eval_expression(if is_true {exp1} else {exp2}, stack, stack_start, false, "".to_string())
+    fn handle(&mut self, frame: Frame, signal: Signal) -> Result<Control, Signal> {
+        match (frame, signal) {
+            (Frame::Catch(handler, slot, env), Signal::Error(Error{msg: _, v})) => {
+                // Bind the exception value to the catch variable, like a let declaration does
+                self.stack.truncate(slot);
+                self.stack.push(Slot::new(v.into_stack_value()));
+                self.frames.push(Frame::Truncate(slot));
+                Result::Ok(Control::Eval(handler, env))
+            },
+            (Frame::WhileBody(exp, _, slot, _) | Frame::ForBody(exp, _, slot, _), Signal::Break(target, v)) if is_target(&target, exp) => {
+                self.stack.truncate(slot);
+                Result::Ok(Control::Value(v))
+            },
+            (Frame::WhileBody(exp, last, slot, env), Signal::Continue(target)) if is_target(&target, exp) => {
+                self.stack.truncate(slot);
+                Result::Ok(self.while_guard(exp, last, env))
+            },
+            (Frame::ForBody(exp, items, slot, env), Signal::Continue(target)) if is_target(&target, exp) => {
+                Result::Ok(self.next_iteration(exp, items, slot, env))
+            },
+            (Frame::Call(slot), Signal::Return(v)) => {
+                self.stack.truncate(slot);
+                Result::Ok(Control::Value(v))
+            },
+            // The handlers of the caller of a delimited continuation work on its stack
+            (Frame::Resume(stack), signal) => {
+                self.stack = stack;
+                Result::Err(signal)
+            },
+            (_, signal) => Result::Err(signal)
         }
+    }
 
-        Exp::Try(exp1)=> {
-            let res: Result<V, Error> = eval_expression(exp1,stack,stack_start, false, "".to_string());
-            match res {
-                Result::Ok(_) => return Result::Ok(res?),
-                Result::Err(Error{msg, v}) => {
-                    return Result::Err(Error{msg, v})
-                }
-            }
+    fn while_guard(&mut self, exp: &'static Exp, last: StackValue, env: Env) -> Control {
+        match exp {
+            Exp::While(guard, _, _) => {
+                self.frames.push(Frame::WhileGuard(exp, last, env));
+                Control::Eval(guard, env)
+            },
+            _ => panic!("Expected a while loop")
         }
-        
-        Exp::TryCatch(exp1,exc,exp2) => {
-            let res: Result<V, Error> = eval_expression(exp1,stack,stack_start, false, "".to_string());
-            match res {
-                Result::Ok(_) => return Result::Ok(res?),
-                Result::Err(Error{msg, v}) => {
-                    let val_exp = match v.as_ref() {
-                        Value::Unit => Exp::Const(Const::None),
-                        Value::Int(x) => Exp::Const(Const::Integer((*x).try_into().unwrap())),
-                        Value::Bool(b) => Exp::Const(Const::Boolean(*b)),
-                        Value::Fn(f) => Exp::Function(Vec::new(), f.body.clone()),
-                        Value::Str(s) => Exp::Const(Const::String(s.to_string())),
-                        Value::List(l) => Exp::List(list_convert(l.clone()))
-                    };
-                    let tmp: Result<V, Error> = eval_expression(&Exp::Decl(exc.clone(),Box::new(val_exp),exp2.clone()),stack,stack_start, false, "".to_string());
-                    match tmp {
-                        Result::Ok(v) => {
-                            return Result::Ok(v)
-                        }
-                        Result::Err(Error{msg, v}) => return Result::Err(Error{msg, v}), 
-                        _ => return Result::Err(Error{msg: String::from("Unknown error"), v:Val(Unit)})
-                    }
-                }
-            }
-        }
-
-        //evaluate the exception then returns the string containing the value. Try-Catch, if present, will handle the exception thrown
-        Exp::Throw(exp) => {
-            let res=eval_expression(exp,stack,stack_start, false, "".to_string())?;
-            return Result::Err(Error{msg: String::from("uncaught exception ".to_string()+&res.to_string()), v:res})
-        },
+    }
 
-        //E.g. throw k 2 => this is used to evaluate a block of the type `callcc k in e`
-        Exp::Throwcc(k,e) => {
-            let res=eval_expression(e,stack,stack_start, false, "".to_string())?;
-            if in_call {return Result::Err(Error{msg: String::from(param), v:res})};
-            return Result::Err(Error{msg: String::from(k.name.to_string()), v:res})
-        },
+    /**
+     * Binds the next element to the pattern of a for loop and evaluates the body. The variables of
+     * the previous iteration are popped, so a fresh binding is pushed on the stack for every element
+     */
+    fn next_iteration(&mut self, exp: &'static Exp, mut items: Items, slot: usize, env: Env) -> Control {
+        self.stack.truncate(slot);
+        let (pattern, body) = match exp {
+            Exp::For(pattern, _, body, _) => (pattern, body),
+            _ => panic!("Expected a for loop")
+        };
+        let item: StackValue = match items.next() {
+            Result::Ok(Option::Some(item)) => item,
+            Result::Ok(Option::None) => return Control::Value(V::Val(Value::Unit)),
+            Result::Err(err) => return Control::Signal(err.into())
+        };
+        for _ in 0..pattern.num_vars() {
+            self.stack.push(Slot::new(StackValue::unit()));
+        }
+        if let Result::Err(err) = bind_pattern(pattern, V::Ptr(item), &mut self.stack, env.stack_start) {
+            return Control::Signal(err.into())
+        }
+        self.frames.push(Frame::ForBody(exp, items, slot, env));
+        Control::Eval(body, env)
+    }
 
-        //calls the current continuation as k and then evaluates the expression e. 
-        //If k is thrown inside e with `throw k m`, then `callcc k in e` evaluates to m
-        Exp::Callcc(k,e) => {
-            let res: Result<V, Error> = eval_expression(e,stack,stack_start, false, "".to_string());
-            match res {
-                Result::Ok(_) => return Result::Ok(res?),
-                Result::Err(Error{msg, v}) => {
-                    if (msg.len()>18 && msg.substring(0,18).eq(&"uncaught exception".to_string())) { return Result::Err(Error{msg: msg, v: v})};
-                    if (msg.eq(&k.name.to_string())) {return Result::Ok(v)};
-                    return eval_expression(e,stack,stack_start, false, "".to_string())
-                }
-                
+    /**
+     * Evaluates the body of the first arm, from the given one, whose pattern matches the value and whose guard is true
+     */
+    fn match_arms(&mut self, exp: &'static Exp, value: StackValue, first_arm: usize, env: Env) -> Control {
+        let arms: &'static Vec<(Pattern, Option<Exp>, Exp)> = match exp {
+            Exp::Match(_, arms) => arms,
+            _ => panic!("Expected a match")
+        };
+        // The variables of the pattern of each arm are pushed on the stack, like in a let declaration
+        let slot: usize = self.stack.len();
+        for (arm, (pattern, guard, body)) in arms.iter().enumerate().skip(first_arm) {
+            for _ in 0..pattern.num_vars() {
+                self.stack.push(Slot::new(StackValue::unit()));
             }
-        },
+            if match_pattern(pattern, value, &mut self.stack, env.stack_start) {
+                match guard {
+                    Option::Some(_) => self.frames.push(Frame::MatchGuard(exp, arm, value, slot, env)),
+                    Option::None => self.frames.push(Frame::Truncate(slot))
+                };
+                return Control::Eval(guard.as_ref().unwrap_or(body), env)
+            }
+            self.stack.truncate(slot);
+        }
+        Control::Signal(Error{msg: format!("No match arm matches value {}", V::Ptr(value)), v:Val(Unit)}.into())
+    }
 
-        Exp::Function(args, body) => {
-            Result::Ok(V::Val(Value::Fn(Function { num_args: args.len(), external_values: Vec::new(), body: body.clone() })))
-        },
+    /**
+     * Evaluates the next element of a list, tuple, map, struct, enum variant or function call,
+     * or builds the value once all of them are evaluated. Map keys and values alternate
+     */
+    fn next_element(&mut self, exp: &'static Exp, values: Vec<StackValue>, env: Env) -> Control {
+        let i: usize = values.len();
+        let next: Option<&'static Exp> = match exp {
+            Exp::List(exps) | Exp::Tuple(exps) | Exp::EnumInstance(_, _, exps) | Exp::Perform(_, exps) => exps.get(i),
+            Exp::Map(entries) => entries.get(i / 2).map(|(key, value)| if i.is_multiple_of(2) {key} else {value}),
+            Exp::StructInstance(_, fields) => fields.get(i).map(|(_, exp)| exp),
+            Exp::FunctionCall(callable, args) => if i == 0 { Option::Some(callable) } else { args.get(i - 1) },
+            _ => panic!("Expected an expression with elements")
+        };
+        if let Option::Some(next) = next {
+            self.frames.push(Frame::Elements(exp, values, env));
+            return Control::Eval(next, env)
+        }
+        let value: Value = match exp {
+            Exp::List(_) => Value::List(values),
+            Exp::Tuple(_) => Value::Tuple(values),
+            Exp::EnumInstance(name, variant, _) => Value::Enum(Variant{name: name.clone(), variant: variant.clone(), values}),
+            Exp::Map(_) => {
+                let mut map: IndexMap<Value, StackValue> = IndexMap::with_capacity(values.len() / 2);
+                for entry in values.chunks(2) {
+                    // The keys were checked when they were evaluated
+                    let key: Value = match map_key(V::Ptr(entry[0]).as_ref()) {
+                        Result::Ok(key) => key,
+                        Result::Err(err) => return Control::Signal(err.into())
+                    };
+                    map.insert(key, entry[1]);
+                }
+                Value::Map(map)
+            },
+            Exp::StructInstance(name, fields) => Value::Struct(Struct{
+                name: name.clone(),
+                fields: fields.iter().map(|(field, _)| field.clone()).zip(values).collect()
+            }),
+            Exp::Perform(effect, _) => return self.perform(effect, values),
+            // Evaluate all the arguments before pushing them, so that they are evaluated in the caller scope
+            _ => return self.apply(values[0], values[1..].to_vec())
+        };
+        Control::Value(V::Val(value))
+    }
 
-        Exp::FunctionCall(callable, args) => {
-            match eval_expression(callable, stack, stack_start, false, "".to_string())?.as_ref() {
-                Value::Fn(function) => {
-                    if args.len() != function.num_args {
-                        return Result::Err(Error { msg: format!("Wrong number of arguments. Expected {}, found {}", function.num_args, args.len()), v:Val(Unit) })
+    /**
+     * Calls a function with arguments that are already evaluated. The call must be checked with `check_call`.
+     * A call in tail position replaces the variables of the calling function, so loops written as recursive
+     * functions run in constant space
+     */
+    fn apply(&mut self, function_ptr: StackValue, arg_values: Vec<StackValue>) -> Control {
+        match function_ptr.as_ref() {
+            Value::Fn(function) => {
+                let function_stack_start: usize = match self.frames.last() {
+                    Option::Some(Frame::Call(slot)) => *slot,
+                    _ => {
+                        self.frames.push(Frame::Call(self.stack.len()));
+                        self.stack.len()
                     }
-                    let function_stack_start: usize = stack.len();
-                    for arg in args {
-                        match eval_expression(arg, stack, stack_start, false, "".to_string())? {
-                            V::Ptr(ptr) => stack.push(ptr),
-                            V::Val(value) => stack.push(StackValue::from_box(Box::new(value)))
-                        };
-                    };
-                    let result: V = eval_expression(function.body.as_ref(), stack, function_stack_start, true, exp_to_string(&args[0]))?;
-                    for _ in 0..function.num_args {
-                        stack.pop();
+                };
+                self.stack.truncate(function_stack_start);
+                self.stack.extend(arg_values.into_iter().map(Slot::new));
+                Control::Eval(function.body, Env { stack_start: function_stack_start, closure: function_ptr })
+            },
+            Value::NativeFn(function) => match (function.function)(&arg_values) {
+                Result::Ok(v) => Control::Value(v),
+                Result::Err(err) => match ESCAPE.with(|escape| escape.borrow_mut().take()) {
+                    Option::Some((continuation, value)) => self.resume_continuation(continuation, value),
+                    Option::None => match threads::take_request() {
+                        Option::Some(op) => self.wait(op),
+                        Option::None => Control::Signal(err.into())
                     }
-                    Result::Ok(result)
-                },
-                _ => return Result::Err(Error{msg: String::from("Expression is not callable"), v:Val(Unit)})
-            }
-        },
-
-        Exp::Seq(exp1, exp2) => {
-            eval_expression(exp1, stack, stack_start, false, "".to_string())?;
-            eval_expression(exp2, stack, stack_start, false, "".to_string())
-        },
+                }
+            },
+            // A continuation is called like a function that accepts at most one argument
+            Value::Continuation(continuation) => {
+                let value: StackValue = arg_values.first().copied().unwrap_or(StackValue::unit());
+                self.resume_continuation(Box::new(continuation.clone()), value)
+            },
+            _ => panic!("Called a value that is not a function")
+        }
+    }
 
-        Exp::Sum(exp1, exp2) => {
-            let (val1, val2) = double_eval(exp1, exp2, stack, stack_start)?;
-            Result::Ok(V::Val(sum(val1.as_ref(), val2.as_ref())?))
-        },
+    /**
+     * Evaluates the innermost handler of the effect that accepts the number of arguments. The handler
+     * replaces its handle expression, and the continuation resumes the body of handle with the handler in place
+     */
+    fn perform(&mut self, effect: &String, args: Vec<StackValue>) -> Control {
+        let mut handler: Option<(usize, &'static Handler, usize, Env)> = Option::None;
+        for (i, frame) in self.frames.iter().enumerate().rev() {
+            if let Frame::Handle(Exp::Handle(_, handlers), slot, env) = frame {
+                let found = handlers.iter().find(|(name, pattern, _, _)| match pattern {
+                    Pattern::Tuple(patterns) => name == effect && patterns.len() == args.len(),
+                    _ => false
+                });
+                if let Option::Some(found) = found {
+                    handler = Option::Some((i, found, *slot, *env));
+                    break
+                }
+            }
+        }
+        let (frame, (_, pattern, _, body), slot, env) = match handler {
+            Option::Some(handler) => handler,
+            // The handlers of the machines that called a native function can not be reached from this machine
+            Option::None if !self.thread && MACHINES.with(|machines| machines.borrow().0.len() > 1) => return Control::Signal(Error{
+                msg: format!("Effect {} performed in a function called by a native function must be handled inside it", effect), v:Val(Unit)
+            }.into()),
+            Option::None => return Control::Signal(Error{msg: format!("Unhandled effect {}", effect), v:Val(Unit)}.into())
+        };
+        let continuation: Continuation = Continuation {
+            machine: self.id, frames: self.frames.split_off(frame), stack: self.stack.clone(), delimited: true
+        };
+        self.stack.truncate(slot);
+        for _ in 0..pattern.num_vars() {
+            self.stack.push(Slot::new(StackValue::unit()));
+        }
+        if let Result::Err(err) = bind_pattern(pattern, V::Val(Value::Tuple(args)), &mut self.stack, env.stack_start) {
+            return Control::Signal(err.into())
+        }
+        self.stack.push(Slot::new(StackValue::from_box(Box::new(Value::Continuation(continuation)))));
+        self.frames.push(Frame::Truncate(slot));
+        Control::Eval(body, env)
+    }
 
-        Exp::Sub(exp1, exp2) => {
-            let (val1, val2) = double_eval(exp1, exp2, stack, stack_start)?;
-            Result::Ok(V::Val(sub(val1.as_ref(), val2.as_ref())?))
-        },
+    /**
+     * Suspends the thread until the operation can be performed. The main program runs the threads instead
+     */
+    fn wait(&mut self, op: Op) -> Control {
+        if self.thread {
+            let continuation: Continuation = Continuation {
+                machine: self.id,
+                frames: mem::take(&mut self.frames),
+                stack: self.stack.clone(),
+                delimited: true
+            };
+            Control::Signal(Signal::Wait(Box::new(continuation), op))
+        } else {
+            threads::block_on(op).into()
+        }
+    }
 
-        Exp::Mul(exp1, exp2) => {
-            let (val1, val2) = double_eval(exp1, exp2, stack, stack_start)?;
-            Result::Ok(V::Val(mul(val1.as_ref(), val2.as_ref())?))
-        },
+    /**
+     * Replaces the rest of the evaluation with the one captured by the continuation, where `callcc` evaluates to the value.
+     * Continuations captured by an enclosing machine leave this machine first
+     */
+    fn resume_continuation(&mut self, continuation: Box<Continuation>, value: StackValue) -> Control {
+        // Delimited continuations do not refer to the frames below them, so any machine can resume them
+        if continuation.delimited {
+            let Continuation { machine: _, frames, stack, delimited: _ } = *continuation;
+            let caller_stack: Vec<Slot> = mem::replace(&mut self.stack, stack);
+            self.frames.push(Frame::Resume(caller_stack));
+            self.frames.extend(frames);
+            Control::Value(V::Ptr(value))
+        } else if continuation.machine == self.id {
+            let Continuation { machine: _, frames, stack, delimited: _ } = *continuation;
+            self.frames = frames;
+            self.stack = stack;
+            Control::Value(V::Ptr(value))
+        } else if is_running(continuation.machine) {
+            Control::Signal(Signal::Escape(continuation, value))
+        } else {
+            Control::Signal(Error{msg: String::from("Continuation resumed after the evaluation that captured it has ended"), v:Val(Unit)}.into())
+        }
+    }
+}
 
-        Exp::Mod(exp1, exp2) => {
-            let (val1, val2) = double_eval(exp1, exp2, stack, stack_start)?;
-            Result::Ok(V::Val(modulo(val1.as_ref(), val2.as_ref())?))
-        },
+fn is_target(target: &Option<String>, exp: &Exp) -> bool {
+    let label: &Option<String> = match exp {
+        Exp::While(_, _, label) | Exp::For(_, _, _, label) => label,
+        _ => panic!("Expected a loop")
+    };
+    target.is_none() || target == label
+}
 
-        Exp::Div(exp1, exp2) => {
-            let (val1, val2) = double_eval(exp1, exp2, stack, stack_start)?;
-            Result::Ok(V::Val(div(val1.as_ref(), val2.as_ref())?))
-        },
+fn read_variable(exp: &Exp, stack: &[Slot], env: Env) -> StackValue {
+    variable_slot(exp, stack, env).get()
+}
 
-        Exp::Lt(exp1, exp2) => {
-            let (val1, val2) = double_eval(exp1, exp2, stack, stack_start)?;
-            Result::Ok(V::Val(lt(val1.as_ref(), val2.as_ref())?))
-        },
+/**
+ * Returns the slot of a variable of the current scope, or of a variable captured by the current function
+ */
+fn variable_slot(exp: &Exp, stack: &[Slot], env: Env) -> Slot {
+    match exp {
+        Exp::Var(x) => stack[x.scope + env.stack_start],
+        Exp::ExternalVar(x) => external_slots(&env.closure)[x.scope],
+        _ => panic!("Expected a variable")
+    }
+}
 
-        Exp::Lte(exp1, exp2) => {
-            let (val1, val2) = double_eval(exp1, exp2, stack, stack_start)?;
-            Result::Ok(V::Val(lte(val1.as_ref(), val2.as_ref())?))
-        },
+/**
+ * Returns the variables captured by the function that is currently being executed.
+ * `closure` must point to a function value.
+ */
+fn external_slots(closure: &StackValue) -> &[Slot] {
+    match closure.as_ref() {
+        Value::Fn(function) => &function.external_values,
+        _ => panic!("Captured variable used outside of a function body")
+    }
+}
 
-        Exp::Gt(exp1, exp2) => {
-            let (val1, val2) = double_eval(exp1, exp2, stack, stack_start)?;
-            Result::Ok(V::Val(gt(val1.as_ref(), val2.as_ref())?))
-        },
+/**
+ * Creates a function value. The function shares the captured variables with the enclosing scope,
+ * so assignments on either side are visible to the other
+ */
+fn new_function(exp: &'static Exp, stack: &[Slot], env: Env) -> Value {
+    match exp {
+        Exp::Function(args, captures, body) => Value::Fn(Function {
+            num_args: args.len(),
+            external_values: captures.iter().map(|capture| variable_slot(capture, stack, env)).collect(),
+            body
+        }),
+        _ => panic!("Expected a function")
+    }
+}
 
-        Exp::Gte(exp1, exp2) => {
-            let (val1, val2) = double_eval(exp1, exp2, stack, stack_start)?;
-            Result::Ok(V::Val(gte(val1.as_ref(), val2.as_ref())?))
-        },
+/**
+ * Pushes a group of function declarations to the stack. The slots of the functions are pushed first,
+ * so the functions can capture each other
+ */
+pub fn push_function_decls(functions: &'static [Exp], stack: &mut Vec<Slot>, stack_start: usize, closure: StackValue) -> Result<(), Error> {
+    let env: Env = Env { stack_start, closure };
+    let decl_stack_start: usize = stack.len();
+    for _ in functions {
+        stack.push(Slot::new(StackValue::from_box(Box::new(Value::Unit))))
+    };
+    for (i, function) in functions.iter().enumerate() {
+        if !matches!(function, Exp::Function(_, _, _)) {
+            return Result::Err(Error{msg: String::from("Expected function in function declaration"), v:Val(Unit)})
+        }
+        let value: Value = new_function(function, stack, env);
+        stack[decl_stack_start + i].set(StackValue::from_box(Box::new(value)));
+    };
+    Result::Ok(())
+}
 
-        Exp::Eq(exp1, exp2) => {
-            let (val1, val2) = double_eval(exp1, exp2, stack, stack_start)?;
-            Result::Ok(V::Val(eq(val1.as_ref(), val2.as_ref())?))
+/**
+ * Returns an error if the value is not a function that accepts the given number of arguments
+ */
+fn check_call(function_ptr: StackValue, num_args: usize) -> Result<(), Error> {
+    let expected: Option<usize> = match if function_ptr.is_unit() { &Value::Unit } else { function_ptr.as_ref() } {
+        Value::Fn(function) => Option::Some(function.num_args),
+        Value::NativeFn(function) => function.num_args,
+        Value::Continuation(_) if num_args <= 1 => Option::None,
+        Value::Continuation(_) => return Result::Err(Error{msg: format!("A continuation is resumed with at most one value, found {}", num_args), v:Val(Unit)}),
+        _ => return Result::Err(Error{msg: String::from("Expression is not callable"), v:Val(Unit)})
+    };
+    match expected {
+        Option::Some(expected) if expected != num_args => {
+            Result::Err(Error { msg: format!("Wrong number of arguments. Expected {}, found {}", expected, num_args), v:Val(Unit) })
         },
+        _ => Result::Ok(())
+    }
+}
 
-        Exp::Neq(exp1, exp2) => {
-            let (val1, val2) = double_eval(exp1, exp2, stack, stack_start)?;
-            Result::Ok(V::Val(neq(val1.as_ref(), val2.as_ref())?))
-        },
+fn binary_operator(exp: &Exp, val1: V, val2: V) -> Result<V, Error> {
+    let (val1, val2) = (val1.as_ref(), val2.as_ref());
+    let value: Value = match exp {
+        Exp::ListSelection(_, _) => return select(val1, val2),
+        Exp::Sum(_, _) => sum(val1, val2)?,
+        Exp::Sub(_, _) => sub(val1, val2)?,
+        Exp::Mul(_, _) => mul(val1, val2)?,
+        Exp::Pow(_, _) => pow(val1, val2)?,
+        Exp::Mod(_, _) => modulo(val1, val2)?,
+        Exp::Div(_, _) => div(val1, val2)?,
+        Exp::Lt(_, _) => lt(val1, val2)?,
+        Exp::Lte(_, _) => lte(val1, val2)?,
+        Exp::Gt(_, _) => gt(val1, val2)?,
+        Exp::Gte(_, _) => gte(val1, val2)?,
+        Exp::Eq(_, _) => eq(val1, val2)?,
+        Exp::Neq(_, _) => neq(val1, val2)?,
+        Exp::In(_, _) => contains(val2, val1)?,
+        Exp::Range(_, _, inclusive) => range(val1, val2, *inclusive)?,
+        _ => panic!("Expected a binary operator")
+    };
+    Result::Ok(V::Val(value))
+}
 
-        Exp::And(exp1, exp2) => {
-            let val_exp1 = eval_expression(exp1, stack, stack_start, false, "".to_string())?;
-            if (!val_exp1.as_bool()) {
-                Result::Ok(V::Val(Value::Bool(false)))
-            }
-            else {
-                let val_exp2 = eval_expression(exp2, stack, stack_start, false, "".to_string())?;
-                Result::Ok(V::Val(Value::Bool(val_exp2.as_bool())))
-            }
+fn select(list: &Value, index: &Value) -> Result<V, Error> {
+    let value = match (list, index) {
+        (Value::List(values), Value::Int(i)) => values.get(*i as usize)
+            .ok_or(Error{msg: String::from("List index out of range"), v:Val(Unit)})?,
+        (Value::Tuple(values), Value::Int(i)) => values.get(*i as usize)
+            .ok_or(Error{msg: String::from("Tuple index out of range"), v:Val(Unit)})?,
+        (Value::Map(map), key) => map.get(key)
+            .ok_or(Error{msg: format!("Key {} not found in map", key), v:Val(Unit)})?,
+        // Strings are indexed by character
+        (Value::Str(s), Value::Int(i)) => return match usize::try_from(*i).ok().and_then(|i| s.chars().nth(i)) {
+            Option::Some(c) => Result::Ok(V::Val(Value::Str(c.to_string()))),
+            Option::None => Result::Err(Error{msg: String::from("String index out of range"), v:Val(Unit)})
         },
+        (list, index) => return Result::Err(Error{msg: format!("Can not select {} from {}", index, list), v:Val(Unit)})
+    };
+    Result::Ok(V::Ptr(*value))
+}
 
-        Exp::Or(exp1, exp2) => {
-            let val_exp1 = eval_expression(exp1, stack, stack_start, false, "".to_string())?;
-            if (val_exp1.as_bool()) {
-                Result::Ok(V::Val(Value::Bool(true)))
-            }
-            else {
-                let val_exp2 = eval_expression(exp2, stack, stack_start, false, "".to_string())?;
-                Result::Ok(V::Val(Value::Bool(val_exp2.as_bool())))
-            }
+fn assign_index(list: &mut StackValue, index: V, right_value: StackValue) -> Result<V, Error> {
+    let list: &mut Vec<StackValue> = match list.as_mut_ref() {
+        Value::List(list) => list,
+        // Assigning to a missing key adds a new entry to the map
+        Value::Map(map) => {
+            map.insert(map_key(index.as_ref())?, right_value);
+            return Result::Ok(V::Ptr(StackValue::unit()))
         },
+        _ => return Result::Err(Error{msg: String::from("Expected list value before list selection"), v:Val(Unit)})
+    };
+    let index: usize = match index.as_ref() {
+        Value::Int(i) => *i as usize,
+        _ => return Result::Err(Error{msg: String::from("Expected number in list selection"), v:Val(Unit)})
+    };
+    if index >= list.len() {
+        return Result::Err(Error{msg: String::from("List index out of range"), v:Val(Unit)})
+    }
+    list[index] = right_value;
+    Result::Ok(V::Ptr(StackValue::unit()))
+}
 
-        Exp::Not(exp1) => {
-            let v = eval_expression(exp1, stack, stack_start, false, "".to_string())?;
-            Result::Ok(V::Val(Value::Bool(!v.as_bool())))
-        }
+/**
+ * Integers are promoted to floats when combined with a float.
+ * Returns both operands as floats if at least one of them is a float and the other one is a number.
+ */
+fn float_operands(val1: &Value, val2: &Value) -> Option<(f64, f64)> {
+    match (val1, val2) {
+        (Value::Float(f1), Value::Float(f2)) => Option::Some((*f1, *f2)),
+        (Value::Int(i1), Value::Float(f2)) => Option::Some((*i1 as f64, *f2)),
+        (Value::Float(f1), Value::Int(i2)) => Option::Some((*f1, *i2 as f64)),
+        (Value::BigInt(i1), Value::Float(f2)) => Option::Some((i1.to_f64()?, *f2)),
+        (Value::Float(f1), Value::BigInt(i2)) => Option::Some((*f1, i2.to_f64()?)),
+        _ => Option::None
     }
 }
 
-fn double_eval(exp1: &Exp, exp2: &Exp, stack: &mut Vec<StackValue>, stack_start: usize) -> Result<(V, V), Error> {
-    let v1 = eval_expression(exp1, stack, stack_start, false, "".to_string())?;
-    let v2 = eval_expression(exp2, stack, stack_start, false, "".to_string())?;
-    Result::Ok((v1, v2))
+/**
+ * Returns both operands as big integers if at least one of them is a big integer and the other one is an integer.
+ * Operations between two `Value::Int` are computed again with big integers only when they overflow.
+ */
+fn big_int_operands(val1: &Value, val2: &Value) -> Option<(BigInt, BigInt)> {
+    match (val1, val2) {
+        (Value::BigInt(i1), Value::BigInt(i2)) => Option::Some((i1.clone(), i2.clone())),
+        (Value::Int(i1), Value::BigInt(i2)) => Option::Some((BigInt::from(*i1), i2.clone())),
+        (Value::BigInt(i1), Value::Int(i2)) => Option::Some((i1.clone(), BigInt::from(*i2))),
+        _ => Option::None
+    }
 }
 
 fn sum(val1: &Value, val2: &Value) -> Result<Value, Error> {
+    if let Option::Some((f1, f2)) = float_operands(val1, val2) {
+        return Result::Ok(Value::Float(f1 + f2))
+    }
+    if let Option::Some((i1, i2)) = big_int_operands(val1, val2) {
+        return Result::Ok(Value::from_big_int(i1 + i2))
+    }
     match (val1, val2) {
-        (Value::Int(i1), Value::Int(i2)) => Result::Ok(Value::Int(i1 + i2)),
+        (Value::Int(i1), Value::Int(i2)) => Result::Ok(match i1.checked_add(*i2) {
+            Option::Some(i) => Value::Int(i),
+            Option::None => Value::from_big_int(BigInt::from(*i1) + i2)
+        }),
 
         (Value::List(l1), Value::List(l2)) => {
             let mut list = l1.clone();
@@ -345,87 +1232,331 @@ fn sum(val1: &Value, val2: &Value) -> Result<Value, Error> {
 }
 
 fn sub(val1: &Value, val2: &Value) -> Result<Value, Error> {
+    if let Option::Some((f1, f2)) = float_operands(val1, val2) {
+        return Result::Ok(Value::Float(f1 - f2))
+    }
+    if let Option::Some((i1, i2)) = big_int_operands(val1, val2) {
+        return Result::Ok(Value::from_big_int(i1 - i2))
+    }
     match (val1, val2) {
-        (Value::Int(i1), Value::Int(i2)) => Result::Ok(Value::Int(i1 - i2)),
+        (Value::Int(i1), Value::Int(i2)) => Result::Ok(match i1.checked_sub(*i2) {
+            Option::Some(i) => Value::Int(i),
+            Option::None => Value::from_big_int(BigInt::from(*i1) - i2)
+        }),
         _ => Result::Err(Error{msg: format!("Unsupported - operator for values {}, {}",val1, val2), v:Val(Unit)})
     }
 }
 
 fn mul(val1: &Value, val2: &Value) -> Result<Value, Error> {
+    if let Option::Some((f1, f2)) = float_operands(val1, val2) {
+        return Result::Ok(Value::Float(f1 * f2))
+    }
+    if let Option::Some((i1, i2)) = big_int_operands(val1, val2) {
+        return Result::Ok(Value::from_big_int(i1 * i2))
+    }
     match (val1, val2) {
-        (Value::Int(i1), Value::Int(i2)) => Result::Ok(Value::Int(i1 * i2)),
+        (Value::Int(i1), Value::Int(i2)) => Result::Ok(match i1.checked_mul(*i2) {
+            Option::Some(i) => Value::Int(i),
+            Option::None => Value::from_big_int(BigInt::from(*i1) * i2)
+        }),
         _ => Result::Err(Error{msg: format!("Unsupported * operator for values {}, {}",val1, val2), v:Val(Unit)})
     }
 }
 
+/**
+ * Builds the error of `throw v`. A try-catch block handles it and binds its catch variable to `v`
+ */
+fn thrown(v: V) -> Error {
+    Error{msg: format!("uncaught exception {}", v), v}
+}
+
+/**
+ * Builds the error raised by a failing operation of the language or of a native function, eg: a division by zero
+ * or a builtin called with a wrong argument. It behaves like `throw msg`, so the catch variable is bound to the string `msg`
+ */
+pub fn exception(msg: &str) -> Error {
+    thrown(Val(Value::Str(String::from(msg))))
+}
+
 fn div(val1: &Value, val2: &Value) -> Result<Value, Error> {
+    if let Option::Some((f1, f2)) = float_operands(val1, val2) {
+        return Result::Ok(Value::Float(f1 / f2))
+    }
+    if let Option::Some((i1, i2)) = big_int_operands(val1, val2) {
+        if i2.is_zero() { return Result::Err(exception("DivZero")) }
+        return Result::Ok(Value::from_big_int(i1 / i2))
+    }
     match (val1, val2) {
-        (Value::Int(i1), Value::Int(i2)) => Result::Ok(Value::Int(i1 / i2)),
+        (Value::Int(_), Value::Int(0)) => Result::Err(exception("DivZero")),
+        (Value::Int(i1), Value::Int(i2)) => Result::Ok(match i1.checked_div(*i2) {
+            Option::Some(i) => Value::Int(i),
+            Option::None => Value::from_big_int(BigInt::from(*i1) / i2)
+        }),
         _ => Result::Err(Error{msg: format!("Unsupported / operator for values {}, {}",val1, val2), v:Val(Unit)})
     }
 }
 
 fn modulo(val1: &Value, val2: &Value) -> Result<Value, Error> {
+    if let Option::Some((f1, f2)) = float_operands(val1, val2) {
+        return Result::Ok(Value::Float(f1 % f2))
+    }
+    if let Option::Some((i1, i2)) = big_int_operands(val1, val2) {
+        if i2.is_zero() { return Result::Err(exception("ModZero")) }
+        return Result::Ok(Value::from_big_int(i1 % i2))
+    }
     match (val1, val2) {
-        (Value::Int(i1), Value::Int(i2)) => Result::Ok(Value::Int(i1 % i2)),
+        (Value::Int(_), Value::Int(0)) => Result::Err(exception("ModZero")),
+        (Value::Int(i1), Value::Int(i2)) => Result::Ok(match i1.checked_rem(*i2) {
+            Option::Some(i) => Value::Int(i),
+            Option::None => Value::from_big_int(BigInt::from(*i1) % i2)
+        }),
         _ => Result::Err(Error{msg: format!("Unsupported % operator for values {}, {}",val1, val2), v:Val(Unit)})
     }
 }
 
+/**
+ * Raises a number to a power. Integers raised to a non negative integer are computed exactly,
+ * promoting to big integers on overflow, while a negative exponent gives a float
+ */
+pub fn pow(val1: &Value, val2: &Value) -> Result<Value, Error> {
+    if let Option::Some((f1, f2)) = float_operands(val1, val2) {
+        return Result::Ok(Value::Float(f1.powf(f2)))
+    }
+    let base: BigInt = match val1 {
+        Value::Int(i) => BigInt::from(*i),
+        Value::BigInt(i) => i.clone(),
+        _ => return Result::Err(Error{msg: format!("Unsupported ** operator for values {}, {}",val1, val2), v:Val(Unit)})
+    };
+    match val2 {
+        Value::Int(i) if *i < 0 => Result::Ok(Value::Float(base.to_f64().unwrap_or(f64::NAN).powf(*i as f64))),
+        Value::Int(i) => match u32::try_from(*i) {
+            Result::Ok(exp) => Result::Ok(match val1 {
+                Value::Int(b) => match b.checked_pow(exp) {
+                    Option::Some(i) => Value::Int(i),
+                    Option::None => Value::from_big_int(base.pow(exp))
+                },
+                _ => Value::from_big_int(base.pow(exp))
+            }),
+            Result::Err(_) => Result::Err(Error{msg: format!("Exponent {} is too large", i), v:Val(Unit)})
+        },
+        Value::BigInt(i) => Result::Err(Error{msg: format!("Exponent {} is too large", i), v:Val(Unit)}),
+        _ => Result::Err(Error{msg: format!("Unsupported ** operator for values {}, {}",val1, val2), v:Val(Unit)})
+    }
+}
+
+/**
+ * Assigns the parts of a value to the variables of a pattern. Their slots must already be on the stack
+ */
+pub fn bind_pattern(pattern: &Pattern, value: V, stack: &mut [Slot], stack_start: usize) -> Result<(), Error> {
+    let value: StackValue = match value {
+        V::Ptr(ptr) => ptr,
+        V::Val(value) => StackValue::from_box(Box::new(value))
+    };
+    if match_pattern(pattern, value, stack, stack_start) {
+        Result::Ok(())
+    } else {
+        Result::Err(Error{msg: format!("Value {} does not match the declared pattern", V::Ptr(value)), v:Val(Unit)})
+    }
+}
+
+/**
+ * Checks if a value matches a pattern. The matched parts of the value are assigned to the variables
+ * of the pattern, whose slots must already be on the stack
+ */
+fn match_pattern(pattern: &Pattern, value: StackValue, stack: &mut [Slot], stack_start: usize) -> bool {
+    match pattern {
+        // Every binding is a new variable: continuations that bind it again do not share it
+        Pattern::Var(var) => {
+            stack[stack_start + var.scope] = Slot::new(value);
+            true
+        },
+        Pattern::Wildcard => true,
+        Pattern::Literal(c) => value.value_eq(&StackValue::from_box(Box::new(Value::from_const(c)))),
+        Pattern::Tuple(patterns) | Pattern::List(patterns) | Pattern::Constructor(_, _, patterns) => {
+            if value.is_unit() { return false }
+            let values: &Vec<StackValue> = match (pattern, value.as_ref()) {
+                (Pattern::Tuple(_), Value::Tuple(values)) => values,
+                (Pattern::List(_), Value::List(values)) => values,
+                (Pattern::Constructor(name, variant, _), Value::Enum(e)) if e.name == *name && e.variant == *variant => &e.values,
+                _ => return false
+            };
+            values.len() == patterns.len()
+                && patterns.iter().zip(values).all(|(pattern, value)| match_pattern(pattern, *value, stack, stack_start))
+        }
+    }
+}
+
+/**
+ * Returns the slot of a struct field. Error if the value is not a struct or has no such field
+ */
+fn struct_field<'a>(value: &'a mut V, field: &String) -> Result<&'a mut StackValue, Error> {
+    let is_unit: bool = match value {
+        V::Ptr(ptr) => ptr.is_unit(),
+        V::Val(_) => false
+    };
+    if is_unit {
+        return Result::Err(Error{msg: format!("Can not read field {} of unit", field), v:Val(Unit)})
+    }
+    match value.as_mut_ref() {
+        Value::Struct(s) => {
+            let name: &String = &s.name;
+            s.fields.get_mut(field).ok_or_else(|| {
+                let msg: String = if name != MODULE_STRUCT {
+                    format!("Struct {} has no field {}", name, field)
+                } else if field.starts_with('_') {
+                    format!("Binding {} is private to the module, because its name starts with _", field)
+                } else {
+                    format!("Module has no binding {}", field)
+                };
+                Error{msg, v:Val(Unit)}
+            })
+        },
+        other => Result::Err(Error{msg: format!("Can not read field {} of {}", field, other), v:Val(Unit)})
+    }
+}
+
+/**
+ * Returns the value to be used as a map key. Error if the value can not be hashed
+ */
+fn map_key(value: &Value) -> Result<Value, Error> {
+    value.clone_key().ok_or(Error{msg: format!("Value {} can not be used as a map key", value), v:Val(Unit)})
+}
+
+/**
+ * Implements `elem in collection`: list elements, map keys and substrings
+ */
+fn contains(collection: &Value, elem: &Value) -> Result<Value, Error> {
+    match (collection, elem) {
+        (Value::List(list), elem) => Result::Ok(Value::Bool(list.iter().any(|value| {
+            if value.is_unit() { *elem == Value::Unit } else { value.as_ref() == elem }
+        }))),
+        (Value::Map(map), key) => Result::Ok(Value::Bool(map.contains_key(key))),
+        (Value::Str(s), Value::Str(sub)) => Result::Ok(Value::Bool(s.contains(sub.as_str()))),
+        (Value::Range(start, end), Value::Int(i)) => Result::Ok(Value::Bool(start <= i && i < end)),
+        (Value::Range(_, _), Value::BigInt(_)) => Result::Ok(Value::Bool(false)),
+        _ => Result::Err(Error{msg: format!("Unsupported in operator for values {}, {}", elem, collection), v:Val(Unit)})
+    }
+}
+
+fn range(val1: &Value, val2: &Value, inclusive: bool) -> Result<Value, Error> {
+    match (val1, val2) {
+        (Value::Int(start), Value::Int(end)) if !inclusive => Result::Ok(Value::Range(*start, *end)),
+        (Value::Int(start), Value::Int(end)) => match end.checked_add(1) {
+            Option::Some(end) => Result::Ok(Value::Range(*start, end)),
+            Option::None => Result::Err(Error{msg: format!("Range end {} is too large", end), v:Val(Unit)})
+        },
+        _ => Result::Err(Error{msg: format!("Unsupported range operator for values {}, {}", val1, val2), v:Val(Unit)})
+    }
+}
+
+/**
+ * Total order of numbers and strings, used to sort lists
+ */
+pub fn compare(val1: &Value, val2: &Value) -> Result<Ordering, Error> {
+    if let Option::Some((f1, f2)) = float_operands(val1, val2) {
+        return f1.partial_cmp(&f2).ok_or(Error{msg: format!("Can not compare {} and {}", val1, val2), v:Val(Unit)})
+    }
+    if let Option::Some((i1, i2)) = big_int_operands(val1, val2) {
+        return Result::Ok(i1.cmp(&i2))
+    }
+    match (val1, val2) {
+        (Value::Int(i1), Value::Int(i2)) => Result::Ok(i1.cmp(i2)),
+        (Value::Str(s1), Value::Str(s2)) => Result::Ok(s1.cmp(s2)),
+        _ => Result::Err(Error{msg: format!("Can not compare {} and {}", val1, val2), v:Val(Unit)})
+    }
+}
+
 fn lt(val1: &Value, val2: &Value) -> Result<Value, Error> {
+    if let Option::Some((f1, f2)) = float_operands(val1, val2) {
+        return Result::Ok(Value::Bool(f1 < f2))
+    }
+    if let Option::Some((i1, i2)) = big_int_operands(val1, val2) {
+        return Result::Ok(Value::Bool(i1 < i2))
+    }
     match (val1, val2) {
         (Value::Int(i1), Value::Int(i2)) => Result::Ok(Value::Bool(i1 < i2)),
+        (Value::Str(s1), Value::Str(s2)) => Result::Ok(Value::Bool(s1 < s2)),
         _ => Result::Err(Error{msg: format!("Unsupported < operator for values {}, {}",val1, val2), v:Val(Unit)})
     }
 }
 
 fn lte(val1: &Value, val2: &Value) -> Result<Value, Error> {
+    if let Option::Some((f1, f2)) = float_operands(val1, val2) {
+        return Result::Ok(Value::Bool(f1 <= f2))
+    }
+    if let Option::Some((i1, i2)) = big_int_operands(val1, val2) {
+        return Result::Ok(Value::Bool(i1 <= i2))
+    }
     match (val1, val2) {
         (Value::Int(i1), Value::Int(i2)) => Result::Ok(Value::Bool(i1 <= i2)),
+        (Value::Str(s1), Value::Str(s2)) => Result::Ok(Value::Bool(s1 <= s2)),
         _ => Result::Err(Error{msg: format!("Unsupported <= operator for values {}, {}",val1, val2), v:Val(Unit)})
     }
 }
 
 fn gt(val1: &Value, val2: &Value) -> Result<Value, Error> {
+    if let Option::Some((f1, f2)) = float_operands(val1, val2) {
+        return Result::Ok(Value::Bool(f1 > f2))
+    }
+    if let Option::Some((i1, i2)) = big_int_operands(val1, val2) {
+        return Result::Ok(Value::Bool(i1 > i2))
+    }
     match (val1, val2) {
         (Value::Int(i1), Value::Int(i2)) => Result::Ok(Value::Bool(i1 > i2)),
+        (Value::Str(s1), Value::Str(s2)) => Result::Ok(Value::Bool(s1 > s2)),
         _ => Result::Err(Error{msg: format!("Unsupported > operator for values {}, {}",val1, val2), v:Val(Unit)})
     }
 }
 
 fn gte(val1: &Value, val2: &Value) -> Result<Value, Error> {
+    if let Option::Some((f1, f2)) = float_operands(val1, val2) {
+        return Result::Ok(Value::Bool(f1 >= f2))
+    }
+    if let Option::Some((i1, i2)) = big_int_operands(val1, val2) {
+        return Result::Ok(Value::Bool(i1 >= i2))
+    }
     match (val1, val2) {
         (Value::Int(i1), Value::Int(i2)) => Result::Ok(Value::Bool(i1 >= i2)),
+        (Value::Str(s1), Value::Str(s2)) => Result::Ok(Value::Bool(s1 >= s2)),
         _ => Result::Err(Error{msg: format!("Unsupported >= operator for values {}, {}",val1, val2), v:Val(Unit)})
     }
 }
 
 fn eq(val1: &Value, val2: &Value) -> Result<Value, Error> {
+    if let Option::Some((f1, f2)) = float_operands(val1, val2) {
+        return Result::Ok(Value::Bool(f1 == f2))
+    }
+    if let Option::Some((i1, i2)) = big_int_operands(val1, val2) {
+        return Result::Ok(Value::Bool(i1 == i2))
+    }
     match (val1, val2) {
         (Value::Int(i1), Value::Int(i2)) => Result::Ok(Value::Bool(i1 == i2)),
         (Value::Bool(b1), Value::Bool(b2)) => Result::Ok(Value::Bool(b1 == b2)),
+        // Any value can be compared with null, eg: the result of read_line
+        (Value::Unit, Value::Unit) => Result::Ok(Value::Bool(true)),
+        (Value::Unit, _) | (_, Value::Unit) => Result::Ok(Value::Bool(false)),
+        (Value::Str(s1), Value::Str(s2)) => Result::Ok(Value::Bool(s1 == s2)),
+        (Value::List(_), Value::List(_)) | (Value::Tuple(_), Value::Tuple(_)) | (Value::Map(_), Value::Map(_))
+            | (Value::Struct(_), Value::Struct(_)) | (Value::Enum(_), Value::Enum(_)) => Result::Ok(Value::Bool(val1 == val2)),
         _ => Result::Err(Error{msg: format!("Unsupported == operator for values {}, {}",val1, val2), v:Val(Unit)})
     }
 }
 
 fn neq(val1: &Value, val2: &Value) -> Result<Value, Error> {
+    if let Option::Some((f1, f2)) = float_operands(val1, val2) {
+        return Result::Ok(Value::Bool(f1 != f2))
+    }
+    if let Option::Some((i1, i2)) = big_int_operands(val1, val2) {
+        return Result::Ok(Value::Bool(i1 != i2))
+    }
     match (val1, val2) {
         (Value::Int(i1), Value::Int(i2)) => Result::Ok(Value::Bool(i1 != i2)),
         (Value::Bool(b1), Value::Bool(b2)) => Result::Ok(Value::Bool(b1 != b2)),
+        (Value::Unit, Value::Unit) => Result::Ok(Value::Bool(false)),
+        (Value::Unit, _) | (_, Value::Unit) => Result::Ok(Value::Bool(true)),
+        (Value::Str(s1), Value::Str(s2)) => Result::Ok(Value::Bool(s1 != s2)),
+        (Value::List(_), Value::List(_)) | (Value::Tuple(_), Value::Tuple(_)) | (Value::Map(_), Value::Map(_))
+            | (Value::Struct(_), Value::Struct(_)) | (Value::Enum(_), Value::Enum(_)) => Result::Ok(Value::Bool(val1 != val2)),
         _ => Result::Err(Error{msg: format!("Unsupported != operator for values {}, {}",val1, val2), v:Val(Unit)})
     }
 }
-
-fn and(val1: &Value, val2: &Value) -> Result<Value, Error> {
-    match (val1, val2) {
-        (Value::Bool(b1), Value::Bool(b2)) => Result::Ok(Value::Bool(*b1 && *b2)),
-        _ => Result::Err(Error{msg: format!("Unsupported && operator for values {}, {}",val1, val2), v:Val(Unit)})
-    }
-}
-
-fn or(val1: &Value, val2: &Value) -> Result<Value, Error> {
-    match (val1, val2) {
-        (Value::Bool(b1), Value::Bool(b2)) => Result::Ok(Value::Bool(*b1 || *b2)),
-        _ => Result::Err(Error{msg: format!("Unsupported || operator for values {}, {}",val1, val2), v:Val(Unit)})
-    }
-}
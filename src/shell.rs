@@ -1,32 +1,21 @@
-use std::collections::HashMap;
-
 use rustyline::error::ReadlineError;
 use rustyline::{Editor};
 
+use crate::builtins::builtin_values;
 use crate::lexer::tokenize;
-use crate::parser::{parse_tokens, FunctionScope};
-use crate::semantics::{eval_expression};
-use crate::value::{Value, StackValue, V};
+use crate::parser::{parse_tokens, parse_function_decls, parse_pattern, declare_pattern, FunctionScope};
+use crate::semantics::{eval_in_scope, push_function_decls, bind_pattern};
+use crate::value::{StackValue, Slot, Value, V};
 
-use crate::expression::Exp;
-use crate::expression::Const;
-use crate::expression::Var;
+use crate::expression::{Exp, Pattern};
 
 use crate::token::Token;
-use crate::token::Operand;
 use crate::token::Operator;
 
 pub fn run_shell() {
-    let mut stack: Vec<StackValue> = Vec::new();
+    let mut stack: Vec<Slot> = builtin_values();
 
-    let main_scope: FunctionScope = FunctionScope {
-        input_vars: Vec::new(),
-        external_variables: Vec::new(),
-        // Current variable scope depth
-        var_scope: 0,
-        variable_map: HashMap::new()
-    };
-    let mut function_stack: Vec<FunctionScope> = vec![main_scope];
+    let mut function_stack: Vec<FunctionScope> = vec![FunctionScope::main()];
 
     let mut rl: Editor<()> = Editor::<()>::new().expect("Error creating editor");
     loop {
@@ -57,7 +46,7 @@ pub fn run_shell() {
  */
 fn handle_user_input(
     line: String,
-    stack: &mut Vec<StackValue>,
+    stack: &mut Vec<Slot>,
     function_stack: &mut Vec<FunctionScope>
 ) {
     // Tokenize string
@@ -70,6 +59,16 @@ fn handle_user_input(
     };
     if tokens.is_empty() { return }
 
+    // Function declarations are kept in scope for the rest of the session, like let declarations
+    match eval_function_decls(&mut tokens, stack, function_stack) {
+        Result::Ok(()) => (),
+        Result::Err(msg) => {
+            println!("{}", msg);
+            return
+        }
+    }
+    if tokens.is_empty() { return }
+
     // We need to handle let expression separately when in interactive mode
     let is_let: bool = match tokens.last() {
         Option::Some(Token::Let) => true,
@@ -78,7 +77,7 @@ fn handle_user_input(
     if is_let {
         match eval_let(&mut tokens, stack, function_stack) {
             // Increment scope after evaluating let
-            Result::Ok(new_scope) => (),
+            Result::Ok(()) => (),
             Result::Err(msg) => println!("{}", msg)
         }
         return
@@ -92,8 +91,10 @@ fn handle_user_input(
         }
     };
     // Evaluate expression
-    match eval_expression(&exp, stack, 0, false, "".to_string()) {
+    match eval_in_scope(exp, stack) {
         Result::Ok(V::Ptr(ptr)) => if ptr.is_unit() {} else {println!("{}", ptr.as_ref())},
+        // Statements like `println(x)` do not print their result
+        Result::Ok(V::Val(Value::Unit)) => (),
         Result::Ok(V::Val(value)) => {
             println!("{}", value);
         },
@@ -107,7 +108,7 @@ fn handle_user_input(
  */
 fn eval_let(
     tokens: &mut Vec<Token>,
-    stack: &mut Vec<StackValue>,
+    stack: &mut Vec<Slot>,
     function_stack: &mut Vec<FunctionScope>
 ) -> Result<(), String> {
     // Pop let token
@@ -115,10 +116,10 @@ fn eval_let(
         Option::Some(Token::Let) => (),
         _ => panic!("First token must be a let when calling handle_let function")
     };
-    // Pop variable token
-    let var_name: String = match tokens.pop() {
-        Option::Some(Token::Operand(Operand::Var(name))) => name,
-        _ => return Result::Err(String::from("SyntaxError: expected variable token"))
+    // Pop variable or tuple pattern tokens
+    let mut pattern: Pattern = match parse_pattern(tokens) {
+        Result::Ok(pattern) => pattern,
+        Result::Err(err) => return Result::Err(format!("SyntaxError: {}", err.msg))
     };
 
     // Pop "=" token
@@ -129,19 +130,55 @@ fn eval_let(
 
     let exp: Exp = match parse_tokens(tokens, function_stack) {
         Result::Ok(exp) => exp,
-        Result::Err(err) => return Result::Err(String::from(format!("SyntaxError: {}", err.msg)))
+        Result::Err(err) => return Result::Err(format!("SyntaxError: {}", err.msg))
     };
-    let val = match eval_expression(&exp, stack, 0, false, "".to_string()) {
+    let val = match eval_in_scope(exp, stack) {
         Result::Ok(val) => val,
         Result::Err(err) => return Result::Err(err.msg)
     };
 
     let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
-    function_scope.variable_map.insert(var_name, function_scope.var_scope);
-    function_scope.var_scope += 1;
-    match val {
-        V::Ptr(ptr) => stack.push(ptr),
-        V::Val(value) => stack.push(StackValue::from_box(Box::new(value)))
+    let (var_scope, variable_map) = (function_scope.var_scope, function_scope.variable_map.clone());
+    declare_pattern(&mut pattern, function_scope);
+    let slot: usize = stack.len();
+    for _ in 0..pattern.num_vars() {
+        stack.push(Slot::new(StackValue::unit()))
+    }
+    match bind_pattern(&pattern, val, stack, 0) {
+        Result::Ok(()) => Result::Ok(()),
+        Result::Err(err) => {
+            // Forget the variables of the pattern
+            stack.truncate(slot);
+            function_scope.var_scope = var_scope;
+            function_scope.variable_map = variable_map;
+            Result::Err(err.msg)
+        }
     }
-    Result::Ok(())
+}
+
+/**
+ * Function declarations are hoisted at the beginning of the user input
+ * and then pushed to the stack without being popped
+ */
+fn eval_function_decls(
+    tokens: &mut Vec<Token>,
+    stack: &mut Vec<Slot>,
+    function_stack: &mut Vec<FunctionScope>
+) -> Result<(), String> {
+    let function_scope: &FunctionScope = function_stack.last().unwrap();
+    let (var_scope, variable_map) = (function_scope.var_scope, function_scope.variable_map.clone());
+    let functions: Vec<Exp> = match parse_function_decls(tokens, function_stack) {
+        Result::Ok((_, functions)) => functions,
+        Result::Err(err) => {
+            // Forget the names declared before the error
+            let function_scope: &mut FunctionScope = function_stack.last_mut().unwrap();
+            function_scope.var_scope = var_scope;
+            function_scope.variable_map = variable_map;
+            return Result::Err(format!("SyntaxError: {}", err.msg))
+        }
+    };
+    // The functions stay on the stack for the rest of the session, so their bodies are leaked like
+    // the lines evaluated by `eval_in_scope`
+    let functions: &'static Vec<Exp> = Box::leak(Box::new(functions));
+    push_function_decls(functions, stack, 0, StackValue::unit()).map_err(|err| err.msg)
 }
@@ -1,4 +1,5 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
     use crate::parser::parse;
     use crate::semantics::eval;
@@ -6,6 +7,7 @@ mod tests {
     use crate::expression::Exp;
     use crate::token::Token;
     use crate::lexer::tokenize;
+    use num_bigint::BigInt;
 
 
     fn eval_program(text: String) -> Result<V, ()> {
@@ -15,10 +17,17 @@ mod tests {
         let exp: Exp = parse(&mut tokens).or(Result::Err(()))?;
 
         // Evaluate expression
-        let val = eval(&exp).or(Result::Err(()))?;
+        let val = eval(exp).or(Result::Err(()))?;
         Result::Ok(val)
     }
 
+    // Returns the message of the runtime error of a program
+    fn eval_error(text: String) -> String {
+        let mut tokens: Vec<Token> = tokenize(text).or(Result::Err(())).unwrap();
+        let exp: Exp = parse(&mut tokens).or(Result::Err(())).unwrap();
+        eval(exp).unwrap_err().msg
+    }
+
     #[test]
     fn test1() {
         assert_eq!(eval_program(String::from("2 + 2")), Result::Ok(V::Val(Value::Int(4))));
@@ -134,4 +143,690 @@ mod tests {
         assert_eq!(eval_program(text3), Result::Ok(V::Val(Value::Int(2))));
     }
 
+    #[test]
+    fn test7() {
+        let text1 = String::from("
+            let make_counter = fn() {
+                let count = 0;
+                fn() { count = count + 1; count }
+            };
+            let c1 = make_counter();
+            let c2 = make_counter();
+            c1(); c1(); c2();
+            c1()");
+        assert_eq!(eval_program(text1), Result::Ok(V::Val(Value::Int(3))));
+
+        let text2 = String::from("
+            let base = 10;
+            let adder = fn(x) { fn(y) { x + y + base } };
+            adder(1)(2)");
+        assert_eq!(eval_program(text2), Result::Ok(V::Val(Value::Int(13))));
+
+        let text3 = String::from("
+            let factor = 3;
+            let apply = fn(f, x) { f(x) };
+            apply(fn(x) { x * factor }, 4)");
+        assert_eq!(eval_program(text3), Result::Ok(V::Val(Value::Int(12))));
+
+        let text4 = String::from("
+            let f = fn(a) { let b = 5; a + b };
+            f(1)");
+        assert_eq!(eval_program(text4), Result::Ok(V::Val(Value::Int(6))));
+
+        // Closures share the captured variables with the enclosing scope
+        let text5 = String::from("let c = 0; let f = fn() { c = c + 1 }; f(); f(); c");
+        assert_eq!(eval_program(text5), Result::Ok(V::Val(Value::Int(2))));
+        let text6 = String::from("let x = 1; let f = fn() { x }; x = 5; f()");
+        assert_eq!(eval_program(text6), Result::Ok(V::Val(Value::Int(5))));
+    }
+
+    #[test]
+    fn test8() {
+        let text1 = String::from("
+            fn fact(n) {
+                if n == 0 { 1 } else { n * fact(n - 1) }
+            }
+            fact(5)");
+        assert_eq!(eval_program(text1), Result::Ok(V::Val(Value::Int(120))));
+
+        // Functions can be called before being declared and can call each other
+        let text2 = String::from("
+            let result = [is_even(10), is_odd(7), is_even(3)];
+            fn is_even(n) {
+                if n == 0 { true } else { is_odd(n - 1) }
+            }
+            fn is_odd(n) {
+                if n == 0 { false } else { is_even(n - 1) }
+            }
+            result[0] && result[1] && !result[2]");
+        assert_eq!(eval_program(text2), Result::Ok(V::Val(Value::Bool(true))));
+
+        let text3 = String::from("
+            let sum_tree = fn(tree) {
+                fn walk(node, depth) {
+                    if depth == 0 { node } else { walk(node[0], depth - 1) + walk(node[1], depth - 1) }
+                }
+                walk(tree, 2)
+            };
+            sum_tree([[1, 2], [3, 4]])");
+        assert_eq!(eval_program(text3), Result::Ok(V::Val(Value::Int(10))));
+
+        assert_eq!(eval_program(String::from("fn f() { 1 } fn f() { 2 } f()")), Result::Err(()));
+
+        // Functions that use a variable are declared after its let
+        assert_eq!(eval_program(String::from("let k = 2; fn f(x) { x * k }; f(3)")), Result::Ok(V::Val(Value::Int(6))));
+        let text4 = String::from("
+            let sq = fn(x) { x * x };
+            fn cube(x) { x * sq(x) }
+            fn ninth(x) { cube(cube(x)) }
+            let total = helper();
+            fn helper() { let total = 5; total }
+            [cube(2), ninth(2), total]");
+        assert_eq!(eval_program(text4).unwrap().to_string(), "[8, 512, 5]");
+        assert_eq!(eval_program(String::from("f(1); let k = 2; fn f(x) { x * k }")), Result::Err(()));
+    }
+
+    #[test]
+    fn test9() {
+        assert_eq!(eval_program(String::from("3.75")), Result::Ok(V::Val(Value::Float(3.75))));
+        assert_eq!(eval_program(String::from("1e-9")), Result::Ok(V::Val(Value::Float(1e-9))));
+        assert_eq!(eval_program(String::from("2.5E+3")), Result::Ok(V::Val(Value::Float(2500.0))));
+        assert_eq!(eval_program(String::from("1.5 + 1.25")), Result::Ok(V::Val(Value::Float(2.75))));
+        assert_eq!(eval_program(String::from("1 + 0.5")), Result::Ok(V::Val(Value::Float(1.5))));
+        assert_eq!(eval_program(String::from("7 / 2")), Result::Ok(V::Val(Value::Int(3))));
+        assert_eq!(eval_program(String::from("7.0 / 2")), Result::Ok(V::Val(Value::Float(3.5))));
+        assert_eq!(eval_program(String::from("5.5 % 2")), Result::Ok(V::Val(Value::Float(1.5))));
+        assert_eq!(eval_program(String::from("1 < 1.5")), Result::Ok(V::Val(Value::Bool(true))));
+        assert_eq!(eval_program(String::from("2.0 == 2")), Result::Ok(V::Val(Value::Bool(true))));
+        assert_eq!(eval_program(String::from("0.1 + 0.2 != 0.3")), Result::Ok(V::Val(Value::Bool(true))));
+        assert_eq!(eval_program(String::from("let e = 3; e-1")), Result::Ok(V::Val(Value::Int(2))));
+    }
+
+    #[test]
+    fn test10() {
+        assert_eq!(eval_program(String::from("3000000000 * 2")), Result::Ok(V::Val(Value::Int(6000000000))));
+        assert_eq!(eval_program(String::from("9223372036854775807 + 1 - 1")), Result::Ok(V::Val(Value::Int(i64::MAX))));
+        let big: BigInt = "265252859812191058636308480000000".parse().unwrap();
+        let text = String::from("
+            let n = 30;
+            let f = 1;
+            while n > 0 {
+                f = f * n;
+                n = n - 1
+            };
+            f");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::BigInt(big.clone()))));
+        assert_eq!(eval_program(String::from("265252859812191058636308480000000")), Result::Ok(V::Val(Value::BigInt(big))));
+        assert_eq!(eval_program(String::from("99999999999999999999 > 9223372036854775807")), Result::Ok(V::Val(Value::Bool(true))));
+    }
+
+    #[test]
+    fn test11() {
+        assert_eq!(eval_program(String::from("let x = 5; let y = 0; try { x / y } catch DivZero { x }")), Result::Ok(V::Val(Value::Int(5))));
+        assert_eq!(eval_program(String::from("try { 7 / 0 } catch e { e }")), Result::Ok(V::Val(Value::Str(String::from("DivZero")))));
+        assert_eq!(eval_program(String::from("try { 7 % 0 } catch e { e }")), Result::Ok(V::Val(Value::Str(String::from("ModZero")))));
+        assert_eq!(eval_program(String::from("try { 99999999999999999999 / 0 } catch e { e }")), Result::Ok(V::Val(Value::Str(String::from("DivZero")))));
+        assert_eq!(eval_program(String::from("let r = try { 1 / 0 } catch e { 0 }; r + 1")), Result::Ok(V::Val(Value::Int(1))));
+        assert_eq!(eval_program(String::from("1 / 0")), Result::Err(()));
+    }
+
+    #[test]
+    fn test12() {
+        let text = String::from("
+            let m = #{\"a\": 1, \"b\": 2 + 3};
+            m[\"a\"] = 10;
+            m[\"c\"] = 7;
+            m[\"a\"] + m[\"b\"] + m[\"c\"]");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(22))));
+        assert_eq!(eval_program(String::from("let m = #{1: true}; [1 in m, 2 in m]")), eval_program(String::from("[true, false]")));
+        assert_eq!(eval_program(String::from("#{\"x\": [1, 2], 3: #{}} == #{3: #{}, \"x\": [1, 2]}")), Result::Ok(V::Val(Value::Bool(true))));
+        assert_eq!(eval_program(String::from("#{\"f\": fn (x) { x * 2 }}[\"f\"](4)")), Result::Ok(V::Val(Value::Int(8))));
+        assert_eq!(eval_program(String::from("#{}[\"missing\"]")), Result::Err(()));
+        assert_eq!(eval_program(String::from("#{[1]: 2}")), Result::Err(()));
+    }
+
+    #[test]
+    fn test13() {
+        let text = String::from("
+            fn divmod(x, y) { (x / y, x % y) }
+            let (q, r) = divmod(17, 5);
+            q * 10 + r");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(32))));
+        assert_eq!(eval_program(String::from("let ((a, b), c) = ((1, 2), 3); let d = 4; a + b + c + d")), Result::Ok(V::Val(Value::Int(10))));
+        assert_eq!(eval_program(String::from("let f = fn ((a, b), c) { let d = a * b; d + c }; f((2, 3), 4)")), Result::Ok(V::Val(Value::Int(10))));
+        assert_eq!(eval_program(String::from("(1, (2, 3)) == (1, (2, 3))")), Result::Ok(V::Val(Value::Bool(true))));
+        assert_eq!(eval_program(String::from("let t = (1, 2); t[1]")), Result::Ok(V::Val(Value::Int(2))));
+        assert_eq!(eval_program(String::from("let (a, b) = (1, 2, 3); a")), Result::Err(()));
+        // Empty tuples, and tuples with one element, written with a trailing comma
+        assert_eq!(eval_program(String::from("[(), (1,), (1 + 2,), (1, 2,), (1)]")).unwrap().to_string(), "[(), (1,), (3,), (1, 2), 1]");
+        assert_eq!(eval_program(String::from("let (a,) = (7,); match (a, ()) { (x, ()) => x }")), Result::Ok(V::Val(Value::Int(7))));
+        assert_eq!(eval_program(String::from("match (5,) { (x,) => x }")), Result::Ok(V::Val(Value::Int(5))));
+        assert_eq!(eval_program(String::from("1; (,)")), Result::Err(()));
+    }
+
+    #[test]
+    fn test14() {
+        let text = String::from("
+            fn norm2(p) { p.x * p.x + p.y * p.y }
+            struct Point { x, y }
+            let p = Point { y: 2, x: 1 };
+            p.x = 3;
+            norm2(p)");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(13))));
+        let text = String::from("
+            struct Point { x, y }
+            struct Line { a, b }
+            let l = Line { a: Point { x: 0, y: 0 }, b: Point { x: 1, y: [1, 2] } };
+            l.b.y[0] = 5;
+            l.b.y[0] + l.b.x");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(6))));
+        assert_eq!(eval_program(String::from("struct P { a } P { a: 1 } == P { a: 1 }")), Result::Ok(V::Val(Value::Bool(true))));
+        assert_eq!(eval_program(String::from("struct P { a } P { a: 1 }.b")), Result::Err(()));
+        assert_eq!(eval_program(String::from("struct P { a } P { b: 1 }")), Result::Err(()));
+        assert_eq!(eval_program(String::from("struct P { a, b } P { a: 1 }")), Result::Err(()));
+    }
+
+    #[test]
+    fn test15() {
+        let text = String::from("
+            enum Shape { Circle(r), Rect(w, h), Empty }
+            fn area(s) {
+                match s {
+                    Circle(r) => 3 * r * r,
+                    Rect(w, h) if w == h => w * w + 1,
+                    Rect(w, h) => { let a = w * h; a },
+                    Empty => 0
+                }
+            }
+            area(Circle(2)) + area(Rect(2, 3)) + area(Rect(4, 4)) + area(Empty)");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(35))));
+        let text = String::from("
+            fn describe(l) {
+                match l {
+                    [] => 0,
+                    [1, _] => 1,
+                    [a, b] => a + b,
+                    _ => 100
+                }
+            }
+            describe([]) + describe([1, 5]) + describe([2, 5]) + describe([1, 2, 3])");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(108))));
+        assert_eq!(eval_program(String::from("enum O { Some(x), None } let a = 2; match Some((a, 3)) { None => 0, Some((x, y)) => x * y }")), Result::Ok(V::Val(Value::Int(6))));
+        assert_eq!(eval_program(String::from("enum O { Some(x), None } Some(1) == Some(1)")), Result::Ok(V::Val(Value::Bool(true))));
+        assert_eq!(eval_program(String::from("match 3 { 1 => 1, 2 => 2 }")), Result::Err(()));
+        assert_eq!(eval_program(String::from("enum O { Some(x), None } Some(1, 2)")), Result::Err(()));
+        // The variables of an arm are not visible after the match
+        assert_eq!(eval_program(String::from("let x = 1; match (7, 8) { (x, z) => x }; let y = 10; x")), Result::Ok(V::Val(Value::Int(1))));
+        assert_eq!(eval_program(String::from("let x = 1; match 5 { x => x }; x")), Result::Ok(V::Val(Value::Int(1))));
+    }
+
+    #[test]
+    fn test16() {
+        let text = String::from("
+            let s = 0;
+            for i in 1..=10 {
+                let d = i * 2;
+                s = s + d
+            };
+            for (a, b) in [(1, 2), (3, 4)] { s = s + a * b }
+            for k in #{1: 10, 2: 20} { s = s + k }
+            s");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(127))));
+        assert_eq!(eval_program(String::from("let s = \"\"; for c in \"abc\" { s = c + s }; s")), Result::Ok(V::Val(Value::Str(String::from("cba")))));
+        assert_eq!(eval_program(String::from("let n = 3; let s = 0; for i in 0..n + 1 { for j in i..n { s = s + 1 } }; s")), Result::Ok(V::Val(Value::Int(6))));
+        assert_eq!(eval_program(String::from("let l = [1, 2]; for x in l { l = [] }; l")), Result::Ok(V::Val(Value::List(vec![]))));
+        assert_eq!(eval_program(String::from("5 in 0..5")), Result::Ok(V::Val(Value::Bool(false))));
+        assert_eq!(eval_program(String::from("for x in 5 { x }")), Result::Err(()));
+        // The variables of the pattern are not visible after the loop
+        assert_eq!(eval_program(String::from("for i in [5] { i }; let y = 10; i")), Result::Err(()));
+        assert_eq!(eval_program(String::from("let x = 1; for x in [5] { x }; x")), Result::Ok(V::Val(Value::Int(1))));
+    }
+
+    #[test]
+    fn test17() {
+        assert_eq!(eval_program(String::from("let i = 0; while true { i = i + 1; if i == 5 { break i * 10 } }")), Result::Ok(V::Val(Value::Int(50))));
+        assert_eq!(eval_program(String::from("let s = 0; for i in 0..10 { if i % 2 == 0 { continue }; s = s + i }; s")), Result::Ok(V::Val(Value::Int(25))));
+        let text = String::from("
+            let c = 0;
+            'outer: for i in 0..5 {
+                let j = 0;
+                'inner: while j < 5 {
+                    j = j + 1;
+                    if j > i { continue 'outer };
+                    if i == 3 { break 'outer };
+                    c = c + 1
+                }
+            };
+            c");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(3))));
+        // Loop signals are not caught as exceptions
+        assert_eq!(eval_program(String::from("let i = 0; while i < 3 { try { i = i + 1; break } catch e { 0 } }; i")), Result::Ok(V::Val(Value::Int(1))));
+        assert_eq!(eval_program(String::from("break")), Result::Err(()));
+        assert_eq!(eval_program(String::from("while true { let f = fn () { break }; f() }")), Result::Err(()));
+        assert_eq!(eval_program(String::from("while true { break 'outer }")), Result::Err(()));
+    }
+
+    #[test]
+    fn test18() {
+        let text = String::from("
+            fn find(l, y) {
+                for (i, x) in l {
+                    if x == y { return i }
+                };
+                0 - 1
+            }
+            let l = [(0, 1), (1, 5), (2, 7)];
+            let a = 100;
+            find(l, 7) + find(l, 3) + a");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(101))));
+        assert_eq!(eval_program(String::from("fn f(x) { if x == 0 { return }; x } f(0)")), Result::Ok(V::Val(Value::Unit)));
+        // Only the innermost function returns
+        assert_eq!(eval_program(String::from("fn f() { let g = fn () { return 2 }; g() + 1 } f()")), Result::Ok(V::Val(Value::Int(3))));
+        assert_eq!(eval_program(String::from("fn f() { while true { try { return 3 } catch e { 0 } } } f()")), Result::Ok(V::Val(Value::Int(3))));
+        assert_eq!(eval_program(String::from("return 1")), Result::Err(()));
+    }
+
+    #[test]
+    fn test19() {
+        let text = String::from("
+            let l = [1, 2];
+            push(l, 3);
+            let x = pop(l);
+            push(l, x * 10);
+            [len(l), l[2], len(\"abc\"), len(#{1: 2})]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[3, 30, 3, 1]");
+        let text = String::from("
+            struct P { a }
+            [typeof(1), typeof(\"a\"), typeof([]), typeof(null), typeof(len), typeof(fn () { 1 }), typeof(P { a: 1 })]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[int, string, list, unit, function, function, P]");
+        assert_eq!(eval_program(String::from("str(12) + str(1.5)")), Result::Ok(V::Val(Value::Str(String::from("121.5")))));
+        assert_eq!(eval_program(String::from("int(\" 42\") + int(3.9) + int(true)")), Result::Ok(V::Val(Value::Int(46))));
+        // Builtins can be shadowed
+        assert_eq!(eval_program(String::from("let len = 3; len + 1")), Result::Ok(V::Val(Value::Int(4))));
+        assert_eq!(eval_program(String::from("int(\"x\")")), Result::Err(()));
+        assert_eq!(eval_program(String::from("pop([])")), Result::Err(()));
+        assert_eq!(eval_program(String::from("len([], [])")), Result::Err(()));
+        // The errors of the builtins are thrown as their message
+        assert_eq!(eval_program(String::from("try { int(\"x\") } catch e { e }")).unwrap().to_string(), "Can not convert string \"x\" to int");
+        assert_eq!(eval_program(String::from("try { join([1]) } catch e { e }")).unwrap().to_string(), "Wrong number of arguments for join(list, separator). Expected 2, found 1");
+    }
+
+    #[test]
+    fn test20() {
+        assert_eq!(eval_program(String::from("[\"a\" == \"a\", \"a\" != \"a\", \"abc\" < \"abd\", \"b\" >= \"c\"]")).unwrap().to_string(), "[true, false, true, false]");
+        let text = String::from("
+            let words = split(trim(\"  the quick brown fox \"), \" \");
+            let result = [];
+            for word in words {
+                if starts_with(word, \"b\") || contains(word, \"ui\") {
+                    push(result, to_upper(substring(word, 0, 3)))
+                }
+            };
+            join(result, \"-\")");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Str(String::from("QUI-BRO")))));
+        assert_eq!(eval_program(String::from("replace(to_lower(\"A-B-C\"), \"-\", \"\")")), Result::Ok(V::Val(Value::Str(String::from("abc")))));
+        assert_eq!(eval_program(String::from("let s = \"héllo\"; s[1] + str(len(chars(s)))")), Result::Ok(V::Val(Value::Str(String::from("é5")))));
+        assert_eq!(eval_program(String::from("parse_int(\"-12\") + 1")), Result::Ok(V::Val(Value::Int(-11))));
+        assert_eq!(eval_program(String::from("parse_int(\"1.5\")")), Result::Err(()));
+        assert_eq!(eval_program(String::from("try { parse_int(\"abc\") } catch e { typeof(e) }")).unwrap().to_string(), "string");
+        assert_eq!(eval_program(String::from("\"abc\"[3]")), Result::Err(()));
+        assert_eq!(eval_program(String::from("substring(\"abc\", 2, 4)")), Result::Err(()));
+    }
+
+    #[test]
+    fn test21() {
+        let text = String::from("
+            let l = [3, 1, 2];
+            [map(l, fn (x) { x * 2 }), filter(l, fn (x) { x > 1 }), fold(l, 0, fn (a, x) { a + x }), reduce(l, fn (a, x) { a * x })]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[[6, 2, 4], [3, 2], 6, 6]");
+        let text = String::from("
+            let l = [3, 1, 2];
+            [any(l, fn (x) { x == 2 }), all(l, fn (x) { x > 1 }), zip(l, [\"a\", \"b\"]), enumerate([\"x\"]), reverse(l), l]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[true, false, [(3, a), (1, b)], [(0, x)], [2, 1, 3], [3, 1, 2]]");
+        assert_eq!(eval_program(String::from("[sort([3, 1.5, 2]), sort_by([\"bb\", \"a\", \"ccc\"], fn (a, b) { len(b) - len(a) }), map([1, 2], str)]")).unwrap().to_string(), "[[1.5, 2, 3], [ccc, bb, a], [1, 2]]");
+        // Exceptions thrown by the callbacks can be caught
+        assert_eq!(eval_program(String::from("try { map([1], fn (x) { throw 2 }) } catch e { e }")), Result::Ok(V::Val(Value::Int(2))));
+        assert_eq!(eval_program(String::from("sort([1, \"a\"])")), Result::Err(()));
+        assert_eq!(eval_program(String::from("reduce([], fn (a, x) { a })")), Result::Err(()));
+        assert_eq!(eval_program(String::from("map([1], fn (a, b) { a })")), Result::Err(()));
+        // Callbacks that end with an assignment return unit
+        assert_eq!(eval_program(String::from("map([1, 2], fn (x) { let y = 0; y = x })")).unwrap().to_string(), "[unit, unit]");
+        assert_eq!(eval_program(String::from("let u = [1]; u[0] = null; u")).unwrap().to_string(), "[unit]");
+    }
+
+    #[test]
+    fn test22() {
+        assert_eq!(eval_program(String::from("[8 - 2 - 1, 8 / 2 / 2, 2 ** 3 ** 2, 2 * 3 ** 2, 2 ** 0.5 ** 2]")).unwrap().to_string(), "[5, 2, 512, 18, 1.189207115002721]");
+        assert_eq!(eval_program(String::from("[2 ** 64, 2 ** (0 - 1), pow(3, 4), pow(4, 0.5)]")).unwrap().to_string(), "[18446744073709551616, 0.5, 81, 2.0]");
+        assert_eq!(eval_program(String::from("[abs(0 - 3), abs(0 - 1.5), min(3, 1, 2), max([1, 5.5, 2]), max(\"a\", \"b\")]")).unwrap().to_string(), "[3, 1.5, 1, 5.5, b]");
+        assert_eq!(eval_program(String::from("[floor(2.7), ceil(2.1), round(0 - 2.5), floor(7), sqrt(16)]")).unwrap().to_string(), "[2, 3, -3, 7, 4.0]");
+        assert_eq!(eval_program(String::from("[gcd(12, 0 - 18), gcd(0, 5), round(sin(pi / 2)), cos(0), floor(euler)]")).unwrap().to_string(), "[6, 5, 1, 1.0, 2]");
+        // Builtin constants can be shadowed, and e is free for the catch variable
+        assert_eq!(eval_program(String::from("let pi = 3; pi ** 2")), Result::Ok(V::Val(Value::Int(9))));
+        assert_eq!(eval_program(String::from("try { 1 / 0 } catch e { e + str(floor(euler)) }")).unwrap().to_string(), "DivZero2");
+        assert_eq!(eval_program(String::from("2 ** 9999999999")), Result::Err(()));
+        assert_eq!(eval_program(String::from("gcd(1.5, 3)")), Result::Err(()));
+        assert_eq!(eval_program(String::from("min([])")), Result::Err(()));
+    }
+
+    #[test]
+    fn test23() {
+        let dir = std::env::temp_dir().join("epilang_test23");
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/math.epi"), "
+            import \"counter.epi\" as counter;
+            push(counter.loads, 1);
+            fn _square(x) { x * x }
+            fn sum_of_squares(x, y) { _square(x) + _square(y) }
+            let origin = (0, 0);").unwrap();
+        std::fs::write(dir.join("lib/counter.epi"), "let loads = [];").unwrap();
+        std::fs::write(dir.join("a.epi"), "import \"b.epi\" as b; 1").unwrap();
+        std::fs::write(dir.join("b.epi"), "import \"a.epi\" as a; 2").unwrap();
+        let dir = dir.display();
+
+        // Modules are evaluated only once, even when they are imported by different files
+        let text = format!("
+            import \"{dir}/lib/math.epi\" as math;
+            import \"{dir}/lib/math.epi\" as again;
+            import \"{dir}/lib/counter.epi\" as counter;
+            [math.sum_of_squares(3, 4), again.origin, len(counter.loads), typeof(math)]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[25, (0, 0), 1, module]");
+        // Bindings starting with `_` are private
+        let text = format!("import \"{dir}/lib/math.epi\" as math; math._square(2)");
+        assert_eq!(eval_error(text), "Binding _square is private to the module, because its name starts with _");
+        assert_eq!(eval_error(format!("import \"{dir}/lib/math.epi\" as math; math.cube")), "Module has no binding cube");
+        assert_eq!(eval_program(format!("import \"{dir}/a.epi\" as a; a")), Result::Err(()));
+        assert_eq!(eval_program(format!("try {{ import \"{dir}/missing.epi\" }} catch e {{ 0 }}")), Result::Ok(V::Val(Value::Int(0))));
+    }
+
+    #[test]
+    fn test24() {
+        let dir = std::env::temp_dir().join("epilang_test24");
+        let _ = std::fs::remove_dir_all(&dir);
+        let dir = dir.display();
+        let text = format!("
+            mkdir(\"{dir}/out\");
+            write_file(\"{dir}/out/a.txt\", \"one\\n\");
+            append_file(\"{dir}/out/a.txt\", \"two\\n\");
+            append_file(\"{dir}/out/b.txt\", \"three\");
+            let result = [read_lines(\"{dir}/out/a.txt\"), read_file(\"{dir}/out/b.txt\"), list_dir(\"{dir}/out\")];
+            remove_file(\"{dir}/out/b.txt\");
+            push(result, [exists(\"{dir}/out/a.txt\"), exists(\"{dir}/out/b.txt\")]);
+            result");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[[one, two], three, [a.txt, b.txt], [true, false]]");
+        // Failures are exceptions with the error message
+        let text = format!("try {{ read_file(\"{dir}/missing.txt\") }} catch e {{ starts_with(e, \"Can not read file\") }}");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Bool(true))));
+        assert_eq!(eval_program(format!("remove_file(\"{dir}/missing.txt\")")), Result::Err(()));
+        assert_eq!(eval_program(String::from("write_file(1, 2)")), Result::Err(()));
+    }
+
+    #[test]
+    fn test25() {
+        // The tests do not read stdin, which is shared with the test runner
+        assert_eq!(eval_program(String::from("[typeof(input), typeof(read_line), typeof(read_all_stdin)]")).unwrap().to_string(), "[function, function, function]");
+        assert_eq!(eval_program(String::from("read_line(1)")), Result::Err(()));
+        // read_line returns null at the end of the input, and null can be compared with any value
+        assert_eq!(eval_program(String::from("[null == null, \"line\" == null, null != null, null != \"line\"]")).unwrap().to_string(), "[true, false, false, true]");
+        assert_eq!(eval_program(String::from("let l = [1, null]; [l[1] == null, l[0] != null]")).unwrap().to_string(), "[true, true]");
+        assert_eq!(eval_program(String::from("input()")), Result::Err(()));
+    }
+
+    #[test]
+    fn test26() {
+        assert_eq!(eval_program(String::from("args")).unwrap().to_string(), "[]");
+        let text = String::from("set_env(\"EPILANG_TEST26\", \"on\"); [env(\"EPILANG_TEST26\"), typeof(env(\"EPILANG_TEST26_UNSET\"))]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[on, unit]");
+        let text = String::from("[env(\"EPILANG_TEST26\") == null, env(\"EPILANG_TEST26_UNSET\") == null]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[false, true]");
+        assert_eq!(eval_program(String::from("set_env(\"A=B\", \"x\")")), Result::Err(()));
+        assert_eq!(eval_program(String::from("exit(\"1\")")), Result::Err(()));
+    }
+
+    #[test]
+    fn test27() {
+        // Escaping continuation passed to a function
+        let text = String::from("
+            let f = fn(x) { throw x 1 };
+            callcc k in {
+                3 + f(k)
+            }");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(1))));
+        // Nested continuations used as exception handlers
+        let text = String::from("
+            let x = 0;
+            callcc tryblock in {
+                callcc handle in {
+                    if (x == 0) { throw handle \"DivZero\" };
+                    throw tryblock (5 / x)
+                }
+                x - 42
+            }");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(-42))));
+        // A stored continuation is resumed after callcc has returned, as many times as needed
+        let text = String::from("
+            let saved = 0;
+            let count = 0;
+            let v = callcc k in { saved = k; 0 };
+            count = count + 1;
+            if (v < 3) { saved(v + 1) };
+            [v, count]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[3, 4]");
+        // Escape from a function called by a builtin
+        let text = String::from("callcc k in { map([1, 2, 3], fn(x) { if (x == 2) { k(x * 10) }; x }) }");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(20))));
+        assert_eq!(eval_program(String::from("typeof(callcc k in { k })")).unwrap().to_string(), "continuation");
+        assert_eq!(eval_program(String::from("callcc k in { k(1, 2) }")), Result::Err(()));
+        // The builtin that created the continuation has returned
+        assert_eq!(eval_program(String::from("map([1], fn(x) { callcc k in { k } })[0](1)")), Result::Err(()));
+        // Deep recursion does not use the Rust stack
+        let text = String::from("fn f(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } } f(100000)");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(100000))));
+        // The continuation and the variables of a block are not visible after it
+        assert_eq!(eval_program(String::from("let k = 7; callcc k in { 1 }; k")), Result::Ok(V::Val(Value::Int(7))));
+        assert_eq!(eval_program(String::from("let a = 2; callcc k in { let a = 15; throw k a }; a")), Result::Ok(V::Val(Value::Int(2))));
+        let text = String::from("callcc x in { let a = 2; let res = callcc y in { let a = 3; throw y 1 }; a }");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(2))));
+        assert_eq!(eval_program(String::from("let x = 1; { let x = 2; x }; x")), Result::Ok(V::Val(Value::Int(1))));
+        assert_eq!(eval_program(String::from("{ let z = 3; z }; let q = 9; z")), Result::Err(()));
+        assert_eq!(eval_program(String::from("let x = 1; try { throw 1 } catch x { x }; x")), Result::Ok(V::Val(Value::Int(1))));
+        assert_eq!(eval_program(String::from("try { throw 1 } catch e { let y = 5; e }")), Result::Ok(V::Val(Value::Int(1))));
+    }
+
+    #[test]
+    fn test28() {
+        assert_eq!(eval_program(String::from("reset { 1 + shift k { k(k(10)) } }")), Result::Ok(V::Val(Value::Int(12))));
+        assert_eq!(eval_program(String::from("reset { 1 + shift k { 5 } }")), Result::Ok(V::Val(Value::Int(5))));
+        // Delimited continuations are functions that can be called after reset has returned
+        let text = String::from("let k = reset { 2 * shift c { c } }; [k(3) + k(4), map([1, 2], k)]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[14, [2, 4]]");
+        // Backtracking search
+        let text = String::from("
+            fn choose(options) {
+                shift k {
+                    let results = [];
+                    for option in options {
+                        for result in k(option) { push(results, result) }
+                    };
+                    results
+                }
+            }
+            reset {
+                let x = choose([1, 2, 3]);
+                let y = choose([1, 2, 3]);
+                if (x + y == 4) { [[x, y]] } else { [] }
+            }");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[[1, 3], [2, 2], [3, 1]]");
+        // Every call binds the variables of the continuation again
+        let text = String::from("let k = reset { let x = shift c { c }; fn() { x } }; let f = k(1); let g = k(2); [f(), g()]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[1, 2]");
+        // Exceptions thrown by a continuation are caught by its caller
+        let text = String::from("let k = reset { 10 / shift c { c } }; [try { k(0) } catch e { \"error\" }, k(5)]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[error, 2]");
+        assert_eq!(eval_program(String::from("shift k { 1 }")), Result::Err(()));
+        // The continuation is not visible after the shift block
+        assert_eq!(eval_program(String::from("let k = 3; reset { 1 + shift k { k(1) } }; k")), Result::Ok(V::Val(Value::Int(3))));
+        assert_eq!(eval_program(String::from("reset { shift c { 1 } }; let y = 10; c")), Result::Err(()));
+    }
+
+    #[test]
+    fn test29() {
+        // Infinite generator, consumed lazily
+        let text = String::from("
+            fn fibonacci() {
+                let (a, b) = (0, 1);
+                while (true) {
+                    yield a;
+                    let c = a + b;
+                    a = b;
+                    b = c
+                }
+            }
+            let g = fibonacci();
+            let result = [next(g), next(g), next(g), next(g)];
+            for x in fibonacci() {
+                if (x > 50) { break };
+                push(result, x)
+            };
+            result");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[0, 1, 1, 2, 0, 1, 1, 2, 3, 5, 8, 13, 21, 34]");
+        // next throws GeneratorDone once the body has ended, also with return, and a yielded null is not the end
+        let text = String::from("
+            fn g(n) { for i in 0..n { yield i * 2 }; yield null; return 0; yield 10 }
+            let x = g(2);
+            [next(x), next(x), next(x), try { next(x) } catch e { e }, try { next(x) } catch e { e }, typeof(x)]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[0, 2, unit, GeneratorDone, GeneratorDone, generator]");
+        // Generators of generators
+        let text = String::from("
+            fn inner() { yield 1; yield 2 }
+            fn outer() { for x in inner() { yield x + 100 } }
+            let result = [];
+            for y in outer() { push(result, y) };
+            result");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[101, 102]");
+        let text = String::from("fn g() { yield 1; throw \"boom\" } let x = g(); next(x); try { next(x) } catch e { e }");
+        assert_eq!(eval_program(text).unwrap().to_string(), "boom");
+        assert_eq!(eval_program(String::from("yield 1")), Result::Err(()));
+        assert_eq!(eval_program(String::from("next([1])")), Result::Err(()));
+    }
+
+    #[test]
+    fn test30() {
+        // Exceptions: the continuation is not resumed
+        let text = String::from("handle { perform Fail(\"x\"); 1 } with { Fail(msg), k => msg }");
+        assert_eq!(eval_program(text).unwrap().to_string(), "x");
+        // State
+        let text = String::from("
+            fn counter() {
+                let x = perform Get();
+                perform Set(x + 1);
+                perform Get()
+            }
+            let state = 10;
+            handle { counter() } with {
+                Get(), k => k(state),
+                Set(v), k => {
+                    state = v;
+                    k(null)
+                }
+            }");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(11))));
+        // Generators
+        let text = String::from("let out = []; handle { for i in 0..3 { perform Emit(i * i) } } with { Emit(x), k => { push(out, x); k() } }; out");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[0, 1, 4]");
+        // The continuation can be resumed more than once, with the handler still in place
+        let text = String::from("handle { perform Choose + perform Choose } with { Choose, k => [k(1), k(10)] }");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[[2, 11], [11, 20]]");
+        // Effects without a handler in the inner handle go to the outer one
+        let text = String::from("handle { handle { perform A(1) + perform B(2) } with { A(x), k => k(x * 100) } } with { B(y), k => k(y) }");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(102))));
+        assert_eq!(eval_program(String::from("perform Oops()")), Result::Err(()));
+        assert_eq!(eval_program(String::from("handle { perform A(1, 2) } with { A(x), k => x }")), Result::Err(()));
+        // handle is still a valid variable name
+        assert_eq!(eval_program(String::from("let handle = 1; handle + 1")), Result::Ok(V::Val(Value::Int(2))));
+        // The variables of a handler are not visible after handle
+        let text = String::from("let x = 1; let k = 2; handle { perform A(5) } with { A(x), k => x }; let y = 10; x + k");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(3))));
+        assert_eq!(eval_program(String::from("handle { perform A(5) } with { A(v), k => v }; let y = 10; v")), Result::Err(()));
+        // Functions called by native functions can not reach the handlers outside of the native function
+        let text = String::from("handle { map([1, 2], fn(x) { perform Dbl(x) }) } with { Dbl(x), k => k(x * 2) }");
+        assert_eq!(eval_program(text), Result::Err(()));
+        let text = String::from("map([1, 2], fn(x) { handle { perform Dbl(x) } with { Dbl(x), k => k(x * 2) } })");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[2, 4]");
+    }
+
+    #[test]
+    fn test31() {
+        // Threads take turns when they yield
+        let text = String::from("
+            let log = [];
+            let worker = fn(name, n) {
+                fn() {
+                    for i in 0..n {
+                        push(log, name + str(i));
+                        yield_now()
+                    };
+                    name
+                }
+            };
+            let a = spawn(worker(\"a\", 3));
+            let b = spawn(worker(\"b\", 2));
+            [join(a), join(b), log]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[a, b, [a0, b0, a1, b1, a2]]");
+        // Unbuffered channels: the producer waits for each value to be received.
+        // The producer pushes its last value after the main program has ended
+        let text = String::from("
+            let ch = channel();
+            let log = [];
+            spawn(fn() { for i in 1..4 { send(ch, i * 10); push(log, i) } });
+            let total = 0;
+            for i in 0..3 { total = total + recv(ch); push(log, \"r\") };
+            [total, log]");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[60, [r, 1, r, 2, r, 3]]");
+        // Buffered channels: send waits only when the buffer is full
+        let text = String::from("
+            let buf = channel(2);
+            let order = [];
+            let p = spawn(fn() { for i in 0..4 { send(buf, i); push(order, \"s\" + str(i)) } });
+            yield_now();
+            push(order, \"main\");
+            for i in 0..4 { push(order, \"r\" + str(recv(buf))) };
+            join(p);
+            order");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[s0, s1, main, r0, r1, s2, s3, r2, r3]");
+        // Threads can spawn and join threads, and join throws the error that ended a thread
+        assert_eq!(eval_program(String::from("join(spawn(fn() { join(spawn(fn() { 42 })) }))")), Result::Ok(V::Val(Value::Int(42))));
+        let text = String::from("try { join(spawn(fn() { throw \"boom\" })) } catch e { e }");
+        assert_eq!(eval_program(text).unwrap().to_string(), "boom");
+        assert_eq!(eval_program(String::from("let t = spawn(fn() { 1 }); [typeof(t), typeof(channel())]")).unwrap().to_string(), "[thread, channel]");
+        // Deadlock
+        assert_eq!(eval_program(String::from("recv(channel())")), Result::Err(()));
+        assert_eq!(eval_program(String::from("let ch = channel(); join(spawn(fn() { send(ch, 1) }))")), Result::Err(()));
+        // Each program starts without the threads and channels of the previous ones
+        assert_eq!(eval_program(String::from("[spawn(fn() { 1 }), channel()]")).unwrap().to_string(), "[thread 0, channel 0]");
+        // The value of a send that fails is not received
+        let text = String::from("let ch = channel(); try { send(ch, 1) } catch e { 0 }; let t = spawn(fn() { recv(ch) }); send(ch, 2); join(t)");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(2))));
+        let text = String::from("
+            let ch = channel();
+            spawn(fn() {
+                try { map([1], fn(x) { send(ch, \"failed\") }) } catch e { 0 };
+                send(ch, \"ok\")
+            });
+            recv(ch)");
+        assert_eq!(eval_program(text).unwrap().to_string(), "ok");
+        // Threads that are never waited for run when the main program ends
+        let text = String::from("let log = []; spawn(fn() { push(log, 1); yield_now(); push(log, 2) }); log");
+        assert_eq!(eval_program(text).unwrap().to_string(), "[1, 2]");
+        // A thread that is never joined and ends with an error makes the program fail
+        assert_eq!(eval_program(String::from("spawn(fn() { throw 1 }); 1")), Result::Err(()));
+        let text = String::from("let t = spawn(fn() { throw 1 }); try { join(t) } catch e { 0 }; 5");
+        assert_eq!(eval_program(text), Result::Ok(V::Val(Value::Int(5))));
+        // join still joins strings. The number of arguments depends on the form
+        assert_eq!(eval_program(String::from("join([\"x\", \"y\"], \"-\")")).unwrap().to_string(), "x-y");
+        assert_eq!(eval_program(String::from("join([\"x\", \"y\"])")), Result::Err(()));
+        assert_eq!(eval_program(String::from("join(spawn(fn() { 1 }), \"-\")")), Result::Err(()));
+        assert_eq!(eval_program(String::from("join(5)")), Result::Err(()));
+    }
+
 }
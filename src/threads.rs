@@ -0,0 +1,265 @@
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::mem;
+
+use crate::semantics::{Error, Outcome, Resume, run_thread};
+use crate::value::{StackValue, Continuation, V};
+use crate::value::Value::Unit;
+use crate::value::V::Val;
+
+/**
+ * Operation that a thread, or the main program, waits to perform
+ */
+#[derive(Clone, Debug)]
+pub enum Op {
+    // Lets the other threads run
+    Yield,
+    // Waits for the end of the thread
+    Join(usize),
+    // Waits for a value in the channel
+    Recv(usize),
+    // Waits for room in the buffer of the channel
+    Send(usize, StackValue),
+    // Waits until the value with the given number, sent on an unbuffered channel, is received
+    Delivered(usize, u64)
+}
+
+enum Thread {
+    // The function that the thread calls
+    Start(StackValue),
+    Waiting(Continuation, Op),
+    Running,
+    // The result of the function, or the message and the value of the error that ended the thread
+    Done(Result<StackValue, (String, StackValue)>)
+}
+
+struct Channel {
+    // Zero for unbuffered channels, whose senders wait until the value is received
+    capacity: usize,
+    // Values with their numbers, in the order they were sent
+    buffer: VecDeque<(u64, StackValue)>,
+    // Number of values sent so far, used to number the next one
+    sent: u64
+}
+
+/**
+ * Threads run one at a time, in the order they were spawned, and switch only when they wait,
+ * so a program always runs the same way
+ */
+#[derive(Default)]
+struct Scheduler {
+    threads: Vec<Thread>,
+    channels: Vec<Channel>,
+    // Threads that have not ended, in the order they run
+    queue: VecDeque<usize>,
+    // Thread whose machine is running, if any
+    current: Option<usize>,
+    // Operation of the native function that could not be performed immediately
+    request: Option<Op>,
+    // Threads whose end has been seen by join or whose error has been reported when the program ended
+    joined: HashSet<usize>
+}
+
+thread_local! {
+    static SCHEDULER: RefCell<Scheduler> = RefCell::new(Scheduler::default());
+}
+
+/**
+ * Forgets every thread and channel, including the threads stuck waiting when a program ended with a deadlock.
+ * Called before a program runs. The shell does not call it, so its threads and channels live as long as its variables
+ */
+pub fn reset() {
+    SCHEDULER.with(|scheduler| *scheduler.borrow_mut() = Scheduler::default())
+}
+
+/**
+ * Creates a thread that calls the function the next time the threads run. Returns the id of the thread
+ */
+pub fn spawn(function: StackValue) -> usize {
+    SCHEDULER.with(|scheduler| {
+        let mut scheduler = scheduler.borrow_mut();
+        let id: usize = scheduler.threads.len();
+        scheduler.threads.push(Thread::Start(function));
+        scheduler.queue.push_back(id);
+        id
+    })
+}
+
+/**
+ * Creates a channel that buffers up to `capacity` values. Returns the id of the channel
+ */
+pub fn channel(capacity: usize) -> usize {
+    SCHEDULER.with(|scheduler| {
+        let mut scheduler = scheduler.borrow_mut();
+        scheduler.channels.push(Channel { capacity, buffer: VecDeque::new(), sent: 0 });
+        scheduler.channels.len() - 1
+    })
+}
+
+/**
+ * Performs the operation of a native function. If it has to wait, the native function fails and the machine
+ * that called it suspends its thread, or runs the other threads if it is not a thread
+ */
+pub fn wait(mut op: Op) -> Result<V, Error> {
+    if !matches!(op, Op::Yield) {
+        if let Option::Some(v) = try_op(&mut op)? {
+            return Result::Ok(v)
+        }
+    }
+    SCHEDULER.with(|scheduler| scheduler.borrow_mut().request = Option::Some(op));
+    Result::Err(Error{msg: String::from("Thread is waiting"), v:Val(Unit)})
+}
+
+/**
+ * Returns the operation that the last native function has to wait for
+ */
+pub fn take_request() -> Option<Op> {
+    SCHEDULER.with(|scheduler| scheduler.borrow_mut().request.take())
+}
+
+/**
+ * Runs the threads until the operation of the main program can be performed
+ */
+pub fn block_on(mut op: Op) -> Result<V, Error> {
+    if SCHEDULER.with(|scheduler| scheduler.borrow().current.is_some()) {
+        cancel(&op);
+        return Result::Err(Error{msg: String::from("A thread can only wait in its own body, not in a function called by a native function"), v:Val(Unit)})
+    }
+    if let Op::Yield = op {
+        run_threads();
+        return Result::Ok(V::Val(Unit))
+    }
+    loop {
+        if let Option::Some(v) = try_op(&mut op)? {
+            return Result::Ok(v)
+        }
+        if !run_threads() {
+            cancel(&op);
+            return Result::Err(Error{msg: String::from("Deadlock: every thread is waiting"), v:Val(Unit)})
+        }
+    }
+}
+
+/**
+ * Runs the threads until every thread has ended or waits for something that will never happen.
+ * Returns the error of the first thread that ended with an error and was never joined
+ */
+pub fn run_remaining() -> Result<(), Error> {
+    while run_threads() {}
+    SCHEDULER.with(|scheduler| {
+        let mut scheduler = scheduler.borrow_mut();
+        let failed: Vec<usize> = (0..scheduler.threads.len())
+            .filter(|id| matches!(scheduler.threads[*id], Thread::Done(Result::Err(_))) && !scheduler.joined.contains(id))
+            .collect();
+        // Each error is reported once, also in the shell where the threads outlive the line that spawned them
+        scheduler.joined.extend(&failed);
+        match failed.first().map(|id| (id, &scheduler.threads[*id])) {
+            Option::Some((id, Thread::Done(Result::Err((msg, value))))) => {
+                Result::Err(Error{msg: format!("Thread {} ended with an error: {}", id, msg), v:V::Ptr(*value)})
+            },
+            _ => Result::Ok(())
+        }
+    })
+}
+
+/**
+ * Runs each thread once, until it ends or waits. Returns false if no thread could run
+ */
+fn run_threads() -> bool {
+    let ids: Vec<usize> = SCHEDULER.with(|scheduler| scheduler.borrow_mut().queue.drain(..).collect());
+    let mut progress: bool = false;
+    for id in ids {
+        let resume: Resume = match with_thread(id, |thread| mem::replace(thread, Thread::Running)) {
+            Thread::Start(function) => Resume::Start(function),
+            Thread::Waiting(continuation, mut op) => match try_op(&mut op) {
+                Result::Ok(Option::Some(v)) => Resume::Continue(continuation, Result::Ok(v)),
+                Result::Err(err) => Resume::Continue(continuation, Result::Err(err)),
+                Result::Ok(Option::None) => {
+                    suspend(id, continuation, op);
+                    continue
+                }
+            },
+            _ => panic!("Ended thread in the queue")
+        };
+        progress = true;
+        SCHEDULER.with(|scheduler| scheduler.borrow_mut().current = Option::Some(id));
+        let outcome: Outcome = run_thread(resume);
+        SCHEDULER.with(|scheduler| scheduler.borrow_mut().current = Option::None);
+        match outcome {
+            Outcome::Done(result) => with_thread(id, |thread| *thread = Thread::Done(match result {
+                Result::Ok(v) => Result::Ok(v.into_stack_value()),
+                Result::Err(err) => Result::Err((err.msg, err.v.into_stack_value()))
+            })),
+            Outcome::Waiting(continuation, op) => suspend(id, continuation, op)
+        }
+    }
+    progress
+}
+
+/**
+ * Takes back the value of a send on an unbuffered channel that can not wait for it to be received,
+ * so that the value of a failed send is never received
+ */
+fn cancel(op: &Op) {
+    if let Op::Delivered(id, number) = op {
+        SCHEDULER.with(|scheduler| scheduler.borrow_mut().channels[*id].buffer.retain(|(n, _)| n != number))
+    }
+}
+
+fn suspend(id: usize, continuation: Continuation, op: Op) {
+    SCHEDULER.with(|scheduler| {
+        let mut scheduler = scheduler.borrow_mut();
+        scheduler.threads[id] = Thread::Waiting(continuation, op);
+        scheduler.queue.push_back(id)
+    })
+}
+
+fn with_thread<T>(id: usize, f: impl FnOnce(&mut Thread) -> T) -> T {
+    SCHEDULER.with(|scheduler| f(&mut scheduler.borrow_mut().threads[id]))
+}
+
+/**
+ * Performs the operation if it does not have to wait, and returns its value. Returns None otherwise.
+ * Sending on an unbuffered channel becomes waiting for the value to be received
+ */
+fn try_op(op: &mut Op) -> Result<Option<V>, Error> {
+    SCHEDULER.with(|scheduler| {
+        let mut scheduler = scheduler.borrow_mut();
+        match op {
+            Op::Yield => Result::Ok(Option::Some(V::Val(Unit))),
+            Op::Join(id) => {
+                if matches!(scheduler.threads[*id], Thread::Done(_)) {
+                    scheduler.joined.insert(*id);
+                }
+                match &scheduler.threads[*id] {
+                    Thread::Done(Result::Ok(value)) => Result::Ok(Option::Some(V::Ptr(*value))),
+                    // The error that ended the thread is thrown again by join
+                    Thread::Done(Result::Err((msg, value))) => Result::Err(Error{msg: msg.clone(), v:V::Ptr(*value)}),
+                    _ => Result::Ok(Option::None)
+                }
+            },
+            Op::Recv(id) => {
+                Result::Ok(scheduler.channels[*id].buffer.pop_front().map(|(_, value)| V::Ptr(value)))
+            },
+            Op::Send(id, value) => {
+                let id: usize = *id;
+                let channel: &mut Channel = &mut scheduler.channels[id];
+                if channel.capacity > 0 && channel.buffer.len() >= channel.capacity {
+                    return Result::Ok(Option::None)
+                }
+                channel.buffer.push_back((channel.sent, *value));
+                channel.sent += 1;
+                if channel.capacity > 0 {
+                    Result::Ok(Option::Some(V::Val(Unit)))
+                } else {
+                    *op = Op::Delivered(id, channel.sent - 1);
+                    Result::Ok(Option::None)
+                }
+            },
+            Op::Delivered(id, number) => {
+                let delivered: bool = !scheduler.channels[*id].buffer.iter().any(|(n, _)| n == number);
+                Result::Ok(if delivered { Option::Some(V::Val(Unit)) } else { Option::None })
+            }
+        }
+    })
+}
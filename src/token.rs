@@ -1,6 +1,8 @@
 use std::fmt;
 
-use crate::expression::{Const, Exp};
+use num_bigint::BigInt;
+
+use crate::expression::{Const, Exp, Pattern, Var};
 
 #[derive(Clone)]
 pub enum Token {
@@ -19,11 +21,41 @@ pub enum Token {
     SquareBracketClosed,
     CurlyBracketOpen,
     CurlyBracketClosed,
+    // Map literal brackets. Eg: #{"a": 1}
+    MapOpen,
+    MapClosed,
+    Colon,
     Comma,
     Try,
     Catch,
     Callcc,
-    In
+    // Delimits the continuations captured by shift. Eg: reset { 1 + shift k { k(2) } }
+    Reset,
+    Shift,
+    In,
+    Struct,
+    // Field access. Eg: p.x
+    Dot,
+    Enum,
+    Match,
+    For,
+    Break,
+    Continue,
+    Return,
+    // Suspends the generator that is being executed, which produces the value. Eg: yield exp
+    Yield,
+    // Calls the handler of an effect. Eg: perform Ask(x)
+    Perform,
+    // Evaluates another file as a module. Eg: import "lib.epi" as lib
+    Import,
+    // Loop label, without the leading `'`. Eg: 'outer
+    Label(String),
+    // Separates the pattern of a match arm from its body. Eg: x => x + 1
+    FatArrow,
+    // Function declarations hoisted to the beginning of a block. Only used in the parser operator stack
+    FunctionDecl(Vec<Var>, Vec<Exp>),
+    // Destructuring let, after its pattern and `=` have been read. Only used in the parser operator stack
+    LetPattern(Pattern)
 }
 
 impl Token {
@@ -47,12 +79,32 @@ impl Token {
             Token::RoundBracketOpen => false,
             Token::CurlyBracketOpen => false,
             Token::CurlyBracketClosed => false,
+            Token::MapOpen => false,
+            Token::MapClosed => true,
+            Token::Colon => false,
             Token::SquareBracketOpen => false,
             Token::Comma => false,
             Token::Try => false,
             Token::Catch => false,
             Token::Callcc => false,
-            Token::In => false
+            Token::Reset => false,
+            Token::Shift => false,
+            Token::In => false,
+            Token::Struct => false,
+            Token::Dot => false,
+            Token::Enum => false,
+            Token::Match => false,
+            Token::For => false,
+            Token::Break => false,
+            Token::Continue => false,
+            Token::Return => false,
+            Token::Yield => false,
+            Token::Perform => false,
+            Token::Import => false,
+            Token::Label(_) => false,
+            Token::FatArrow => false,
+            Token::FunctionDecl(_, _) => false,
+            Token::LetPattern(_) => false
         }
     }
 }
@@ -70,6 +122,7 @@ impl PartialEq for Token {
             (Token::ListSelectionOpen, Token::ListSelectionOpen) => true,
             (Token::RoundBracketOpen, Token::RoundBracketOpen) => true,
             (Token::RoundBracketClosed, Token::RoundBracketClosed) => true,
+            (Token::SquareBracketClosed, Token::SquareBracketClosed) => true,
             (Token::Let, Token::Let) => true,
             (Token::Fn, Token::Fn) => true,
             (Token::Operand(Operand::Null), Token::Operand(Operand::Null)) => true,
@@ -78,6 +131,11 @@ impl PartialEq for Token {
             (Token::Catch, Token::Catch) => true,
             (Token::Callcc, Token::Callcc) => true,
             (Token::In, Token::In) => true,
+            (Token::Struct, Token::Struct) => true,
+            (Token::Enum, Token::Enum) => true,
+            (Token::FatArrow, Token::FatArrow) => true,
+            (Token::Comma, Token::Comma) => true,
+            (Token::Colon, Token::Colon) => true,
             _ => false
         }
     }
@@ -101,11 +159,31 @@ impl fmt::Display for Token {
             Token::SquareBracketClosed => write!(f, "]"),
             Token::CurlyBracketOpen => write!(f, "{{"),
             Token::CurlyBracketClosed => write!(f, "}}"),
+            Token::MapOpen => write!(f, "#{{"),
+            Token::MapClosed => write!(f, "}}"),
+            Token::Colon => write!(f, ":"),
             Token::Comma => write!(f, ","),
             Token::Try => write!(f, "try"),
             Token::Catch => write!(f, "catch"),
             Token::Callcc => write!(f, "callcc"),
-            Token::In => write!(f,"in")
+            Token::Reset => write!(f, "reset"),
+            Token::Shift => write!(f, "shift"),
+            Token::In => write!(f,"in"),
+            Token::Struct => write!(f, "struct"),
+            Token::Dot => write!(f, "."),
+            Token::Enum => write!(f, "enum"),
+            Token::Match => write!(f, "match"),
+            Token::For => write!(f, "for"),
+            Token::Break => write!(f, "break"),
+            Token::Continue => write!(f, "continue"),
+            Token::Return => write!(f, "return"),
+            Token::Yield => write!(f, "yield"),
+            Token::Perform => write!(f, "perform"),
+            Token::Import => write!(f, "import"),
+            Token::Label(name) => write!(f, "'{}", name),
+            Token::FatArrow => write!(f, "=>"),
+            Token::FunctionDecl(_, _) => write!(f, "fn"),
+            Token::LetPattern(_) => write!(f, "let")
         }
     }
 }
@@ -113,7 +191,9 @@ impl fmt::Display for Token {
 #[derive(Clone)]
 pub enum Operand {
     Null,
-    Int(i32),
+    Int(i64),
+    BigInt(BigInt),
+    Float(f64),
     Bool(bool),
     Str(String),
     Var(String)
@@ -124,6 +204,8 @@ impl Operand {
         match self {
             Operand::Null => Exp::Const(Const::None),
             Operand::Int(i) => Exp::Const(Const::Integer(*i)),
+            Operand::BigInt(i) => Exp::Const(Const::BigInt(i.clone())),
+            Operand::Float(x) => Exp::Const(Const::Float(*x)),
             Operand::Bool(b) => Exp::Const(Const::Boolean(*b)),
             Operand::Str(s) => Exp::Const(Const::String(s.clone())),
             Operand::Var(_) => panic!("Never call to_exp() on variables because we need to know the scope to parse them correctly"),
@@ -136,6 +218,8 @@ impl fmt::Display for Operand {
         match self {
             Operand::Null => write!(f, "null"),
             Operand::Int(i) => write!(f, "{}", *i),
+            Operand::BigInt(i) => write!(f, "{}", i),
+            Operand::Float(x) => write!(f, "{:?}", *x),
             Operand::Bool(b) => write!(f, "{}", *b),
             Operand::Str(str) => write!(f, "\"{}\"", str),
             Operand::Var(name) => write!(f, "{}", name)
@@ -159,8 +243,12 @@ pub enum Operator {
     Sum,
     Sub,
     Mul,
+    Pow,
     Div,
     Mod,
+    In,
+    Range,
+    RangeInclusive,
     Throw,
     Throwcc
 }
@@ -170,16 +258,20 @@ impl Operator {
         match self {
             Operator::Throw => 0,
             Operator::Throwcc => 0,
+            Operator::Pow => 0,
             Operator::Mul => 1,
             Operator::Div => 1,
             Operator::Mod => 1,
             Operator::Sum => 2,
             Operator::Sub => 2,
+            Operator::Range => 3,
+            Operator::RangeInclusive => 3,
             Operator::Not => 4,
             Operator::Lt => 4,
             Operator::Lte => 4,
             Operator::Gt => 4,
             Operator::Gte => 4,
+            Operator::In => 4,
             Operator::Eq => 4,
             Operator::Neq => 4,
             Operator::And => 5,
@@ -188,29 +280,45 @@ impl Operator {
             Operator::Seq => 8
         }
     }
+
+    /**
+     * Right associative operators are grouped from the right: `2 ** 3 ** 2` is `2 ** (3 ** 2)`.
+     * Prefix operators are right associative too, so they apply to the whole operand that follows them
+     */
+    pub fn is_right_associative(&self) -> bool {
+        match self {
+            Operator::Pow | Operator::Assign | Operator::Seq => true,
+            Operator::Not | Operator::Throw | Operator::Throwcc => true,
+            _ => false
+        }
+    }
 }
 
 impl fmt::Display for Operator {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Operator::Mul => write!(f, "{}","*"),
-            Operator::Div => write!(f, "{}","/"),
-            Operator::Mod => write!(f, "{}","%"),
-            Operator::Sum => write!(f, "{}","+"),
-            Operator::Sub => write!(f, "{}","-"),
-            Operator::Not => write!(f, "{}","!"),
-            Operator::Lt => write!(f, "{}","<"),
-            Operator::Lte => write!(f, "{}","<="),
-            Operator::Gt => write!(f, "{}",">"),
-            Operator::Gte => write!(f, "{}",">="),
-            Operator::Eq => write!(f, "{}","=="),
-            Operator::Neq => write!(f, "{}","!="),
-            Operator::And => write!(f, "{}","&&"),
-            Operator::Or => write!(f, "{}","||"),
-            Operator::Assign => write!(f, "{}","="),
-            Operator::Seq => write!(f, "{}",";"),
-            Operator::Throw => write!(f,"{}","throw"),
-            Operator::Throwcc => write!(f,"{}","throw")
+            Operator::Mul => write!(f, "*"),
+            Operator::Pow => write!(f, "**"),
+            Operator::Div => write!(f, "/"),
+            Operator::Mod => write!(f, "%"),
+            Operator::Sum => write!(f, "+"),
+            Operator::Sub => write!(f, "-"),
+            Operator::Not => write!(f, "!"),
+            Operator::Lt => write!(f, "<"),
+            Operator::Lte => write!(f, "<="),
+            Operator::Gt => write!(f, ">"),
+            Operator::Gte => write!(f, ">="),
+            Operator::Eq => write!(f, "=="),
+            Operator::Neq => write!(f, "!="),
+            Operator::In => write!(f, "in"),
+            Operator::Range => write!(f, ".."),
+            Operator::RangeInclusive => write!(f, "..="),
+            Operator::And => write!(f, "&&"),
+            Operator::Or => write!(f, "||"),
+            Operator::Assign => write!(f, "="),
+            Operator::Seq => write!(f, ";"),
+            Operator::Throw => write!(f, "throw"),
+            Operator::Throwcc => write!(f, "throw")
         }
     }
 }
@@ -1,14 +1,76 @@
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
 use std::ptr;
 
+use indexmap::IndexMap;
+use num_bigint::BigInt;
+use num_traits::Zero;
+
 use crate::expression::Exp;
 use crate::expression::Const;
+use crate::semantics::{Error, Frame};
 
 #[derive(Debug)]
 pub struct Function {
     pub num_args: usize,
-    pub external_values: Vec<Value>,
-    pub body: Box<Exp>
+    // Variables captured from the enclosing scopes when the function was created, shared with those scopes
+    pub external_values: Vec<Slot>,
+    // Functions refer to their body in the program, which is never freed
+    pub body: &'static Exp
+}
+
+// Function implemented in Rust. Eg: print
+#[derive(Copy, Clone, Debug)]
+pub struct NativeFunction {
+    pub name: &'static str,
+    // None if the function accepts any number of arguments
+    pub num_args: Option<usize>,
+    pub function: fn(&[StackValue]) -> Result<V, Error>
+}
+
+/**
+ * The rest of the evaluation at the point where `callcc` was evaluated. Invoking it replaces
+ * the frames and the stack of the machine that captured it with these ones.
+ * Continuations captured by `shift` only hold the frames up to the enclosing `reset`: invoking them
+ * pushes the frames on top of the current ones, and returns the value of the reset like a function call
+ */
+#[derive(Clone, Debug)]
+pub struct Continuation {
+    // Id of the machine that captured the continuation
+    pub machine: usize,
+    pub frames: Vec<Frame>,
+    pub stack: Vec<Slot>,
+    pub delimited: bool
+}
+
+/**
+ * State of the evaluation of the body of a generator function
+ */
+#[derive(Debug)]
+pub enum Generator {
+    // The body has not started yet. It is evaluated on the arguments of the call and the captured values of the function
+    Start(&'static Exp, Vec<Slot>, StackValue),
+    // Suspended by a yield
+    Suspended(Continuation),
+    Running,
+    Done
+}
+
+#[derive(Debug)]
+pub struct Struct {
+    // Name of the struct type
+    pub name: String,
+    // Fields in declaration order
+    pub fields: IndexMap<String, StackValue>
+}
+
+#[derive(Debug)]
+pub struct Variant {
+    // Name of the enum type
+    pub name: String,
+    pub variant: String,
+    pub values: Vec<StackValue>
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -36,6 +98,41 @@ impl StackValue {
     pub fn as_mut_ref(&mut self) -> &mut Value {
         unsafe{ &mut *self.value }
     }
+
+    /**
+     * Compares the pointed values. The null pointer is equal to `Value::Unit`
+     */
+    pub fn value_eq(&self, other: &StackValue) -> bool {
+        match (self.is_unit(), other.is_unit()) {
+            (true, true) => true,
+            (true, false) => *other.as_ref() == Value::Unit,
+            (false, true) => *self.as_ref() == Value::Unit,
+            (false, false) => self.as_ref() == other.as_ref()
+        }
+    }
+}
+
+/**
+ * Variable on the stack. The stack of a continuation is a copy of the stack of the machine,
+ * so they share the variables: assignments done after the continuation was captured are visible after resuming it
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct Slot {
+    cell: *mut StackValue
+}
+
+impl Slot {
+    pub fn new(value: StackValue) -> Slot {
+        Slot { cell: Box::into_raw(Box::new(value)) }
+    }
+
+    pub fn get(&self) -> StackValue {
+        unsafe{ *self.cell }
+    }
+
+    pub fn set(&self, value: StackValue) {
+        unsafe{ *self.cell = value }
+    }
 }
 
 impl fmt::Display for StackValue {
@@ -47,42 +144,126 @@ impl fmt::Display for StackValue {
 #[derive(Debug)]
 pub enum Value {
     Unit,
-    Int(isize),
+    Int(i64),
+    // Integers that do not fit in 64 bits. Values that fit in 64 bits are always stored as `Int`
+    BigInt(BigInt),
+    Float(f64),
     Bool(bool),
     Fn(Function),
+    NativeFn(NativeFunction),
     List(Vec<StackValue>),
-    Str(String)
+    // Immutable sequence of values. Eg: (1, "a")
+    Tuple(Vec<StackValue>),
+    Str(String),
+    // Entries are kept in insertion order. Keys are always immutable values, see `Value::clone_key`
+    Map(IndexMap<Value, StackValue>),
+    Struct(Struct),
+    Enum(Variant),
+    // Integers from the first, included, to the second, excluded. Eg: 0..3
+    Range(i64, i64),
+    Continuation(Continuation),
+    Generator(Generator),
+    // Ids of a green thread and of a channel, owned by the scheduler
+    Thread(usize),
+    Channel(usize)
 }
 
 impl Value {
     pub fn from_const(c: &Const) -> Value {
         match c {
             Const::None => Value::Unit,
-            Const::Integer(i) => Value::Int(isize::try_from(*i).ok().unwrap()),
+            Const::Integer(i) => Value::Int(*i),
+            Const::BigInt(i) => Value::from_big_int(i.clone()),
+            Const::Float(x) => Value::Float(*x),
             Const::Boolean(b) => Value::Bool(*b),
             Const::String(s) => Value::Str(s.clone())
         }
     }
 
+    /**
+     * Builds an integer value, using a big integer only if the number does not fit in 64 bits
+     */
+    pub fn from_big_int(i: BigInt) -> Value {
+        match i64::try_from(&i) {
+            Result::Ok(i) => Value::Int(i),
+            Result::Err(_) => Value::BigInt(i)
+        }
+    }
+
+    /**
+     * Copies a value so that it can be stored as a map key.
+     * Only immutable values can be used as keys, for the others `None` is returned
+     */
+    pub fn clone_key(&self) -> Option<Value> {
+        match self {
+            Value::Unit => Option::Some(Value::Unit),
+            Value::Int(i) => Option::Some(Value::Int(*i)),
+            Value::BigInt(i) => Option::Some(Value::BigInt(i.clone())),
+            Value::Float(x) => Option::Some(Value::Float(*x)),
+            Value::Bool(b) => Option::Some(Value::Bool(*b)),
+            Value::Str(s) => Option::Some(Value::Str(s.clone())),
+            // Tuples can be keys if all their elements can
+            Value::Tuple(values) => {
+                let mut keys: Vec<StackValue> = Vec::with_capacity(values.len());
+                for value in values {
+                    keys.push(if value.is_unit() { StackValue::unit() } else {
+                        StackValue::from_box(Box::new(value.as_ref().clone_key()?))
+                    })
+                }
+                Option::Some(Value::Tuple(keys))
+            },
+            Value::Range(start, end) => Option::Some(Value::Range(*start, *end)),
+            Value::Thread(id) => Option::Some(Value::Thread(*id)),
+            Value::Channel(id) => Option::Some(Value::Channel(*id)),
+            Value::Fn(_) | Value::NativeFn(_) | Value::List(_) | Value::Map(_) | Value::Struct(_) | Value::Enum(_)
+                | Value::Continuation(_) | Value::Generator(_) => Option::None
+        }
+    }
+
     pub fn as_bool(&self) -> bool {
         match self {
             Value::Unit => false,
             Value::Bool(b) => *b,
             Value::Int(i) => *i != 0,
-            Value::Str(s) => s == "",
+            Value::BigInt(i) => !i.is_zero(),
+            Value::Float(x) => *x != 0.0,
+            Value::Str(s) => s.is_empty(),
             Value::Fn(_) => true,
-            Value::List(_) => true
+            Value::NativeFn(_) => true,
+            Value::List(_) => true,
+            Value::Tuple(_) => true,
+            Value::Map(_) => true,
+            Value::Struct(_) => true,
+            Value::Enum(_) => true,
+            Value::Range(_, _) => true,
+            Value::Continuation(_) => true,
+            Value::Generator(_) => true,
+            Value::Thread(_) => true,
+            Value::Channel(_) => true
         }
     }
 
+    #[allow(dead_code)]
     pub fn as_string(&self) -> String {
         match self {
             Value::Unit => String::from("Unit"),
             Value::Bool(b) => b.to_string(),
             Value::Int(i) => i.to_string(),
+            Value::BigInt(i) => i.to_string(),
+            Value::Float(x) => x.to_string(),
             Value::Str(s) => s.to_string(),
             Value::Fn(_) => String::from("Function"),
-            Value::List(_) => String::from("List")
+            Value::NativeFn(_) => String::from("Function"),
+            Value::List(_) => String::from("List"),
+            Value::Tuple(_) => String::from("Tuple"),
+            Value::Map(_) => String::from("Map"),
+            Value::Struct(s) => s.name.clone(),
+            Value::Enum(e) => e.name.clone(),
+            Value::Range(_, _) => String::from("Range"),
+            Value::Continuation(_) => String::from("Continuation"),
+            Value::Generator(_) => String::from("Generator"),
+            Value::Thread(_) => String::from("Thread"),
+            Value::Channel(_) => String::from("Channel")
         }
     }
 }
@@ -92,32 +273,119 @@ impl PartialEq for Value {
         match (self, other) {
             (Value::Unit, Value::Unit) => true,
             (Value::Int(i1), Value::Int(i2)) => *i1 == *i2,
+            (Value::BigInt(i1), Value::BigInt(i2)) => i1 == i2,
+            (Value::Float(f1), Value::Float(f2)) => *f1 == *f2,
             (Value::Bool(b1), Value::Bool(b2)) => *b1 == *b2,
             (Value::Str(s1), Value::Str(s2)) => s1 == s2,
             (Value::Fn(f1), Value::Fn(f2)) => std::ptr::eq(f1, f2),
+            (Value::NativeFn(f1), Value::NativeFn(f2)) => f1.name == f2.name,
+            (Value::List(l1), Value::List(l2)) | (Value::Tuple(l1), Value::Tuple(l2)) => l1.len() == l2.len()
+                && l1.iter().zip(l2).all(|(v1, v2)| v1.value_eq(v2)),
+            (Value::Map(m1), Value::Map(m2)) => m1.len() == m2.len()
+                && m1.iter().all(|(key, v1)| match m2.get(key) {
+                    Option::Some(v2) => v1.value_eq(v2),
+                    Option::None => false
+                }),
+            (Value::Struct(s1), Value::Struct(s2)) => s1.name == s2.name
+                && s1.fields.iter().zip(&s2.fields).all(|((_, v1), (_, v2))| v1.value_eq(v2)),
+            (Value::Enum(e1), Value::Enum(e2)) => e1.name == e2.name && e1.variant == e2.variant
+                && e1.values.iter().zip(&e2.values).all(|(v1, v2)| v1.value_eq(v2)),
+            (Value::Range(s1, e1), Value::Range(s2, e2)) => s1 == s2 && e1 == e2,
+            (Value::Thread(id1), Value::Thread(id2)) | (Value::Channel(id1), Value::Channel(id2)) => id1 == id2,
             _ => false
         }
     }
 }
 
+// Required by maps. Equality is not reflexive only for NaN, so a NaN key can never be found again
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Value::Int(i) => i.hash(state),
+            Value::BigInt(i) => i.hash(state),
+            // Adding 0.0 turns -0.0 into 0.0, because they are equal
+            Value::Float(x) => (*x + 0.0).to_bits().hash(state),
+            Value::Bool(b) => b.hash(state),
+            Value::Str(s) => s.hash(state),
+            Value::Tuple(values) => for value in values {
+                if value.is_unit() { Value::Unit.hash(state) } else { value.as_ref().hash(state) }
+            },
+            Value::Range(start, end) => {
+                start.hash(state);
+                end.hash(state)
+            },
+            Value::Thread(id) | Value::Channel(id) => id.hash(state),
+            // Not hashable: these values are never used as keys
+            Value::Unit | Value::Fn(_) | Value::NativeFn(_) | Value::List(_) | Value::Map(_) | Value::Struct(_) | Value::Enum(_)
+                | Value::Continuation(_) | Value::Generator(_) => ()
+        }
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::Unit => write!(f, "unit"),
             Value::Int(i) => write!(f, "{}", i),
+            Value::BigInt(i) => write!(f, "{}", i),
+            // Debug formatting always shows the decimal point, so that floats can be told apart from integers
+            Value::Float(x) => write!(f, "{:?}", x),
             Value::Bool(b) => write!(f, "{}", b),
             Value::Str(s) => write!(f, "{}", s),
             Value::Fn(func) => write!(f, "{:?}", func),
+            Value::NativeFn(func) => write!(f, "fn {}", func.name),
             Value::List(list) => {
                 write!(f, "[")?;
-                if list.len() > 0 {
-                    for i in 0..list.len() -1 {
-                        write!(f, "{}, ", list[i])?;
-                    };
-                    write!(f, "{}", list[list.len() - 1])?;
-                }
+                for (i, value) in list.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")? }
+                    if value.is_unit() { write!(f, "unit")? } else { write!(f, "{}", value)? }
+                };
                 write!(f, "]")
-            }
+            },
+            Value::Tuple(values) => {
+                write!(f, "(")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")? }
+                    if value.is_unit() { write!(f, "unit")? } else { write!(f, "{}", value)? }
+                };
+                // Like in the syntax, the comma tells a tuple with one element from a value in brackets
+                if values.len() == 1 { write!(f, ",")? }
+                write!(f, ")")
+            },
+            Value::Map(map) => {
+                write!(f, "#{{")?;
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")? }
+                    if value.is_unit() { write!(f, "{}: unit", key)? } else { write!(f, "{}: {}", key, value)? }
+                };
+                write!(f, "}}")
+            },
+            Value::Struct(s) => {
+                write!(f, "{} {{ ", s.name)?;
+                for (i, (field, value)) in s.fields.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")? }
+                    if value.is_unit() { write!(f, "{}: unit", field)? } else { write!(f, "{}: {}", field, value)? }
+                };
+                write!(f, " }}")
+            },
+            Value::Enum(e) => {
+                write!(f, "{}", e.variant)?;
+                if e.values.is_empty() { return Result::Ok(()) }
+                write!(f, "(")?;
+                for (i, value) in e.values.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")? }
+                    if value.is_unit() { write!(f, "unit")? } else { write!(f, "{}", value)? }
+                };
+                write!(f, ")")
+            },
+            Value::Range(start, end) => write!(f, "{}..{}", start, end),
+            Value::Continuation(_) => write!(f, "continuation"),
+            Value::Generator(_) => write!(f, "generator"),
+            Value::Thread(id) => write!(f, "thread {}", id),
+            Value::Channel(id) => write!(f, "channel {}", id)
         }
     }
 }
@@ -138,6 +406,7 @@ impl V {
         }
     }
 
+    #[allow(dead_code)]
     pub fn as_string(&self) -> String {
         match self {
             V::Ptr(ptr) => ptr.as_ref().as_string(),
@@ -158,6 +427,16 @@ impl V {
             V::Val(value) => value
         }
     }
+
+    /**
+     * Moves the value to the heap, if it is not there already
+     */
+    pub fn into_stack_value(self) -> StackValue {
+        match self {
+            V::Ptr(ptr) => ptr,
+            V::Val(value) => StackValue::from_box(Box::new(value))
+        }
+    }
 }
 
 impl PartialEq for V {